@@ -1,16 +1,14 @@
-use gpui::{App, AppContext, DragMoveEvent, Entity, ExternalPaths, ListAlignment, ListState, px};
+use gpui::{AppContext, ClipboardItem, DragMoveEvent, Entity, ExternalPaths, ListAlignment, ListState, Subscription, px};
 use gpui_component::{select::{SelectDelegate, SelectEvent, SelectItem, SelectState}, slider::{SliderEvent, SliderState}};
-use image::ImageReader;
-use mimetype_detector::{IMAGE_HEIC, match_file};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use smol::channel::{bounded, unbounded};
-use std::{collections::HashMap, path::PathBuf};
+use smol::channel::{Receiver, bounded, unbounded};
+use std::path::PathBuf;
+use unheic_core::{BatchItem, BatchTarget, CancellationToken, CollisionPolicy, ConversionError, ConversionOutput, Transform};
 use gpui::{ClickEvent, Context, Window};
 
 pub(super) fn handle_open_folder_button(_: &mut super::ui::Application, _: &ClickEvent, window: &mut Window, cx: &mut Context<super::ui::Application>) {
     let (sender, receiver) = bounded::<Option<PathBuf>>(1);
     cx.foreground_executor().spawn(async move {
-        sender.send(super::utils::open_single_directory().await).await.unwrap();
+        sender.send(super::utils::open_single_directory("Ausgabeordner auswählen").await).await.unwrap();
     }).detach();
     let window_handle = window.window_handle();
     cx.spawn(async move |weak, cx| {
@@ -34,60 +32,135 @@ pub(super) fn handle_open_folder_button(_: &mut super::ui::Application, _: &Clic
 
 pub(super) fn handle_metadata_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, _: &mut Context<super::ui::Application>) {
     match &mut this.state.conversion_settings.settings {
-        crate::state::ConversionSettings::JPEG(metadata, _, _) => *metadata = *checked,
-        crate::state::ConversionSettings::PNG(metadata, _, _) => *metadata = *checked,
-        crate::state::ConversionSettings::TIFF(metadata, _, _) => *metadata = *checked,
+        crate::state::ConversionSettings::JPEG(metadata, _, _, _, _, _) => *metadata = *checked,
+        crate::state::ConversionSettings::PNG(metadata, _, _, _, _, _, _) => *metadata = *checked,
+        crate::state::ConversionSettings::TIFF(metadata, _, _, _, _, _, _) => *metadata = *checked,
         crate::state::ConversionSettings::WebP(metadata, _, _) => *metadata = *checked,
+        crate::state::ConversionSettings::AVIF(metadata, _, _, _, _, _, _) => *metadata = *checked,
+        crate::state::ConversionSettings::JXL(metadata, _, _, _, _) => *metadata = *checked,
+        crate::state::ConversionSettings::HEIF(metadata, _, _, _) => *metadata = *checked,
     }
 }
 
-pub(super) fn handle_select_event<D: SelectDelegate>(for_target: &Entity<SelectState<D>>, window: &mut Window, cx: &mut Context<super::ui::Application>, setter: impl Fn(&mut App, &mut Window, &mut super::ui::Application, &<<D as SelectDelegate>::Item as SelectItem>::Value) + 'static) {
-    let window_handle = window.window_handle();
-    cx.subscribe(for_target, move |app, _, ev: &SelectEvent<D>, cx| {
-        cx.update_window(window_handle, |_, window, cx| {
-            match ev {
-                SelectEvent::Confirm(value_opt) => {
-                    if let Some(value) = value_opt {
-                        setter(cx, window, app, value);
-                    }
-                },
-            }
-        }).unwrap();
-    }).detach();
+pub(super) fn handle_keep_structure_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.output_folder_state.keep_structure = *checked;
+    cx.notify();
 }
 
-pub(super) fn handle_slider_event(for_target: &Entity<SliderState>, window: &mut Window, cx: &mut Context<super::ui::Application>, setter: impl Fn(&mut App, &mut Window, &mut super::ui::Application, f32) + 'static) {
-    let window_handle = window.window_handle();
-    cx.subscribe(for_target, move |app, _, ev: &SliderEvent, cx| {
-        cx.update_window(window_handle, |_, window, cx| {
-            match ev {
-                SliderEvent::Change(val) => setter(cx, window, app, val.end()),
-            }
-        }).unwrap();
+pub(super) fn handle_choose_structure_base_button(_: &mut super::ui::Application, _: &ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    let (sender, receiver) = bounded::<Option<PathBuf>>(1);
+    cx.foreground_executor().spawn(async move {
+        sender.send(super::utils::open_single_directory("Basisordner für die Ordnerstruktur auswählen").await).await.unwrap();
     }).detach();
+    cx.spawn(async move |weak, cx| {
+        if let Ok(Some(path)) = receiver.recv().await {
+            let _ = weak.update(cx, move |this, cx| {
+                this.state.output_folder_state.structure_base = Some(path);
+                cx.notify();
+            });
+        }
+    }).detach();
+}
+
+pub(super) fn handle_reset_structure_base_button(this: &mut super::ui::Application, _: &ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.output_folder_state.structure_base = None;
+    cx.notify();
+}
+
+pub(super) fn handle_heif_lossless_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    if let crate::state::ConversionSettings::HEIF(_, _, lossless, _) = &mut this.state.conversion_settings.settings {
+        *lossless = *checked;
+    }
+    cx.notify();
+}
+
+pub(super) fn handle_srgb_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, _: &mut Context<super::ui::Application>) {
+    this.state.conversion_settings.convert_to_srgb = *checked;
+}
+
+pub(super) fn handle_export_all_images_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, _: &mut Context<super::ui::Application>) {
+    this.state.conversion_settings.export_all_images = *checked;
+}
+
+pub(super) fn handle_export_depth_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.conversion_settings.export_depth = *checked;
+    cx.notify();
+}
+
+pub(super) fn handle_export_auxiliary_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.conversion_settings.export_auxiliary = *checked;
+    cx.notify();
+}
+
+pub(super) fn handle_flip_horizontal_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.conversion_settings.transform.flip_horizontal = *checked;
+    cx.notify();
+}
+
+pub(super) fn handle_flip_vertical_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.conversion_settings.transform.flip_vertical = *checked;
+    cx.notify();
+}
+
+pub(super) fn open_transform_editor(this: &mut super::ui::Application, path: PathBuf, cx: &mut Context<super::ui::Application>) {
+    this.state.transform_editor = Some(path);
+    cx.notify();
+}
+
+pub(super) fn handle_close_transform_editor_button(this: &mut super::ui::Application, _: &ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.transform_editor = None;
+    cx.notify();
+}
+
+/// Changes the transform of the edited image, starting from the batch transform if it had none of its own
+pub(super) fn edit_transform_override(this: &mut super::ui::Application, edit: impl FnOnce(&mut Transform), cx: &mut Context<super::ui::Application>) {
+    let batch_transform = this.state.conversion_settings.transform;
+    let Some(path) = &this.state.transform_editor else { return; };
+    if let Some(image) = this.state.input_image_state.images.get_mut(path) {
+        edit(image.transform_override.get_or_insert(batch_transform));
+        cx.notify();
+    }
+}
+
+pub(super) fn handle_reset_transform_override_button(this: &mut super::ui::Application, _: &ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    let Some(path) = &this.state.transform_editor else { return; };
+    if let Some(image) = this.state.input_image_state.images.get_mut(path) {
+        image.transform_override = None;
+        cx.notify();
+    }
+}
+
+/// Calls `setter` for every confirmed selection. The subscription has to be kept as long as the select
+/// exists, subscribing while rendering would add another handler on every frame.
+pub(super) fn handle_select_event<D: SelectDelegate>(for_target: &Entity<SelectState<D>>, window: &Window, cx: &mut Context<super::ui::Application>, setter: impl Fn(&mut Context<super::ui::Application>, &mut Window, &mut super::ui::Application, &<<D as SelectDelegate>::Item as SelectItem>::Value) + 'static) -> Subscription {
+    cx.subscribe_in(for_target, window, move |app, _, ev: &SelectEvent<D>, window, cx| {
+        match ev {
+            SelectEvent::Confirm(value_opt) => {
+                if let Some(value) = value_opt {
+                    setter(cx, window, app, value);
+                    cx.notify();
+                }
+            },
+        }
+    })
+}
+
+/// Calls `setter` for every change of the slider, the subscription has to be kept like for `handle_select_event`
+pub(super) fn handle_slider_event(for_target: &Entity<SliderState>, window: &Window, cx: &mut Context<super::ui::Application>, setter: impl Fn(&mut Context<super::ui::Application>, &mut Window, &mut super::ui::Application, f32) + 'static) -> Subscription {
+    cx.subscribe_in(for_target, window, move |app, _, ev: &SliderEvent, window, cx| {
+        match ev {
+            SliderEvent::Change(val) => {
+                setter(cx, window, app, val.end());
+                cx.notify();
+            },
+        }
+    })
 }
 
 pub(super) fn handle_file_drop(this: &mut super::ui::Application, external_paths: &ExternalPaths, _: &mut Window, cx: &mut Context<super::ui::Application>) {
     if external_paths.paths().is_empty() { return; }
-    let input_map = &mut this.state.input_image_state.images;
-    for external_path in external_paths.paths() {
-        if input_map.contains_key(external_path) { continue; }
-        if !match_file(external_path, IMAGE_HEIC).unwrap_or(false) { continue; }
-        input_map.insert(external_path.clone(), super::state::InputImage {
-            state: super::state::ImageLoadingState::NotStarted,
-            path: external_path.clone(),
-            name: external_path.file_prefix().map(|filename| { filename.display().to_string() }).unwrap_or("Kein Dateiname".into()),
-        });
-        let path_async = external_path.clone();
-        cx.spawn(async move |we, async_cx| {
-            // Request generating the thumbnail image
-            super::utils::request_thumbnail_generation(path_async, we, async_cx).await;
-        }).detach();
-    }
-    this.state.input_image_state.total_count = input_map.len() as u16;
-    this.state.input_image_state.ui_liststate = ListState::new(input_map.len(), ListAlignment::Top, px(16.));
+    import_paths(this, external_paths.paths().to_vec(), cx);
     cx.stop_propagation();
-    cx.notify();
 }
 
 pub(super) fn handle_file_drag(_: &mut super::ui::Application, event: &DragMoveEvent<ExternalPaths>, window: &mut Window, cx: &mut Context<super::ui::Application>) {
@@ -104,6 +177,23 @@ pub(super) fn handle_add_image_button(_: &mut super::ui::Application, _:&ClickEv
     cx.foreground_executor().spawn(async move {
         sender.send(super::utils::open_multiple_files().await).await.unwrap();
     }).detach();
+    receive_picked_paths(receiver, window, cx);
+}
+
+pub(super) fn handle_add_folder_button(_: &mut super::ui::Application, _:&ClickEvent, window: &mut Window, cx: &mut Context<super::ui::Application>) {
+    let (sender, receiver) = bounded::<Option<Vec<PathBuf>>>(1);
+    cx.foreground_executor().spawn(async move {
+        sender.send(super::utils::open_multiple_directories().await).await.unwrap();
+    }).detach();
+    receive_picked_paths(receiver, window, cx);
+}
+
+pub(super) fn handle_include_hidden_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.import_state.include_hidden = *checked;
+    cx.notify();
+}
+
+fn receive_picked_paths(receiver: Receiver<Option<Vec<PathBuf>>>, window: &mut Window, cx: &mut Context<super::ui::Application>) {
     let window_handle = window.window_handle();
     cx.spawn(async move |weak, cx| {
         if let Ok(paths_maybe) = receiver.recv().await {
@@ -113,24 +203,7 @@ pub(super) fn handle_add_image_button(_: &mut super::ui::Application, _:&ClickEv
                         .upgrade()
                         .unwrap()
                         .update(cx, move |this, cx| {
-                            let input_map = &mut this.state.input_image_state.images;
-                            for external_path in paths {
-                                if input_map.contains_key(&external_path) { continue; }
-                                if !match_file(&external_path, IMAGE_HEIC).unwrap_or(false) { continue; }
-                                input_map.insert(external_path.clone(), super::state::InputImage {
-                                    state: super::state::ImageLoadingState::NotStarted,
-                                    path: external_path.clone(),
-                                    name: external_path.file_prefix().map(|filename| { filename.display().to_string() }).unwrap_or("Kein Dateiname".into()),
-                                });
-                                let path_async = external_path.clone();
-                                cx.spawn(async move |we, async_cx| {
-                                    // Request generating the thumbnail image
-                                    super::utils::request_thumbnail_generation(path_async, we, async_cx).await;
-                                }).detach();
-                            }
-                            this.state.input_image_state.total_count = input_map.len() as u16;
-                            this.state.input_image_state.ui_liststate = ListState::new(input_map.len(), ListAlignment::Top, px(16.));
-                            cx.notify();
+                            import_paths(this, paths, cx);
                         });
                 }).unwrap();
             }
@@ -138,93 +211,266 @@ pub(super) fn handle_add_image_button(_: &mut super::ui::Application, _:&ClickEv
     }).detach();
 }
 
+/// Adds HEIF files right away and searches folders in the background
+fn import_paths(this: &mut super::ui::Application, paths: Vec<PathBuf>, cx: &mut Context<super::ui::Application>) {
+    let (directories, files): (Vec<_>, Vec<_>) = paths.into_iter().partition(|path| { path.is_dir() });
+    add_input_images(this, files.into_iter().filter_map(|path| {
+        // A Live Photo video stands for its still, so the pair ends up in a single list entry
+        let path = unheic_core::find_live_photo_still(&path).unwrap_or(path);
+        unheic_core::detect_heif_brand(&path).map(|brand| { (path, brand) })
+    }).collect(), cx);
+    if directories.is_empty() { return; }
+    
+    let max_depth = this.state.import_state.depth.max_depth();
+    let include_hidden = this.state.import_state.include_hidden;
+    this.state.import_state.running_scans += 1;
+    cx.notify();
+    
+    cx.spawn(async move |weak, async_app| {
+        let (sender, receiver) = unbounded::<super::utils::ScanMessage>();
+        async_app.background_spawn(async move {
+            super::utils::scan_directories(directories, max_depth, include_hidden, sender);
+        }).detach();
+        
+        while let Ok(message) = receiver.recv().await {
+            let update = weak.update(async_app, |this, cx| {
+                match message {
+                    super::utils::ScanMessage::Progress(count) => this.state.import_state.scanned_files += count,
+                    super::utils::ScanMessage::Found(paths) => add_input_images(this, paths, cx),
+                }
+                cx.notify();
+            });
+            // Dropping the receiver stops the scan once the window is gone
+            if update.is_err() { return; }
+        }
+        
+        let _ = weak.update(async_app, |this, cx| {
+            this.state.import_state.running_scans -= 1;
+            if this.state.import_state.running_scans == 0 { this.state.import_state.scanned_files = 0; }
+            cx.notify();
+        });
+    }).detach();
+}
+
+fn add_input_images(this: &mut super::ui::Application, paths: Vec<(PathBuf, String)>, cx: &mut Context<super::ui::Application>) {
+    if paths.is_empty() { return; }
+    let input_state = &mut this.state.input_image_state;
+    for (external_path, brand) in paths {
+        if input_state.images.contains_key(&external_path) { continue; }
+        input_state.added_count += 1;
+        input_state.images.insert(external_path.clone(), super::state::InputImage {
+            state: super::state::ImageLoadingState::NotStarted,
+            path: external_path.clone(),
+            name: external_path.file_prefix().map(|filename| { filename.display().to_string() }).unwrap_or("Kein Dateiname".into()),
+            index: input_state.added_count,
+            brand,
+            image_count: 1,
+            live_photo_video: None,
+            transform_override: None,
+            conversion_result: Default::default(),
+        });
+        let path_async = external_path.clone();
+        cx.spawn(async move |we, async_cx| {
+            // Request generating the thumbnail image
+            super::utils::request_thumbnail_generation(path_async, we, async_cx).await;
+        }).detach();
+    }
+    input_state.total_count = input_state.images.len() as u16;
+    input_state.ui_liststate = ListState::new(input_state.images.len(), ListAlignment::Top, px(16.));
+    cx.notify();
+}
+
 enum SingleConversionResult {
-    Done,
-    Error(PathBuf, String),
+    /// Source and every image written for it
+    Done(PathBuf, Vec<PathBuf>),
+    /// Source and the first existing output, if nothing was written
+    Skipped(PathBuf, PathBuf),
+    Error(ConversionError),
+    /// Not a result, a worker asks what to do with an existing output file
+    Collision(PathBuf, smol::channel::Sender<CollisionPolicy>),
+}
+
+pub(super) fn handle_conversion_start_button(this: &mut super::ui::Application, _:&ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    // Images which already failed to decode for the thumbnail are left out until they are retried
+    let input_image_paths = this.state.input_image_state.images.values()
+        .filter(|image| { !matches!(image.state, super::state::ImageLoadingState::Failure(_)) })
+        .map(|image| { image.path.clone() })
+        .collect::<Vec<_>>();
+    start_conversion(this, input_image_paths, cx);
+}
+
+pub(super) fn handle_pause_conversion_button(this: &mut super::ui::Application, _:&ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    let token = &this.state.cancellation_token;
+    if token.is_paused() { token.resume(); } else { token.pause(); }
+    cx.notify();
 }
 
-pub(super) fn handle_conversion_start_button<'a>(this: &mut super::ui::Application, _:&ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+pub(super) fn handle_cancel_conversion_button(this: &mut super::ui::Application, _:&ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.cancellation_token.cancel();
+    // Dropping the senders lets waiting workers skip their file
+    this.state.collision_prompt.pending.clear();
+    cx.notify();
+}
+
+pub(super) fn handle_collision_apply_to_all_checkbox_change(this: &mut super::ui::Application, checked: &bool, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.collision_prompt.apply_to_all = *checked;
+    cx.notify();
+}
+
+pub(super) fn answer_collision_prompt(this: &mut super::ui::Application, policy: CollisionPolicy, cx: &mut Context<super::ui::Application>) {
+    let prompt = &mut this.state.collision_prompt;
+    if prompt.apply_to_all {
+        if let Ok(mut remembered) = prompt.remembered.lock() { *remembered = Some(policy); }
+        for (_, sender) in prompt.pending.drain(..) {
+            let _ = sender.try_send(policy);
+        }
+    } else if let Some((_, sender)) = prompt.pending.pop_front() {
+        let _ = sender.try_send(policy);
+    }
+    cx.notify();
+}
+
+pub(super) fn handle_show_error_report_button(this: &mut super::ui::Application, _:&ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.show_error_report = true;
+    cx.notify();
+}
+
+pub(super) fn handle_close_error_report_button(this: &mut super::ui::Application, _:&ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    this.state.show_error_report = false;
+    cx.notify();
+}
+
+pub(super) fn handle_copy_error_report_button(this: &mut super::ui::Application, _:&ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    if !this.state.conversion_errors.is_empty() {
+        cx.write_to_clipboard(ClipboardItem::new_string(super::utils::error_report(&this.state.conversion_errors)));
+    }
+}
+
+pub(super) fn handle_retry_failed_button(this: &mut super::ui::Application, _:&ClickEvent, _: &mut Window, cx: &mut Context<super::ui::Application>) {
+    // Skip images the user removed from the list since the last run
+    let failed_paths = this.state.conversion_errors.keys().filter(|path| { this.state.input_image_state.images.contains_key(*path) }).cloned().collect::<Vec<_>>();
+    if failed_paths.is_empty() { return; }
+    start_conversion(this, failed_paths, cx);
+}
+
+fn start_conversion(this: &mut super::ui::Application, input_image_paths: Vec<PathBuf>, cx: &mut Context<super::ui::Application>) {
     
-    let total_images = this.state.input_image_state.total_count;
+    let total_images = input_image_paths.len() as u16;
     this.state.conversion_progress = super::state::ConversionProgress::InProgress(0, total_images, 0.);
-    let input_image_paths = this.state.input_image_state.images.keys().cloned::<PathBuf>().collect::<Vec<_>>();
-    let output_dir = this.state.output_folder_state.value.clone();
-    let conversion_settings = this.state.conversion_settings.settings.clone();
+    this.state.conversion_errors.clear();
+    this.state.show_error_report = false;
+    // Each image keeps its index in the input list and its own transform, if it has one
+    let mut items = Vec::with_capacity(input_image_paths.len());
+    for path in input_image_paths {
+        let Some(image) = this.state.input_image_state.images.get_mut(&path) else { continue; };
+        image.conversion_result = super::state::ConversionResult::Pending;
+        items.push(BatchItem { path, index: image.index, transform: image.transform_override });
+    }
+    let target = BatchTarget {
+        output_dir: this.state.output_folder_state.value.clone(),
+        // Taken from the whole list instead of this run, so retrying a few files keeps the same layout
+        structure_base: match this.state.output_folder_state.keep_structure {
+            true => this.state.output_folder_state.structure_base.clone().or_else(|| { unheic_core::common_root(this.state.input_image_state.images.keys().map(|path| { path.as_path() })) }),
+            false => None,
+        },
+    };
+    let conversion_options = this.state.conversion_settings.conversion_options(&this.state.output_folder_state, cx);
+    let cancellation_token = CancellationToken::default();
+    this.state.cancellation_token = cancellation_token.clone();
+    this.state.conversion_running = true;
+    this.state.collision_prompt = Default::default();
+    let remembered_policy = this.state.collision_prompt.remembered.clone();
     
     // Convert each image in parallel
     cx.spawn(async move |weak, async_app| {
         
         let (sender, receiver) = unbounded::<SingleConversionResult>();
         
-        async_app.background_spawn(async move {
+        let batch = async_app.background_spawn(async move {
             
-            input_image_paths.into_par_iter().for_each_with((output_dir, conversion_settings), |(output_dir, conversion_settings), path| {
-                
-                let img = ImageReader::open(&path);
-                if let Err(err) = img { sender.send_blocking(SingleConversionResult::Error(path, err.to_string())).unwrap(); return; }
-                let img = img.unwrap().with_guessed_format();
-                if let Err(err) = img { sender.send_blocking(SingleConversionResult::Error(path, err.to_string())).unwrap(); return; }
-                let img = img.unwrap().decode();
-                if let Err(err) = img { sender.send_blocking(SingleConversionResult::Error(path, err.to_string())).unwrap(); return; }
-                let img = super::conversion::convert_to_format(img.unwrap().into_rgba8(), conversion_settings);
-                if let Err(err) = img { sender.send_blocking(SingleConversionResult::Error(path, err.to_string())).unwrap(); return; }
-                
-                // Write to file
-                let out_file_path = output_dir.join(path.file_prefix().unwrap()).with_added_extension(super::utils::file_extension_for_format(conversion_settings));
-                let write_result = std::fs::write(&out_file_path, img.unwrap().as_ref());
-                if let Err(err) = write_result { sender.send_blocking(SingleConversionResult::Error(path, err.to_string())).unwrap(); return; }
-                
-                sender.send_blocking(SingleConversionResult::Done).unwrap();
-            });
+            let ask = |out_path: &std::path::Path| {
+                if let Some(policy) = remembered_policy.lock().ok().and_then(|remembered| { *remembered }) {
+                    return policy;
+                }
+                let (answer_sender, answer_receiver) = bounded::<CollisionPolicy>(1);
+                if sender.send_blocking(SingleConversionResult::Collision(out_path.to_path_buf(), answer_sender)).is_err() {
+                    return CollisionPolicy::Skip;
+                }
+                // A dropped question, e.g. after cancelling, skips the file
+                answer_receiver.recv_blocking().unwrap_or(CollisionPolicy::Skip)
+            };
+            let report = |path: &std::path::Path, result: &Result<Vec<ConversionOutput>, ConversionError>| {
+                match result {
+                    Ok(outputs) => {
+                        let written = outputs.iter().filter_map(|output| { match output { ConversionOutput::Written(out_path) => Some(out_path.clone()), ConversionOutput::Skipped(_) => None } }).collect::<Vec<_>>();
+                        match outputs.first() {
+                            Some(ConversionOutput::Skipped(out_path)) if written.is_empty() => sender.send_blocking(SingleConversionResult::Skipped(path.to_path_buf(), out_path.clone())).unwrap(),
+                            _ => sender.send_blocking(SingleConversionResult::Done(path.to_path_buf(), written)).unwrap(),
+                        }
+                    },
+                    Err(err) => sender.send_blocking(SingleConversionResult::Error(err.clone())).unwrap(),
+                }
+            };
+            // Workers wait while paused, files not started before a cancellation are skipped
+            unheic_core::convert_batch(items, &target, &conversion_options, &cancellation_token, &ask, &report)
             
-        }).detach();
+        });
         
+        // The channel closes once all workers are done, also when cancelled early
         let mut idx = 1u16;
-        while idx != total_images + 1 {
-            let recv = receiver.recv().await.unwrap();
+        while let Ok(recv) = receiver.recv().await {
+            if let SingleConversionResult::Collision(out_path, answer_sender) = recv {
+                weak.update(async_app, |this, cx| {
+                    this.state.collision_prompt.pending.push_back((out_path, answer_sender));
+                    cx.notify();
+                }).unwrap();
+                continue;
+            }
             weak.update(async_app, |this, cx| {
                 match recv {
-                    SingleConversionResult::Done => {
-                        match &mut this.state.conversion_progress {
-                            super::state::ConversionProgress::InProgress(curr, total, percent) => {
-                                *curr = idx;
-                                *percent = (*curr as f32 / *total as f32) * 100.;
-                            },
-                            _ => {}
+                    SingleConversionResult::Done(path_buf, out_paths) => {
+                        if let Some(image) = this.state.input_image_state.images.get_mut(&path_buf) {
+                            image.conversion_result = super::state::ConversionResult::Converted(out_paths);
                         }
                     },
-                    SingleConversionResult::Error(path_buf, err) => {
-                        match &mut this.state.conversion_progress {
-                            super::state::ConversionProgress::Error(err_map) => {
-                                err_map.insert(path_buf, err);
-                            },
-                            _ => {
-                                this.state.conversion_progress = super::state::ConversionProgress::Error(HashMap::new());
-                                match &mut this.state.conversion_progress {
-                                    super::state::ConversionProgress::Error(err_map) => {
-                                        err_map.insert(path_buf, err);
-                                    },
-                                    _ => unreachable!("Logic Error: Got wrong ConversionProgress"),
-                                }
-                            }
+                    SingleConversionResult::Skipped(path_buf, out_path) => {
+                        if let Some(image) = this.state.input_image_state.images.get_mut(&path_buf) {
+                            image.conversion_result = super::state::ConversionResult::Skipped(out_path);
                         }
                     },
+                    SingleConversionResult::Collision(_, _) => unreachable!("Logic Error: Collisions are handled before"),
+                    SingleConversionResult::Error(err) => {
+                        if let Some(image) = this.state.input_image_state.images.get_mut(&err.path) {
+                            image.conversion_result = super::state::ConversionResult::Failed(err.kind.clone());
+                        }
+                        this.state.conversion_errors.insert(err.path, err.kind);
+                    },
+                }
+                // Failed files count as processed as well, so the progress keeps moving
+                if let super::state::ConversionProgress::InProgress(curr, total, percent) = &mut this.state.conversion_progress {
+                    *curr = idx;
+                    *percent = (*curr as f32 / *total as f32) * 100.;
                 }
                 cx.notify();
             }).unwrap();
             idx += 1;
         }
+        let summary = batch.await;
         
         weak.update(async_app, |this, cx| {
             
-            match &mut this.state.conversion_progress {
+            this.state.conversion_running = false;
+            this.state.collision_prompt.pending.clear();
+            // A cancellation wins over errors, the failed files stay available in the report either way
+            this.state.conversion_progress = match &this.state.conversion_progress {
                 super::state::ConversionProgress::Inactive => unreachable!("Logic Error: Cannot reach here when the conversion is inactive."),
-                super::state::ConversionProgress::InProgress(_, _, _) => {
-                    this.state.conversion_progress = super::state::ConversionProgress::Completed(total_images);
-                },
-                super::state::ConversionProgress::Error(_) => {},
+                _ if summary.cancelled => super::state::ConversionProgress::Cancelled(summary.converted as u16, total_images),
+                super::state::ConversionProgress::InProgress(_, _, _) if !this.state.conversion_errors.is_empty() => super::state::ConversionProgress::Error(this.state.conversion_errors.len() as u16, total_images),
+                super::state::ConversionProgress::InProgress(_, _, _) => super::state::ConversionProgress::Completed(total_images),
+                super::state::ConversionProgress::Error(_, _) => unreachable!("Logic Error: Error cannot be set before"),
                 super::state::ConversionProgress::Completed(_) => unreachable!("Logic Error: Completed cannot be set before"),
-            }
+                super::state::ConversionProgress::Cancelled(_, _) => unreachable!("Logic Error: Cancelled cannot be set before"),
+            };
             
             cx.notify();
             
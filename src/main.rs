@@ -1,4 +1,4 @@
-use std::error::Error;
+use std::{error::Error, process::ExitCode};
 
 use gpui::{AppContext, Application, Global, KeyBinding, TitlebarOptions, WindowBounds, WindowOptions, actions, point, px, size};
 use gpui_component::Root;
@@ -6,7 +6,6 @@ use gpui_component_assets::Assets;
 use libheif_rs::integration::image::register_heic_decoding_hook;
 
 mod actions;
-mod conversion;
 mod state;
 mod ui;
 mod utils;
@@ -19,11 +18,17 @@ impl Global for GlobalStub {}
 
 actions!(window, [Quit]);
 
-fn main() {
+fn main() -> ExitCode {
     
     // Register libHEIF image hooks
     register_heic_decoding_hook();
     
+    // Run headless if requested, this never touches the display
+    if std::env::args().nth(1).as_deref() == Some(unheic_core::cli::SUBCOMMAND) {
+        let program = format!("UnHEIC {}", unheic_core::cli::SUBCOMMAND);
+        return unheic_core::cli::run(std::iter::once(program).chain(std::env::args().skip(2)));
+    }
+    
     let app = Application::new()
         .with_assets(Assets);
     app.run(move |cx_sync| {
@@ -56,4 +61,6 @@ fn main() {
         cx_sync.on_action(|_: &Quit, cx| cx.quit());
         cx_sync.bind_keys([KeyBinding::new("cmd-q", Quit, None)]);
     });
+    
+    ExitCode::SUCCESS
 }
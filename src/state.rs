@@ -1,50 +1,188 @@
-use std::{collections::HashMap, path::PathBuf, sync::{Arc, RwLock}};
+use std::{collections::{HashMap, VecDeque}, path::PathBuf, sync::{Arc, Mutex, RwLock}};
 
-use gpui::{App, AppContext, Entity, ImageCacheError, ListAlignment, ListState, RenderImage, Window, px};
+use gpui::{App, AppContext, Context, Entity, ImageCacheError, ListAlignment, ListState, RenderImage, Subscription, Window, px};
 use gpui_component::{IndexPath, input::InputState, select::SelectState, slider::SliderState};
 use ordermap::OrderMap;
 use strum::{EnumDiscriminants, EnumIter, EnumMessage, IntoEnumIterator};
 
+use smol::channel::Sender;
+use unheic_core::{AlphaPolicy, AlphaPolicyKind, AuxiliaryFormat, BitDepth, CancellationToken, ChromaSubsampling, CollisionPolicy, ColorMode, ConversionErrorKind, ConversionOptions, CropAnchor, CropAspect, DEFAULT_NAME_TEMPLATE, LivePhotoVideoAction, OutputFormat, OutputFormatKind, Resize, ResizeFilter, ResizeKind, Rotation, TIFFCompression, Transform};
+
 #[derive(Default, PartialEq)]
 pub(crate) enum ConversionProgress {
     #[default]
     Inactive,
     InProgress(u16, u16, f32),
-    Error(HashMap<PathBuf, String>),
+    /// Failed and total number of images of a run which finished with errors
+    Error(u16, u16),
     Completed(u16),
-}
-
-#[derive(Clone, Copy, Default, EnumIter, EnumMessage)]
-pub(crate) enum TIFFCompression {
-    #[strum(message = "Keine Kompression")]
-    #[default]
-    None,
-    #[strum(message = "Lempel-Ziv-Welch (LZW)")]
-    LZW,
-    #[strum(message = "Deflate (LZ77 / Huffman)")]
-    Deflate,
+    /// Converted and total number of images of a run which was stopped early
+    Cancelled(u16, u16),
 }
 
 #[derive(Clone, EnumDiscriminants)]
 #[strum_discriminants(derive(EnumIter, EnumMessage))]
 pub(crate) enum ConversionSettings {
     #[strum_discriminants(strum(message = "JPEG (.jpg/.jpeg)"))]
-    JPEG(bool, u8, Entity<SliderState>),
+    /// Alpha policy and background colour input for transparent images
+    JPEG(bool, u8, AlphaPolicyKind, Entity<SliderState>, Entity<SelectState<Vec<String>>>, Entity<InputState>),
     #[strum_discriminants(strum(message = "PNG (.png)"))]
-    PNG(bool, u8, Entity<SliderState>),
+    PNG(bool, u8, BitDepth, ColorMode, Entity<SliderState>, Entity<SelectState<Vec<String>>>, Entity<SelectState<Vec<String>>>),
     #[strum_discriminants(strum(message = "TIFF (.tif/.tiff)"))]
-    TIFF(bool, TIFFCompression, Entity<SelectState<Vec<String>>>),
+    TIFF(bool, TIFFCompression, BitDepth, ColorMode, Entity<SelectState<Vec<String>>>, Entity<SelectState<Vec<String>>>, Entity<SelectState<Vec<String>>>),
     #[strum_discriminants(strum(message = "WebP (.webp)"))]
     WebP(bool, u8, Entity<SliderState>),
+    #[strum_discriminants(strum(message = "AVIF (.avif)"))]
+    AVIF(bool, u8, u8, ChromaSubsampling, Entity<SliderState>, Entity<SliderState>, Entity<SelectState<Vec<String>>>),
+    #[strum_discriminants(strum(message = "JPEG XL (.jxl)"))]
+    JXL(bool, u8, u8, Entity<SliderState>, Entity<SliderState>),
+    #[strum_discriminants(strum(message = "HEIF (.heic)"))]
+    HEIF(bool, u8, bool, Entity<SliderState>),
 }
 
 impl ConversionSettings {
     pub(super) fn new(cx: &mut App, window: &mut Window, variant: ConversionSettingsDiscriminants) -> Self {
+        let slider = |cx: &mut App, min: f32, max: f32, value: u8| { cx.new(|_| { SliderState::new().max(max).min(min).step(1.).default_value(value as f32) }) };
+        match OutputFormat::default_for(variant.into()) {
+            OutputFormat::JPEG(quality, _) => Self::JPEG(true, quality, AlphaPolicyKind::Composite, slider(cx, 0., 100., quality), cx.new(|cx| { SelectState::new(AlphaPolicyKind::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }), cx.new(|cx| { InputState::new(window, cx).default_value("#FFFFFF").placeholder("Hintergrundfarbe, z.B. #FFFFFF") })),
+            OutputFormat::PNG(compression, bit_depth, color_mode) => Self::PNG(true, compression, bit_depth, color_mode, slider(cx, 0., 100., compression), cx.new(|cx| { SelectState::new(BitDepth::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }), cx.new(|cx| { SelectState::new(ColorMode::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) })),
+            // TIFF files are written without palette
+            OutputFormat::TIFF(compression, bit_depth, color_mode) => Self::TIFF(true, compression, bit_depth, color_mode, cx.new(|cx| { SelectState::new(TIFFCompression::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }), cx.new(|cx| { SelectState::new(BitDepth::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }), cx.new(|cx| { SelectState::new(ColorMode::iter().filter(|variant| { *variant != ColorMode::Palette }).map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) })),
+            OutputFormat::WebP(quality) => Self::WebP(true, quality, slider(cx, 0., 100., quality)),
+            OutputFormat::AVIF(quality, speed, chroma) => Self::AVIF(true, quality, speed, chroma, slider(cx, 0., 100., quality), slider(cx, 0., 9., speed), cx.new(|cx| { SelectState::new(ChromaSubsampling::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) })),
+            OutputFormat::JXL(quality, effort) => Self::JXL(true, quality, effort, slider(cx, 0., 100., quality), slider(cx, 1., 9., effort)),
+            OutputFormat::HEIF(quality, lossless) => Self::HEIF(true, quality, lossless, slider(cx, 0., 100., quality)),
+        }
+    }
+    
+    /// Strips the UI state, leaving the plain encoder settings. An invalid background colour falls back to white.
+    pub(super) fn output_format(&self, cx: &App) -> OutputFormat {
+        match self {
+            Self::JPEG(_, quality, alpha_policy, _, _, background_entity) => OutputFormat::JPEG(*quality, match AlphaPolicy::default_for(*alpha_policy) {
+                AlphaPolicy::Composite(background) => AlphaPolicy::Composite(AlphaPolicy::parse_background(&background_entity.read(cx).value()).unwrap_or(background)),
+                alpha_policy => alpha_policy,
+            }),
+            Self::PNG(_, compression, bit_depth, color_mode, _, _, _) => OutputFormat::PNG(*compression, *bit_depth, *color_mode),
+            Self::TIFF(_, compression, bit_depth, color_mode, _, _, _) => OutputFormat::TIFF(*compression, *bit_depth, *color_mode),
+            Self::WebP(_, quality, _) => OutputFormat::WebP(*quality),
+            Self::AVIF(_, quality, speed, chroma, _, _, _) => OutputFormat::AVIF(*quality, *speed, *chroma),
+            Self::JXL(_, quality, effort, _, _) => OutputFormat::JXL(*quality, *effort),
+            Self::HEIF(_, quality, lossless, _) => OutputFormat::HEIF(*quality, *lossless),
+        }
+    }
+    
+    pub(super) fn keep_metadata(&self) -> bool {
+        match self {
+            Self::JPEG(metadata, _, _, _, _, _) |
+            Self::PNG(metadata, _, _, _, _, _, _) |
+            Self::TIFF(metadata, _, _, _, _, _, _) |
+            Self::WebP(metadata, _, _) |
+            Self::AVIF(metadata, _, _, _, _, _, _) |
+            Self::JXL(metadata, _, _, _, _) |
+            Self::HEIF(metadata, _, _, _) => *metadata,
+        }
+    }
+    
+    /// Connects the selects and sliders of the format to its values, the subscriptions end when they are dropped
+    pub(super) fn subscribe(&self, window: &Window, cx: &mut Context<super::ui::Application>) -> Vec<Subscription> {
+        let quality_setter = |_: &mut Context<super::ui::Application>, _: &mut Window, this: &mut super::ui::Application, value: f32| {
+            match &mut this.state.conversion_settings.settings {
+                Self::JPEG(_, comp, _, _, _, _) => *comp = value as u8,
+                Self::PNG(_, comp, _, _, _, _, _) => *comp = value as u8,
+                Self::WebP(_, comp, _) => *comp = value as u8,
+                Self::AVIF(_, comp, _, _, _, _, _) => *comp = value as u8,
+                Self::JXL(_, comp, _, _, _) => *comp = value as u8,
+                Self::HEIF(_, comp, _, _) => *comp = value as u8,
+                Self::TIFF(_, _, _, _, _, _, _) => {},
+            }
+        };
+        let bit_depth_setter = |_: &mut Context<super::ui::Application>, _: &mut Window, this: &mut super::ui::Application, value: &String| {
+            let variant = BitDepth::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+            match &mut this.state.conversion_settings.settings {
+                Self::PNG(_, _, bit_depth, _, _, _, _) => *bit_depth = variant,
+                Self::TIFF(_, _, bit_depth, _, _, _, _) => *bit_depth = variant,
+                _ => {}
+            }
+        };
+        // The PNG/TIFF colour type
+        let color_mode_setter = |_: &mut Context<super::ui::Application>, _: &mut Window, this: &mut super::ui::Application, value: &String| {
+            let variant = ColorMode::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+            match &mut this.state.conversion_settings.settings {
+                Self::PNG(_, _, _, color_mode, _, _, _) => *color_mode = variant,
+                Self::TIFF(_, _, _, color_mode, _, _, _) => *color_mode = variant,
+                _ => {}
+            }
+        };
+        match self {
+            Self::JPEG(_, _, _, quality_entity, alpha_policy_entity, _) => vec![
+                super::actions::handle_slider_event(quality_entity, window, cx, quality_setter),
+                super::actions::handle_select_event(alpha_policy_entity, window, cx, |_, _, this, value| {
+                    let variant = AlphaPolicyKind::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                    match &mut this.state.conversion_settings.settings {
+                        Self::JPEG(_, _, alpha_policy, _, _, _) => *alpha_policy = variant,
+                        _ => {}
+                    }
+                }),
+            ],
+            Self::PNG(_, _, _, _, compression_entity, bit_depth_entity, color_mode_entity) => vec![
+                super::actions::handle_slider_event(compression_entity, window, cx, quality_setter),
+                super::actions::handle_select_event(bit_depth_entity, window, cx, bit_depth_setter),
+                super::actions::handle_select_event(color_mode_entity, window, cx, color_mode_setter),
+            ],
+            Self::TIFF(_, _, _, _, compression_entity, bit_depth_entity, color_mode_entity) => vec![
+                super::actions::handle_select_event(compression_entity, window, cx, |_, _, this, value| {
+                    let variant = TIFFCompression::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                    match &mut this.state.conversion_settings.settings {
+                        Self::TIFF(_, tiffcompression, _, _, _, _, _) => *tiffcompression = variant,
+                        _ => {}
+                    }
+                }),
+                super::actions::handle_select_event(bit_depth_entity, window, cx, bit_depth_setter),
+                super::actions::handle_select_event(color_mode_entity, window, cx, color_mode_setter),
+            ],
+            Self::WebP(_, _, quality_entity) |
+            Self::HEIF(_, _, _, quality_entity) => vec![super::actions::handle_slider_event(quality_entity, window, cx, quality_setter)],
+            Self::AVIF(_, _, _, _, quality_entity, speed_entity, chroma_entity) => vec![
+                super::actions::handle_slider_event(quality_entity, window, cx, quality_setter),
+                // Encoder speed
+                super::actions::handle_slider_event(speed_entity, window, cx, |_, _, this, value| {
+                    match &mut this.state.conversion_settings.settings {
+                        Self::AVIF(_, _, speed, _, _, _, _) => *speed = value as u8,
+                        _ => {}
+                    }
+                }),
+                // Chroma subsampling
+                super::actions::handle_select_event(chroma_entity, window, cx, |_, _, this, value| {
+                    let variant = ChromaSubsampling::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                    match &mut this.state.conversion_settings.settings {
+                        Self::AVIF(_, _, _, chroma, _, _, _) => *chroma = variant,
+                        _ => {}
+                    }
+                }),
+            ],
+            Self::JXL(_, _, _, quality_entity, effort_entity) => vec![
+                super::actions::handle_slider_event(quality_entity, window, cx, quality_setter),
+                super::actions::handle_slider_event(effort_entity, window, cx, |_, _, this, value| {
+                    match &mut this.state.conversion_settings.settings {
+                        Self::JXL(_, _, effort, _, _) => *effort = value as u8,
+                        _ => {}
+                    }
+                }),
+            ],
+        }
+    }
+}
+
+impl From<ConversionSettingsDiscriminants> for OutputFormatKind {
+    fn from(variant: ConversionSettingsDiscriminants) -> Self {
         match variant {
-            ConversionSettingsDiscriminants::JPEG => Self::JPEG(true, 90, cx.new(|_| { SliderState::new().max(100.).min(0.).step(1.).default_value(90.) })),
-            ConversionSettingsDiscriminants::PNG => Self::PNG(true, 75, cx.new(|_| { SliderState::new().max(100.).min(0.).step(1.).default_value(75.) })),
-            ConversionSettingsDiscriminants::TIFF => Self::TIFF(true, TIFFCompression::None, cx.new(|cx| { SelectState::new(TIFFCompression::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) })),
-            ConversionSettingsDiscriminants::WebP => Self::WebP(true, 80, cx.new(|_| { SliderState::new().max(100.).min(0.).step(1.).default_value(80.) })),
+            ConversionSettingsDiscriminants::JPEG => Self::JPEG,
+            ConversionSettingsDiscriminants::PNG => Self::PNG,
+            ConversionSettingsDiscriminants::TIFF => Self::TIFF,
+            ConversionSettingsDiscriminants::WebP => Self::WebP,
+            ConversionSettingsDiscriminants::AVIF => Self::AVIF,
+            ConversionSettingsDiscriminants::JXL => Self::JXL,
+            ConversionSettingsDiscriminants::HEIF => Self::HEIF,
         }
     }
 }
@@ -52,13 +190,21 @@ impl ConversionSettings {
 pub(super) struct OutputFolderState {
     pub(super) ui_entity: Entity<InputState>,
     pub(super) value: PathBuf,
+    pub(super) name_template_entity: Entity<InputState>,
+    pub(super) collision_policy_entity: Entity<SelectState<Vec<String>>>,
+    pub(super) collision_policy: CollisionPolicy,
+    /// Recreates the folders of the inputs below the output folder
+    pub(super) keep_structure: bool,
+    /// Folder the structure is taken relative to, the common folder of all inputs if unset
+    pub(super) structure_base: Option<PathBuf>,
+    _subscriptions: Vec<Subscription>,
 }
 
 impl OutputFolderState {
-    fn new(cx: &mut App, window: &mut Window) -> Self {
+    fn new(cx: &mut Context<super::ui::Application>, window: &mut Window) -> Self {
         // Get default output folder
         let picture_dir = Arc::new(RwLock::new(super::utils::user_picture_dir()));
-        Self {
+        let mut state = Self {
             // Output Folder Input
             ui_entity: cx.new(|cx| {
                 InputState::new(window, cx)
@@ -66,19 +212,119 @@ impl OutputFolderState {
                     .placeholder("Ausgabeordner")
             }),
             value: super::utils::user_picture_dir(),
+            name_template_entity: cx.new(|cx| {
+                InputState::new(window, cx)
+                    .default_value(DEFAULT_NAME_TEMPLATE)
+                    .placeholder("Dateiname, z.B. {date}_{index}")
+            }),
+            collision_policy_entity: cx.new(|cx| { SelectState::new(CollisionPolicy::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }),
+            collision_policy: CollisionPolicy::default(),
+            keep_structure: false,
+            structure_base: None,
+            _subscriptions: Vec::new(),
+        };
+        // Handle Select Event for the collision policy
+        state._subscriptions = vec![super::actions::handle_select_event(&state.collision_policy_entity, window, cx, |_, _, this, value| {
+            let variant = CollisionPolicy::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+            this.state.output_folder_state.collision_policy = variant;
+        })];
+        state
+    }
+}
+
+/// How deep dropped or picked folders are searched for images
+#[derive(Clone, Copy, Default, PartialEq, EnumIter, EnumMessage)]
+pub(super) enum ScanDepth {
+    #[strum(message = "Alle Unterordner")]
+    #[default]
+    Unlimited,
+    #[strum(message = "Nur der Ordner selbst")]
+    Flat,
+    #[strum(message = "1 Unterordnerebene")]
+    One,
+    #[strum(message = "2 Unterordnerebenen")]
+    Two,
+    #[strum(message = "3 Unterordnerebenen")]
+    Three,
+}
+
+impl ScanDepth {
+    pub(super) fn max_depth(&self) -> Option<usize> {
+        match self {
+            Self::Unlimited => None,
+            Self::Flat => Some(0),
+            Self::One => Some(1),
+            Self::Two => Some(2),
+            Self::Three => Some(3),
         }
     }
 }
 
+pub(super) struct ImportState {
+    pub(super) depth_dropdown_entity: Entity<SelectState<Vec<String>>>,
+    pub(super) depth: ScanDepth,
+    pub(super) include_hidden: bool,
+    /// Folder scans running in the background
+    pub(super) running_scans: usize,
+    /// Number of files the running scans looked at
+    pub(super) scanned_files: usize,
+    _subscriptions: Vec<Subscription>,
+}
+
+impl ImportState {
+    fn new(cx: &mut Context<super::ui::Application>, window: &mut Window) -> Self {
+        let mut state = Self {
+            depth_dropdown_entity: cx.new(|cx| { SelectState::new(ScanDepth::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }),
+            depth: ScanDepth::default(),
+            include_hidden: false,
+            running_scans: 0,
+            scanned_files: 0,
+            _subscriptions: Vec::new(),
+        };
+        // Handle Select Event for the folder scan depth
+        state._subscriptions = vec![super::actions::handle_select_event(&state.depth_dropdown_entity, window, cx, |_, _, this, value| {
+            let variant = ScanDepth::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+            this.state.import_state.depth = variant;
+        })];
+        state
+    }
+}
+
 pub(super) struct ConversionSettingsState {
     pub(super) format_dropdown_entity: Entity<SelectState<Vec<String>>>,
     pub(super) settings: ConversionSettings,
     pub(super) variant: ConversionSettingsDiscriminants,
+    /// Whether the pixels are transformed into sRGB instead of keeping the source colour space
+    pub(super) convert_to_srgb: bool,
+    /// Whether every image of multi-image containers is exported instead of only the primary one
+    pub(super) export_all_images: bool,
+    pub(super) export_depth: bool,
+    pub(super) export_auxiliary: bool,
+    pub(super) auxiliary_format_dropdown_entity: Entity<SelectState<Vec<String>>>,
+    pub(super) auxiliary_format: AuxiliaryFormat,
+    pub(super) live_photo_video_dropdown_entity: Entity<SelectState<Vec<String>>>,
+    pub(super) live_photo_video: LivePhotoVideoAction,
+    pub(super) resize_kind_dropdown_entity: Entity<SelectState<Vec<String>>>,
+    pub(super) resize_kind: ResizeKind,
+    /// Longest edge, box width, percentage or megapixels, depending on the resize kind
+    pub(super) resize_value_entity: Entity<InputState>,
+    /// Box height, only used when fitting into a box
+    pub(super) resize_height_entity: Entity<InputState>,
+    pub(super) resize_filter_dropdown_entity: Entity<SelectState<Vec<String>>>,
+    pub(super) resize_filter: ResizeFilter,
+    pub(super) rotation_dropdown_entity: Entity<SelectState<Vec<String>>>,
+    pub(super) crop_dropdown_entity: Entity<SelectState<Vec<String>>>,
+    pub(super) crop_anchor_dropdown_entity: Entity<SelectState<Vec<String>>>,
+    /// Applied to every image without an override of its own
+    pub(super) transform: Transform,
+    _subscriptions: Vec<Subscription>,
+    /// Subscriptions to the selects and sliders of `settings`, replaced together with them
+    _settings_subscriptions: Vec<Subscription>,
 }
 
 impl ConversionSettingsState {
-    pub(super) fn new(cx: &mut App, window: &mut Window) -> Self {
-        Self { 
+    pub(super) fn new(cx: &mut Context<super::ui::Application>, window: &mut Window) -> Self {
+        let mut state = Self { 
             format_dropdown_entity: cx.new(|cx| {
                 SelectState::new(
                     ConversionSettingsDiscriminants::iter().map(|variant| { format!("{}", variant.get_message().unwrap()) }).collect::<Vec<_>>(), 
@@ -88,7 +334,128 @@ impl ConversionSettingsState {
                 )
             }), 
             settings: ConversionSettings::new(cx, window, ConversionSettingsDiscriminants::JPEG),
-            variant: ConversionSettingsDiscriminants::JPEG
+            variant: ConversionSettingsDiscriminants::JPEG,
+            convert_to_srgb: false,
+            export_all_images: false,
+            export_depth: false,
+            export_auxiliary: false,
+            auxiliary_format_dropdown_entity: cx.new(|cx| { SelectState::new(AuxiliaryFormat::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }),
+            auxiliary_format: AuxiliaryFormat::default(),
+            live_photo_video_dropdown_entity: cx.new(|cx| { SelectState::new(LivePhotoVideoAction::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }),
+            live_photo_video: LivePhotoVideoAction::default(),
+            resize_kind_dropdown_entity: cx.new(|cx| { SelectState::new(ResizeKind::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }),
+            resize_kind: ResizeKind::Original,
+            resize_value_entity: cx.new(|cx| { InputState::new(window, cx) }),
+            resize_height_entity: cx.new(|cx| { InputState::new(window, cx) }),
+            resize_filter_dropdown_entity: cx.new(|cx| { SelectState::new(ResizeFilter::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }),
+            resize_filter: ResizeFilter::default(),
+            rotation_dropdown_entity: cx.new(|cx| { SelectState::new(Rotation::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }),
+            crop_dropdown_entity: cx.new(|cx| { SelectState::new(CropAspect::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }),
+            crop_anchor_dropdown_entity: cx.new(|cx| { SelectState::new(CropAnchor::iter().map(|variant| { variant.get_message().unwrap().to_string() }).collect::<Vec<String>>(), Some(IndexPath::default()), window, cx) }),
+            transform: Transform::default(),
+            _subscriptions: Vec::new(),
+            _settings_subscriptions: Vec::new(),
+        };
+        state._subscriptions = state.subscribe(window, cx);
+        state._settings_subscriptions = state.settings.subscribe(window, cx);
+        state
+    }
+    
+    /// Switches to the default settings of another format
+    pub(super) fn set_format(&mut self, variant: ConversionSettingsDiscriminants, window: &mut Window, cx: &mut Context<super::ui::Application>) {
+        self.variant = variant;
+        self.settings = ConversionSettings::new(cx, window, variant);
+        self._settings_subscriptions = self.settings.subscribe(window, cx);
+    }
+    
+    fn subscribe(&self, window: &Window, cx: &mut Context<super::ui::Application>) -> Vec<Subscription> {
+        vec![
+            // Handle Select Event for Format
+            super::actions::handle_select_event(&self.format_dropdown_entity, window, cx, |cx, window, this, value| {
+                let variant = ConversionSettingsDiscriminants::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                this.state.conversion_settings.set_format(variant, window, cx);
+            }),
+            // Handle Select Event for the depth map and auxiliary image format
+            super::actions::handle_select_event(&self.auxiliary_format_dropdown_entity, window, cx, |_, _, this, value| {
+                let variant = AuxiliaryFormat::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                this.state.conversion_settings.auxiliary_format = variant;
+            }),
+            // Handle Select Event for the Live Photo video
+            super::actions::handle_select_event(&self.live_photo_video_dropdown_entity, window, cx, |_, _, this, value| {
+                let variant = LivePhotoVideoAction::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                this.state.conversion_settings.live_photo_video = variant;
+            }),
+            // Handle Select Event for the resize kind, the inputs switch to the defaults of the new kind
+            super::actions::handle_select_event(&self.resize_kind_dropdown_entity, window, cx, |cx, window, this, value| {
+                let variant = ResizeKind::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                let (resize_value, resize_height) = Self::resize_input_values(variant);
+                this.state.conversion_settings.resize_kind = variant;
+                this.state.conversion_settings.resize_value_entity.update(cx, |input, cx| { input.set_value(resize_value, window, cx); });
+                this.state.conversion_settings.resize_height_entity.update(cx, |input, cx| { input.set_value(resize_height, window, cx); });
+            }),
+            // Handle Select Event for the resize filter
+            super::actions::handle_select_event(&self.resize_filter_dropdown_entity, window, cx, |_, _, this, value| {
+                let variant = ResizeFilter::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                this.state.conversion_settings.resize_filter = variant;
+            }),
+            // Handle Select Events for the batch transform
+            super::actions::handle_select_event(&self.rotation_dropdown_entity, window, cx, |_, _, this, value| {
+                let variant = Rotation::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                this.state.conversion_settings.transform.rotation = variant;
+            }),
+            super::actions::handle_select_event(&self.crop_dropdown_entity, window, cx, |_, _, this, value| {
+                let variant = CropAspect::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                this.state.conversion_settings.transform.crop = variant;
+            }),
+            super::actions::handle_select_event(&self.crop_anchor_dropdown_entity, window, cx, |_, _, this, value| {
+                let variant = CropAnchor::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
+                this.state.conversion_settings.transform.crop_anchor = variant;
+            }),
+        ]
+    }
+    
+    /// Values shown in the resize inputs when switching to `kind`
+    pub(super) fn resize_input_values(kind: ResizeKind) -> (String, String) {
+        match Resize::default_for(kind) {
+            Resize::Original => (String::new(), String::new()),
+            Resize::LongestEdge(edge) => (edge.to_string(), String::new()),
+            Resize::FitInBox(width, height) => (width.to_string(), height.to_string()),
+            Resize::Percentage(percentage) => (percentage.to_string(), String::new()),
+            Resize::Megapixels(megapixels) => (megapixels.to_string(), String::new()),
+        }
+    }
+    
+    /// Reads the resize inputs, values which are not a positive number fall back to the defaults
+    fn resize(&self, cx: &App) -> Resize {
+        let value = self.resize_value_entity.read(cx).value().trim().replace(',', ".");
+        let height = self.resize_height_entity.read(cx).value().trim().to_string();
+        match Resize::default_for(self.resize_kind) {
+            Resize::Original => Resize::Original,
+            Resize::LongestEdge(edge) => Resize::LongestEdge(value.parse().ok().filter(|edge| { *edge > 0 }).unwrap_or(edge)),
+            Resize::FitInBox(default_width, default_height) => Resize::FitInBox(
+                value.parse().ok().filter(|width| { *width > 0 }).unwrap_or(default_width),
+                height.parse().ok().filter(|height| { *height > 0 }).unwrap_or(default_height),
+            ),
+            Resize::Percentage(percentage) => Resize::Percentage(value.parse().ok().filter(|percentage| { (1..=100).contains(percentage) }).unwrap_or(percentage)),
+            Resize::Megapixels(megapixels) => Resize::Megapixels(value.parse().ok().filter(|megapixels| { *megapixels > 0. }).unwrap_or(megapixels)),
+        }
+    }
+    
+    pub(super) fn conversion_options(&self, output_folder_state: &OutputFolderState, cx: &App) -> ConversionOptions {
+        ConversionOptions {
+            format: self.settings.output_format(cx),
+            keep_metadata: self.settings.keep_metadata(),
+            convert_to_srgb: self.convert_to_srgb,
+            name_template: output_folder_state.name_template_entity.read(cx).value().to_string(),
+            collision_policy: output_folder_state.collision_policy,
+            export_all_images: self.export_all_images,
+            export_depth: self.export_depth,
+            export_auxiliary: self.export_auxiliary,
+            auxiliary_format: self.auxiliary_format,
+            live_photo_video: self.live_photo_video,
+            transform: self.transform,
+            resize: self.resize(cx),
+            resize_filter: self.resize_filter,
         }
     }
 }
@@ -99,18 +466,43 @@ pub(super) enum ImageLoadingState {
     NotStarted,
     InProgress,
     Done(Arc<dyn Fn(&mut Window, &mut App) -> Option<Result<Arc<RenderImage>, ImageCacheError>>>),
-    Failure(String),
+    Failure(ConversionErrorKind),
+}
+
+/// Outcome of the last conversion run for a single image
+#[derive(Default)]
+pub(super) enum ConversionResult {
+    #[default]
+    Pending,
+    /// Every image written for the source, several if all images of a container were exported
+    Converted(Vec<PathBuf>),
+    /// The output already existed and was kept
+    Skipped(PathBuf),
+    Failed(ConversionErrorKind),
 }
 
 pub(super) struct InputImage {
     pub(super) state: ImageLoadingState,
     pub(super) path: PathBuf,
     pub(super) name: String,
+    /// 1-based position in the input list for the `{index}` token, kept when other images are removed or skipped
+    pub(super) index: usize,
+    /// HEIF brand from the file header, e.g. heic, mif1 or avif
+    pub(super) brand: String,
+    /// Number of top-level images in the container, known once the thumbnail is loaded
+    pub(super) image_count: usize,
+    /// Companion video if the image is the still of a Live Photo, known once the thumbnail is loaded
+    pub(super) live_photo_video: Option<PathBuf>,
+    /// Replaces the batch transform for this image only
+    pub(super) transform_override: Option<Transform>,
+    pub(super) conversion_result: ConversionResult,
 }
 
 pub(super) struct InputImageState {
     pub(super) total_count: u16,
     pub(super) images: OrderMap<PathBuf, InputImage>,
+    /// Number of images ever added, so a new image never reuses the index of a removed one
+    pub(super) added_count: usize,
     pub(super) ui_liststate: ListState,
 }
 
@@ -119,25 +511,55 @@ impl Default for InputImageState {
         Self {
             total_count: Default::default(),
             images: Default::default(),
+            added_count: 0,
             ui_liststate: ListState::new(0, ListAlignment::Top, px(16.))
         }
     }
 }
 
+/// Output files a running conversion found already existing, waiting for the user to decide
+#[derive(Default)]
+pub(super) struct CollisionPromptState {
+    /// Each worker blocks until its question is answered through the sender
+    pub(super) pending: VecDeque<(PathBuf, Sender<CollisionPolicy>)>,
+    pub(super) apply_to_all: bool,
+    /// Answer given with "apply to all", shared with the workers so they stop asking
+    pub(super) remembered: Arc<Mutex<Option<CollisionPolicy>>>,
+}
+
 pub(super) struct ApplicationState {
     pub(super) conversion_progress: ConversionProgress,
+    /// Failed files of the last run, kept apart from the progress so it keeps counting after a failure
+    pub(super) conversion_errors: HashMap<PathBuf, ConversionErrorKind>,
     pub(super) conversion_settings: ConversionSettingsState,
     pub(super) output_folder_state: OutputFolderState,
     pub(super) input_image_state: InputImageState,
+    pub(super) import_state: ImportState,
+    /// Whether the failed files of the last run are listed instead of the input images
+    pub(super) show_error_report: bool,
+    /// Token of the running conversion, replaced on every start
+    pub(super) cancellation_token: CancellationToken,
+    /// Set while workers are busy, also after a cancellation until the running files are done
+    pub(super) conversion_running: bool,
+    pub(super) collision_prompt: CollisionPromptState,
+    /// Image whose own transform is being edited
+    pub(super) transform_editor: Option<PathBuf>,
 }
 
 impl ApplicationState {
-    pub(super) fn new(cx: &mut App, window: &mut Window) -> Self {
+    pub(super) fn new(cx: &mut Context<super::ui::Application>, window: &mut Window) -> Self {
         ApplicationState {
             conversion_progress: Default::default(),
+            conversion_errors: Default::default(),
             input_image_state: Default::default(),
+            import_state: ImportState::new(cx, window),
             conversion_settings: ConversionSettingsState::new(cx, window),
             output_folder_state: OutputFolderState::new(cx, window),
+            show_error_report: false,
+            cancellation_token: Default::default(),
+            conversion_running: false,
+            collision_prompt: Default::default(),
+            transform_editor: None,
         }
     }
 }
@@ -1,6 +1,6 @@
 use std::ops::Range;
-use gpui::{App, Context, ElementId, Entity, ExternalPaths, Fill, ImageSource, InteractiveElement, IntoElement, ObjectFit, ParentElement, Render, Styled, StyledImage, Window, div, img, prelude::FluentBuilder, px, uniform_list};
-use gpui_component::{ActiveTheme, Disableable, Icon, IconName, Sizable, StyledExt, button::{Button, ButtonCustomVariant, ButtonVariants}, checkbox::Checkbox, input::Input, label::Label, progress::Progress, select::Select, slider::{Slider, SliderState}, spinner::Spinner};
+use gpui::{Context, ElementId, ExternalPaths, Fill, ImageSource, InteractiveElement, IntoElement, ObjectFit, ParentElement, Render, StatefulInteractiveElement, Styled, StyledImage, Window, div, img, prelude::FluentBuilder, px, uniform_list};
+use gpui_component::{ActiveTheme, Disableable, Icon, IconName, Sizable, StyledExt, button::{Button, ButtonCustomVariant, ButtonVariants}, checkbox::Checkbox, input::Input, label::Label, progress::Progress, select::Select, slider::Slider, spinner::Spinner};
 use strum::{EnumMessage, IntoEnumIterator};
 
 pub(super) struct Application {
@@ -8,8 +8,272 @@ pub(super) struct Application {
 }
 
 impl Application {
-    pub(super) fn new(mut cx: &mut App, window: &mut Window) -> Self {
-        Self { state: super::state::ApplicationState::new(&mut cx, window) }
+    pub(super) fn new(cx: &mut Context<Self>, window: &mut Window) -> Self {
+        Self { state: super::state::ApplicationState::new(cx, window) }
+    }
+    
+    /// Lists every file which failed in the last conversion run, laid over the input list
+    fn render_error_report(&self, cx: &mut Context<Self>) -> impl IntoElement {
+        let mut errors = self.state.conversion_errors.iter().collect::<Vec<_>>();
+        errors.sort_by(|(a, _), (b, _)| { a.cmp(b) });
+        
+        div()
+            .absolute()
+            .top_0()
+            .left_0()
+            .size_full()
+            .bg(cx.theme().background)
+            .p_4()
+            .flex()
+            .flex_col()
+            .gap_2()
+            .child(
+                div()
+                    .w_full()
+                    .flex()
+                    .items_center()
+                    .justify_between()
+                    .gap_2()
+                    .child(
+                        Label::new(format!("{} Bild(er) konnten nicht umgewandelt werden", errors.len()))
+                            .font_semibold()
+                            .flex_grow()
+                    )
+                    .child(
+                        Button::new("UnHEIC.UI.ErrorReport.Button.RetryFailed")
+                            .label("Fehlgeschlagene erneut versuchen")
+                            .small()
+                            .compact()
+                            // The report already lists failures while the run continues
+                            .disabled(self.state.conversion_running)
+                            .on_click(cx.listener(super::actions::handle_retry_failed_button))
+                    )
+                    .child(
+                        Button::new("UnHEIC.UI.ErrorReport.Button.CopyReport")
+                            .label("Bericht kopieren")
+                            .small()
+                            .compact()
+                            .on_click(cx.listener(super::actions::handle_copy_error_report_button))
+                    )
+                    .child(
+                        Button::new("UnHEIC.UI.ErrorReport.Button.Close")
+                            .icon(Icon::new(IconName::Close))
+                            .tooltip("Fehlerbericht schließen")
+                            .small()
+                            .compact()
+                            .on_click(cx.listener(super::actions::handle_close_error_report_button))
+                    )
+            )
+            .child(
+                div()
+                    .id("UnHEIC.UI.ErrorReport.List")
+                    .w_full()
+                    .flex_grow()
+                    .overflow_y_scroll()
+                    .flex()
+                    .flex_col()
+                    .children(errors.into_iter().map(|(path, kind)| {
+                        let reveal_path = path.clone();
+                        div()
+                            .w_full()
+                            .py_2()
+                            .flex()
+                            .items_center()
+                            .justify_between()
+                            .gap_4()
+                            .border_b_1()
+                            .border_color(cx.theme().title_bar_border)
+                            .child(
+                                div()
+                                    .flex_grow()
+                                    .flex()
+                                    .flex_col()
+                                    .gap_1()
+                                    .child(
+                                        Label::new(format!("{}", path.display()))
+                                            .text_sm()
+                                            .font_semibold()
+                                    )
+                                    .child(
+                                        Label::new(super::utils::describe_error(kind))
+                                            .text_xs()
+                                            .text_color(cx.theme().red)
+                                    )
+                            )
+                            .child(
+                                Button::new(ElementId::Name(format!("UnHEIC.UI.ErrorReport.Button.Reveal.{}", path.display()).into()))
+                                    .icon(Icon::new(IconName::Folder))
+                                    .tooltip("Im Dateimanager anzeigen")
+                                    .ghost()
+                                    .on_click(move |_, _, app| { app.reveal_path(&reveal_path); })
+                            )
+                    }))
+            )
+    }
+    
+    /// Asks what to do with an output file which already exists, one question at a time
+    fn render_collision_prompt(&self, cx: &mut Context<Self>) -> impl IntoElement {
+        let prompt = &self.state.collision_prompt;
+        let out_path = prompt.pending.front().map(|(path, _)| { path.display().to_string() }).unwrap_or_default();
+        let answer_button = |id: &'static str, label: &'static str, policy: unheic_core::CollisionPolicy, cx: &mut Context<Self>| {
+            Button::new(id)
+                .label(label)
+                .small()
+                .compact()
+                .on_click(cx.listener(move |this, _, _, cx| { super::actions::answer_collision_prompt(this, policy, cx); }))
+        };
+        
+        div()
+            .absolute()
+            .top_0()
+            .left_0()
+            .size_full()
+            .bg(cx.theme().background)
+            .p_4()
+            .flex()
+            .flex_col()
+            .justify_center()
+            .items_center()
+            .gap_3()
+            .child(
+                Label::new("Die Ausgabedatei existiert bereits")
+                    .font_semibold()
+            )
+            .child(
+                Label::new(out_path)
+                    .text_sm()
+            )
+            .when(prompt.pending.len() > 1, |this| {
+                this.child(
+                    Label::new(format!("{} weitere Datei(en) warten auf eine Entscheidung", prompt.pending.len() - 1))
+                        .text_xs()
+                        .text_color(cx.theme().secondary_foreground)
+                )
+            })
+            .child(
+                div()
+                    .flex()
+                    .items_center()
+                    .gap_2()
+                    .child(answer_button("UnHEIC.UI.CollisionPrompt.Button.Overwrite", "Überschreiben", unheic_core::CollisionPolicy::Overwrite, cx))
+                    .child(answer_button("UnHEIC.UI.CollisionPrompt.Button.Skip", "Überspringen", unheic_core::CollisionPolicy::Skip, cx))
+                    .child(answer_button("UnHEIC.UI.CollisionPrompt.Button.AutoSuffix", "Nummer anhängen", unheic_core::CollisionPolicy::AutoSuffix, cx))
+            )
+            .child(
+                Checkbox::new("UnHEIC.UI.CollisionPrompt.Checkbox.ApplyToAll")
+                    .label("Für alle weiteren Dateien übernehmen")
+                    .xsmall()
+                    .checked(prompt.apply_to_all)
+                    .on_click(cx.listener(super::actions::handle_collision_apply_to_all_checkbox_change))
+            )
+    }
+    
+    /// Edits the transform of a single image, laid over the input list
+    fn render_transform_editor(&self, cx: &mut Context<Self>) -> impl IntoElement {
+        let image = self.state.transform_editor.as_ref().and_then(|path| { self.state.input_image_state.images.get(path) });
+        let name = image.map(|image| { image.name.clone() }).unwrap_or_default();
+        let has_override = image.is_some_and(|image| { image.transform_override.is_some() });
+        let transform = image.and_then(|image| { image.transform_override }).unwrap_or(self.state.conversion_settings.transform);
+        // One button per choice, the current one is highlighted
+        let choice_button = |id: String, label: &'static str, selected: bool, edit: Box<dyn Fn(&mut unheic_core::Transform)>, cx: &mut Context<Self>| {
+            Button::new(ElementId::Name(id.into()))
+                .label(label)
+                .xsmall()
+                .compact()
+                .map(|this| { if selected { this.primary() } else { this.outline() } })
+                .on_click(cx.listener(move |this, _, _, cx| { super::actions::edit_transform_override(this, &edit, cx); }))
+        };
+        
+        div()
+            .absolute()
+            .top_0()
+            .left_0()
+            .size_full()
+            .bg(cx.theme().background)
+            .p_4()
+            .flex()
+            .flex_col()
+            .justify_center()
+            .items_center()
+            .gap_3()
+            .child(
+                Label::new(format!("Transformation für {name}"))
+                    .font_semibold()
+            )
+            .child(
+                Label::new(if has_override { "Eigene Einstellungen, die Einstellungen für alle Bilder gelten hier nicht" } else { "Verwendet die Einstellungen für alle Bilder, jede Änderung gilt nur für dieses Bild" })
+                    .text_xs()
+                    .text_color(cx.theme().secondary_foreground)
+            )
+            .child(
+                div()
+                    .flex()
+                    .items_center()
+                    .gap_2()
+                    .children(unheic_core::Rotation::iter().map(|rotation| {
+                        choice_button(format!("UnHEIC.UI.TransformEditor.Button.Rotation.{rotation:?}"), rotation.get_message().unwrap(), transform.rotation == rotation, Box::new(move |transform| { transform.rotation = rotation; }), cx)
+                    }).collect::<Vec<_>>())
+            )
+            .child(
+                div()
+                    .flex()
+                    .items_center()
+                    .gap_4()
+                    .child(
+                        Checkbox::new("UnHEIC.UI.TransformEditor.Checkbox.FlipHorizontal")
+                            .label("Horizontal spiegeln")
+                            .xsmall()
+                            .checked(transform.flip_horizontal)
+                            .on_click(cx.listener(|this, checked: &bool, _, cx| { let checked = *checked; super::actions::edit_transform_override(this, |transform| { transform.flip_horizontal = checked; }, cx); }))
+                    )
+                    .child(
+                        Checkbox::new("UnHEIC.UI.TransformEditor.Checkbox.FlipVertical")
+                            .label("Vertikal spiegeln")
+                            .xsmall()
+                            .checked(transform.flip_vertical)
+                            .on_click(cx.listener(|this, checked: &bool, _, cx| { let checked = *checked; super::actions::edit_transform_override(this, |transform| { transform.flip_vertical = checked; }, cx); }))
+                    )
+            )
+            .child(
+                div()
+                    .flex()
+                    .items_center()
+                    .gap_2()
+                    .children(unheic_core::CropAspect::iter().map(|crop| {
+                        choice_button(format!("UnHEIC.UI.TransformEditor.Button.Crop.{crop:?}"), crop.get_message().unwrap(), transform.crop == crop, Box::new(move |transform| { transform.crop = crop; }), cx)
+                    }).collect::<Vec<_>>())
+            )
+            .when(transform.crop != unheic_core::CropAspect::None, |this| {
+                this.child(
+                    div()
+                        .flex()
+                        .items_center()
+                        .gap_2()
+                        .children(unheic_core::CropAnchor::iter().map(|anchor| {
+                            choice_button(format!("UnHEIC.UI.TransformEditor.Button.CropAnchor.{anchor:?}"), anchor.get_message().unwrap(), transform.crop_anchor == anchor, Box::new(move |transform| { transform.crop_anchor = anchor; }), cx)
+                        }).collect::<Vec<_>>())
+                )
+            })
+            .child(
+                div()
+                    .flex()
+                    .items_center()
+                    .gap_2()
+                    .child(
+                        Button::new("UnHEIC.UI.TransformEditor.Button.Reset")
+                            .label("Einstellungen für alle Bilder verwenden")
+                            .small()
+                            .disabled(!has_override)
+                            .on_click(cx.listener(super::actions::handle_reset_transform_override_button))
+                    )
+                    .child(
+                        Button::new("UnHEIC.UI.TransformEditor.Button.Close")
+                            .label("Fertig")
+                            .small()
+                            .primary()
+                            .on_click(cx.listener(super::actions::handle_close_transform_editor_button))
+                    )
+            )
     }
 }
 
@@ -19,46 +283,6 @@ impl Render for Application {
         // Force System Theme
         super::theme::theme_fn(window, cx);
         
-        // Handle Select Event for Format
-        super::actions::handle_select_event(&self.state.conversion_settings.format_dropdown_entity, window, cx, |cx, window, this, value| {
-            let variant = super::state::ConversionSettingsDiscriminants::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
-            this.state.conversion_settings.variant = variant;
-            this.state.conversion_settings.settings = super::state::ConversionSettings::new(cx, window, variant);
-        });
-        
-        // Handle Select Event for TIFF compression
-        match &self.state.conversion_settings.settings {
-            crate::state::ConversionSettings::TIFF(_, _, entity) => {
-                super::actions::handle_select_event(entity, window, cx, |_, _, this, value| {
-                    let variant = super::state::TIFFCompression::iter().find(|variant| { variant.get_message().unwrap() == value }).unwrap();
-                    match &mut this.state.conversion_settings.settings {
-                        crate::state::ConversionSettings::TIFF(_, tiffcompression, _) => *tiffcompression = variant,
-                        _ => {}
-                    }
-                });
-            },
-            _ => {}
-        }
-        
-        let mut slider_fn = |entity: &Entity<SliderState>| {
-            super::actions::handle_slider_event(entity, window, cx, |_, _, this, value| {
-                match &mut this.state.conversion_settings.settings {
-                    crate::state::ConversionSettings::JPEG(_, comp, _) => *comp = value as u8,
-                    crate::state::ConversionSettings::PNG(_, comp, _) => *comp = value as u8,
-                    crate::state::ConversionSettings::WebP(_, comp, _) => *comp = value as u8,
-                    crate::state::ConversionSettings::TIFF(_, _, _) => {},
-                }
-            });
-        };
-        
-        // Handle Slider Events
-        match &self.state.conversion_settings.settings {
-            crate::state::ConversionSettings::JPEG(_, _, entity) => slider_fn(&entity),
-            crate::state::ConversionSettings::PNG(_, _, entity) => slider_fn(&entity),
-            crate::state::ConversionSettings::WebP(_, _, entity) => slider_fn(&entity),
-            _ => {}
-        }
-        
         div()
             .w_full()
             .h_full()
@@ -93,6 +317,40 @@ impl Render for Application {
                                 .justify_end()
                                 .items_center()
                                 .gap_2()
+                                .when(self.state.import_state.running_scans > 0, |this| {
+                                    this
+                                        .child(
+                                            Spinner::new()
+                                                .xsmall()
+                                        )
+                                        .child(
+                                            Label::new(format!("Durchsuche Ordner, {} Dateien geprüft", self.state.import_state.scanned_files))
+                                                .text_xs()
+                                                .text_color(cx.theme().secondary_foreground)
+                                        )
+                                })
+                                .child(
+                                    Select::new(&self.state.import_state.depth_dropdown_entity)
+                                        .xsmall()
+                                        .w_40()
+                                )
+                                .child(
+                                    Checkbox::new("UnHEIC.UI.TitleBar.Checkbox.IncludeHidden")
+                                        .label("Versteckte Dateien")
+                                        .xsmall()
+                                        .checked(self.state.import_state.include_hidden)
+                                        .on_click(cx.listener(super::actions::handle_include_hidden_checkbox_change))
+                                )
+                                .child(
+                                    Button::new("UnHEIC.UI.TitleBar.Button.AddFolder")
+                                        .text_xs()
+                                        .tooltip("Ordner hinzufügen")
+                                        .icon(
+                                            Icon::new(IconName::Folder)
+                                                .text_color(cx.theme().secondary_foreground)
+                                        )
+                                        .on_click(cx.listener(super::actions::handle_add_folder_button))
+                                )
                                 .child(
                                     Button::new("UnHEIC.UI.TitleBar.Button.AddImages")
                                         .text_xs()
@@ -130,6 +388,7 @@ impl Render for Application {
                     .flex_grow()
                     .on_drag_move::<ExternalPaths>(cx.listener(super::actions::handle_file_drag))
                     .on_drop(cx.listener(super::actions::handle_file_drop))
+                    .relative()
                     .child(
                         div()
                             .pl_4()
@@ -143,6 +402,11 @@ impl Render for Application {
                                         let mut items = Vec::new();
                                         for range_idx in range.clone() {
                                             let image = this.state.input_image_state.images.get_index_entry(range_idx).unwrap();
+                                            let error = match (&image.get().state, &image.get().conversion_result) {
+                                                (super::state::ImageLoadingState::Failure(kind), _) => Some(kind),
+                                                (_, super::state::ConversionResult::Failed(kind)) => Some(kind),
+                                                _ => None,
+                                            };
                                             items.push(
                                                 div()
                                                     .w_full()
@@ -195,7 +459,36 @@ impl Render for Application {
                                                                         )
                                                                 )
                                                             },
-                                                            super::state::ImageLoadingState::Failure(_) => todo!(),
+                                                            super::state::ImageLoadingState::Failure(kind) => {
+                                                                let path = image.get().path.clone();
+                                                                this = this.child(
+                                                                    div()
+                                                                        .h_full()
+                                                                        .pt_2()
+                                                                        .pb_2()
+                                                                        .mr_6()
+                                                                        .flex()
+                                                                        .items_center()
+                                                                        .justify_center()
+                                                                        .child(
+                                                                            Button::new(ElementId::Name(format!("UnHEIC.UI.InputArea.Button.RetryThumbnail.{}", path.display()).into()))
+                                                                                .icon(Icon::new(IconName::TriangleAlert).text_color(cx.theme().red))
+                                                                                .ghost()
+                                                                                .tooltip(format!("{}\nWird nicht umgewandelt, klicken um es erneut zu versuchen", super::utils::describe_error(kind)))
+                                                                                .on_click(cx.listener(move |this, _, _, cx| {
+                                                                                    if let Some(entry) = this.state.input_image_state.images.get_mut(&path) {
+                                                                                        entry.state = super::state::ImageLoadingState::NotStarted;
+                                                                                    }
+                                                                                    let path_async = path.clone();
+                                                                                    cx.spawn(async move |we, async_cx| {
+                                                                                        // Request generating the thumbnail image again
+                                                                                        super::utils::request_thumbnail_generation(path_async, we, async_cx).await;
+                                                                                    }).detach();
+                                                                                    cx.notify();
+                                                                                }))
+                                                                        )
+                                                                )
+                                                            },
                                                         }
                                                         this
                                                     })
@@ -208,16 +501,114 @@ impl Render for Application {
                                                             .justify_center()
                                                             .gap_1()
                                                             .child(
-                                                                Label::new(format!("{}", image.get().name))
-                                                                    .font_semibold() 
+                                                                div()
+                                                                    .flex()
+                                                                    .items_center()
+                                                                    .gap_2()
+                                                                    .child(
+                                                                        Label::new(format!("{}", image.get().name))
+                                                                            .font_semibold() 
+                                                                    )
+                                                                    .child(
+                                                                        div()
+                                                                            .px_1()
+                                                                            .rounded_sm()
+                                                                            .border_1()
+                                                                            .border_color(cx.theme().title_bar_border)
+                                                                            .text_xs()
+                                                                            .text_color(cx.theme().secondary_foreground)
+                                                                            .child(image.get().brand.clone())
+                                                                    )
+                                                                    .when(image.get().image_count > 1, |this| {
+                                                                        this.child(
+                                                                            div()
+                                                                                .px_1()
+                                                                                .rounded_sm()
+                                                                                .border_1()
+                                                                                .border_color(cx.theme().title_bar_border)
+                                                                                .text_xs()
+                                                                                .text_color(cx.theme().secondary_foreground)
+                                                                                .child(format!("{} Bilder", image.get().image_count))
+                                                                        )
+                                                                    })
+                                                                    .when(image.get().live_photo_video.is_some(), |this| {
+                                                                        this.child(
+                                                                            div()
+                                                                                .px_1()
+                                                                                .rounded_sm()
+                                                                                .border_1()
+                                                                                .border_color(cx.theme().title_bar_border)
+                                                                                .text_xs()
+                                                                                .text_color(cx.theme().secondary_foreground)
+                                                                                .child("Live Photo")
+                                                                        )
+                                                                    })
                                                             )
                                                             .child(
-                                                                Label::new(format!("{}", image.get().path.display()))
+                                                                Label::new(match &image.get().live_photo_video {
+                                                                    Some(video) => format!("{} + {}", image.get().path.display(), video.file_name().unwrap_or_default().display()),
+                                                                    None => format!("{}", image.get().path.display()),
+                                                                })
                                                                     .text_sm()
                                                                     .italic()
                                                                     .font_light()
                                                             )
+                                                            .when_some(error, |this, kind| {
+                                                                this.child(
+                                                                    Label::new(super::utils::describe_error(kind))
+                                                                        .text_sm()
+                                                                        .text_color(cx.theme().red)
+                                                                )
+                                                            })
                                                     )
+                                                    .map(|this| {
+                                                        let button_id = format!("UnHEIC.UI.InputArea.Button.ConversionResult.{}", image.get().path.display());
+                                                        match &image.get().conversion_result {
+                                                            super::state::ConversionResult::Pending => this,
+                                                            super::state::ConversionResult::Converted(out_paths) => {
+                                                                let tooltip = match out_paths.as_slice() {
+                                                                    [out_path] => format!("Umgewandelt nach {}", out_path.display()),
+                                                                    out_paths => format!("{} Bilder umgewandelt:\n{}", out_paths.len(), out_paths.iter().map(|out_path| { out_path.display().to_string() }).collect::<Vec<_>>().join("\n")),
+                                                                };
+                                                                let out_path = out_paths.first().cloned();
+                                                                this.child(
+                                                                    Button::new(ElementId::Name(button_id.into()))
+                                                                        .icon(Icon::new(IconName::CircleCheck).text_color(cx.theme().success))
+                                                                        .ghost()
+                                                                        .tooltip(tooltip)
+                                                                        .on_click(move |_, _, app| { if let Some(out_path) = &out_path { app.reveal_path(out_path); } })
+                                                                )
+                                                            },
+                                                            super::state::ConversionResult::Skipped(out_path) => {
+                                                                let out_path = out_path.clone();
+                                                                this.child(
+                                                                    Button::new(ElementId::Name(button_id.into()))
+                                                                        .icon(Icon::new(IconName::Minus).text_color(cx.theme().secondary_foreground))
+                                                                        .ghost()
+                                                                        .tooltip(format!("Übersprungen, {} existiert bereits", out_path.display()))
+                                                                        .on_click(move |_, _, app| { app.reveal_path(&out_path); })
+                                                                )
+                                                            },
+                                                            super::state::ConversionResult::Failed(kind) => {
+                                                                this.child(
+                                                                    Button::new(ElementId::Name(button_id.into()))
+                                                                        .icon(Icon::new(IconName::CircleX).text_color(cx.theme().red))
+                                                                        .ghost()
+                                                                        .tooltip(super::utils::describe_error(kind))
+                                                                        .on_click(cx.listener(super::actions::handle_show_error_report_button))
+                                                                )
+                                                            },
+                                                        }
+                                                    })
+                                                    .child({
+                                                        let path = image.get().path.clone();
+                                                        Button::new(ElementId::Name(format!("UnHEIC.UI.InputArea.Button.EditTransform.{}", image.get().path.display()).into()))
+                                                            .label(if image.get().transform_override.is_some() { "Eigene Transformation" } else { "Transformieren" })
+                                                            .xsmall()
+                                                            .map(|this| { if image.get().transform_override.is_some() { this.primary() } else { this.ghost() } })
+                                                            .tooltip("Drehen, Spiegeln und Zuschneiden nur für dieses Bild festlegen")
+                                                            .on_click(cx.listener(move |this, _, _, cx| { super::actions::open_transform_editor(this, path.clone(), cx); }))
+                                                    })
                                                     .child(
                                                         div()
                                                             .h_full()
@@ -248,11 +639,20 @@ impl Render for Application {
                                 .h_full(),
                         )
                     )
+                    .when(self.state.show_error_report, |this| {
+                        this.child(self.render_error_report(cx))
+                    })
+                    .when(self.state.transform_editor.is_some(), |this| {
+                        this.child(self.render_transform_editor(cx))
+                    })
+                    .when(!self.state.collision_prompt.pending.is_empty(), |this| {
+                        this.child(self.render_collision_prompt(cx))
+                    })
             )
             .child(
                 div()
                     .w_full()
-                    .h_32()
+                    .h_64()
                     .border_t_1()
                     .border_color(cx.theme().title_bar_border)
                     .bg(cx.theme().title_bar)
@@ -294,6 +694,28 @@ impl Render for Application {
                                             )
                                     )
                             )
+                            .child(
+                                div()
+                                    .w_full()
+                                    .flex()
+                                    .items_center()
+                                    .gap_2()
+                                    .child(
+                                        Input::new(&self.state.output_folder_state.name_template_entity)
+                                            .xsmall()
+                                            .flex_grow()
+                                    )
+                                    .child(
+                                        Label::new("Wenn vorhanden")
+                                            .text_xs()
+                                            .flex_shrink_0()
+                                    )
+                                    .child(
+                                        Select::new(&self.state.output_folder_state.collision_policy_entity)
+                                            .xsmall()
+                                            .w_40()
+                                    )
+                            )
                             .child(
                                 div()
                                     .w_full()
@@ -313,22 +735,222 @@ impl Render for Application {
                             )
                             .child(
                                 div()
-                                    .h_full()
                                     .w_full()
                                     .flex()
                                     .items_center()
+                                    .gap_2()
+                                    .child(
+                                        Label::new("Größe")
+                                            .text_xs()
+                                    )
                                     .child(
-                                        Checkbox::new("UnHEIC.UI.Footer.Checkbox.Metadata")
+                                        Select::new(&self.state.conversion_settings.resize_kind_dropdown_entity)
+                                            .xsmall()
+                                            .w_40()
+                                    )
+                                    .when(self.state.conversion_settings.resize_kind != unheic_core::ResizeKind::Original, |this| {
+                                        this
+                                            .child(
+                                                Input::new(&self.state.conversion_settings.resize_value_entity)
+                                                    .xsmall()
+                                                    .w_20()
+                                            )
+                                            .when(self.state.conversion_settings.resize_kind == unheic_core::ResizeKind::FitInBox, |this| {
+                                                this
+                                                    .child(
+                                                        Label::new("×")
+                                                            .text_xs()
+                                                    )
+                                                    .child(
+                                                        Input::new(&self.state.conversion_settings.resize_height_entity)
+                                                            .xsmall()
+                                                            .w_20()
+                                                    )
+                                            })
+                                            .child(
+                                                Label::new(match self.state.conversion_settings.resize_kind {
+                                                    unheic_core::ResizeKind::Percentage => "%",
+                                                    unheic_core::ResizeKind::Megapixels => "MP",
+                                                    _ => "px",
+                                                })
+                                                    .text_xs()
+                                            )
+                                            .child(
+                                                Select::new(&self.state.conversion_settings.resize_filter_dropdown_entity)
+                                                    .xsmall()
+                                                    .flex_grow()
+                                            )
+                                    })
+                            )
+                            .child(
+                                div()
+                                    .w_full()
+                                    .flex()
+                                    .items_center()
+                                    .gap_2()
+                                    .child(
+                                        Select::new(&self.state.conversion_settings.rotation_dropdown_entity)
+                                            .xsmall()
+                                            .w_48()
+                                    )
+                                    .child(
+                                        Checkbox::new("UnHEIC.UI.Footer.Checkbox.FlipHorizontal")
+                                            .flex_shrink_0()
+                                            .label("Horizontal spiegeln")
+                                            .xsmall()
+                                            .checked(self.state.conversion_settings.transform.flip_horizontal)
+                                            .on_click(cx.listener(super::actions::handle_flip_horizontal_checkbox_change))
+                                    )
+                                    .child(
+                                        Checkbox::new("UnHEIC.UI.Footer.Checkbox.FlipVertical")
                                             .flex_shrink_0()
-                                            .label("Metadaten beibehalten")
+                                            .label("Vertikal spiegeln")
+                                            .xsmall()
+                                            .checked(self.state.conversion_settings.transform.flip_vertical)
+                                            .on_click(cx.listener(super::actions::handle_flip_vertical_checkbox_change))
+                                    )
+                            )
+                            .child(
+                                div()
+                                    .w_full()
+                                    .flex()
+                                    .items_center()
+                                    .gap_2()
+                                    .child(
+                                        Select::new(&self.state.conversion_settings.crop_dropdown_entity)
                                             .xsmall()
-                                            .checked(match self.state.conversion_settings.settings {
-                                                crate::state::ConversionSettings::JPEG(metadata, _, _) => metadata,
-                                                crate::state::ConversionSettings::PNG(metadata, _, _) => metadata,
-                                                crate::state::ConversionSettings::TIFF(metadata, _, _) => metadata,
-                                                crate::state::ConversionSettings::WebP(metadata, _, _) => metadata,
+                                            .w_48()
+                                    )
+                                    .when(self.state.conversion_settings.transform.crop != unheic_core::CropAspect::None, |this| {
+                                        this.child(
+                                            Select::new(&self.state.conversion_settings.crop_anchor_dropdown_entity)
+                                                .xsmall()
+                                                .w_56()
+                                        )
+                                    })
+                            )
+                            .child(
+                                div()
+                                    .h_full()
+                                    .w_full()
+                                    .flex()
+                                    .items_center()
+                                    .child(
+                                        div()
+                                            .flex()
+                                            .flex_col()
+                                            .flex_shrink_0()
+                                            .gap_1()
+                                            .child(
+                                                Checkbox::new("UnHEIC.UI.Footer.Checkbox.Metadata")
+                                                    .flex_shrink_0()
+                                                    .label("Metadaten beibehalten")
+                                                    .xsmall()
+                                                    .checked(match self.state.conversion_settings.settings {
+                                                        crate::state::ConversionSettings::JPEG(metadata, _, _, _, _, _) => metadata,
+                                                        crate::state::ConversionSettings::PNG(metadata, _, _, _, _, _, _) => metadata,
+                                                        crate::state::ConversionSettings::TIFF(metadata, _, _, _, _, _, _) => metadata,
+                                                        crate::state::ConversionSettings::WebP(metadata, _, _) => metadata,
+                                                        crate::state::ConversionSettings::AVIF(metadata, _, _, _, _, _, _) => metadata,
+                                                        crate::state::ConversionSettings::JXL(metadata, _, _, _, _) => metadata,
+                                                        crate::state::ConversionSettings::HEIF(metadata, _, _, _) => metadata,
+                                                    })
+                                                    .on_click(cx.listener(super::actions::handle_metadata_checkbox_change))
+                                            )
+                                            .child(
+                                                Checkbox::new("UnHEIC.UI.Footer.Checkbox.sRGB")
+                                                    .flex_shrink_0()
+                                                    .label("In sRGB umwandeln")
+                                                    .xsmall()
+                                                    .checked(self.state.conversion_settings.convert_to_srgb)
+                                                    .on_click(cx.listener(super::actions::handle_srgb_checkbox_change))
+                                            )
+
+                                            .child(
+                                                div()
+                                                    .flex()
+                                                    .items_center()
+                                                    .gap_1()
+                                                    .child(
+                                                        Checkbox::new("UnHEIC.UI.Footer.Checkbox.KeepStructure")
+                                                            .flex_shrink_0()
+                                                            .label("Ordnerstruktur beibehalten")
+                                                            .xsmall()
+                                                            .checked(self.state.output_folder_state.keep_structure)
+                                                            .on_click(cx.listener(super::actions::handle_keep_structure_checkbox_change))
+                                                    )
+                                                    .when(self.state.output_folder_state.keep_structure, |this| {
+                                                        let base = &self.state.output_folder_state.structure_base;
+                                                        this
+                                                            .child(
+                                                                Button::new("UnHEIC.UI.Footer.Button.ChooseStructureBase")
+                                                                    .icon(Icon::new(IconName::Folder))
+                                                                    .tooltip(match base {
+                                                                        Some(base) => format!("Basisordner: {}", base.display()),
+                                                                        None => "Basisordner: gemeinsamer Ordner aller Bilder".to_string(),
+                                                                    })
+                                                                    .ghost()
+                                                                    .xsmall()
+                                                                    .compact()
+                                                                    .on_click(cx.listener(super::actions::handle_choose_structure_base_button))
+                                                            )
+                                                            .when(base.is_some(), |this| {
+                                                                this.child(
+                                                                    Button::new("UnHEIC.UI.Footer.Button.ResetStructureBase")
+                                                                        .icon(Icon::new(IconName::Close))
+                                                                        .tooltip("Gemeinsamen Ordner aller Bilder verwenden")
+                                                                        .ghost()
+                                                                        .xsmall()
+                                                                        .compact()
+                                                                        .on_click(cx.listener(super::actions::handle_reset_structure_base_button))
+                                                                )
+                                                            })
+                                                    })
+                                            )
+                                    )
+                                    .child(
+                                        div()
+                                            .flex()
+                                            .flex_col()
+                                            .flex_shrink_0()
+                                            .gap_1()
+                                            .ml_4()
+                                            .child(
+                                                Checkbox::new("UnHEIC.UI.Footer.Checkbox.ExportAllImages")
+                                                    .flex_shrink_0()
+                                                    .label("Alle Bilder einer Datei exportieren")
+                                                    .xsmall()
+                                                    .checked(self.state.conversion_settings.export_all_images)
+                                                    .on_click(cx.listener(super::actions::handle_export_all_images_checkbox_change))
+                                            )
+                                            .child(
+                                                Checkbox::new("UnHEIC.UI.Footer.Checkbox.ExportDepth")
+                                                    .flex_shrink_0()
+                                                    .label("Tiefenkarten exportieren")
+                                                    .xsmall()
+                                                    .checked(self.state.conversion_settings.export_depth)
+                                                    .on_click(cx.listener(super::actions::handle_export_depth_checkbox_change))
+                                            )
+                                            .child(
+                                                Checkbox::new("UnHEIC.UI.Footer.Checkbox.ExportAuxiliary")
+                                                    .flex_shrink_0()
+                                                    .label("Hilfsbilder exportieren (Porträtmaske, HDR Gain Map)")
+                                                    .xsmall()
+                                                    .checked(self.state.conversion_settings.export_auxiliary)
+                                                    .on_click(cx.listener(super::actions::handle_export_auxiliary_checkbox_change))
+                                            )
+                                            .when(self.state.conversion_settings.export_depth || self.state.conversion_settings.export_auxiliary, |this| {
+                                                this.child(
+                                                    Select::new(&self.state.conversion_settings.auxiliary_format_dropdown_entity)
+                                                        .xsmall()
+                                                        .w_48()
+                                                )
                                             })
-                                            .on_click(cx.listener(super::actions::handle_metadata_checkbox_change))
+                                            .child(
+                                                Select::new(&self.state.conversion_settings.live_photo_video_dropdown_entity)
+                                                    .xsmall()
+                                                    .w_48()
+                                            )
                                     )
                                     .child(
                                         div()
@@ -340,8 +962,8 @@ impl Render for Application {
                                     )
                                     .child(
                                         div()
-                                            .when(self.state.conversion_settings.variant == super::state::ConversionSettingsDiscriminants::JPEG, |div| {
-                                                div
+                                            .when(self.state.conversion_settings.variant == super::state::ConversionSettingsDiscriminants::JPEG, |this| {
+                                                this
                                                 .flex_grow()
                                                 .flex()
                                                 .flex_col()
@@ -350,21 +972,44 @@ impl Render for Application {
                                                 .gap_1()
                                                 .child(
                                                     Slider::new(match &self.state.conversion_settings.settings {
-                                                        super::state::ConversionSettings::JPEG(_, _, entity) => entity,
+                                                        super::state::ConversionSettings::JPEG(_, _, _, entity, _, _) => entity,
                                                         _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
                                                     })
                                                     .horizontal()
                                                 )
                                                 .child(
                                                     Label::new(format!("Qualität ({} %)", match &self.state.conversion_settings.settings {
-                                                        super::state::ConversionSettings::JPEG(_, quality, _) => quality,
+                                                        super::state::ConversionSettings::JPEG(_, quality, _, _, _, _) => quality,
                                                         _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
                                                     }))
                                                         .text_xs()
                                                 )  
+                                                .child(
+                                                    div()
+                                                        .flex()
+                                                        .items_center()
+                                                        .gap_1()
+                                                        .child(
+                                                            Select::new(match &self.state.conversion_settings.settings {
+                                                                super::state::ConversionSettings::JPEG(_, _, _, _, entity, _) => entity,
+                                                                _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                            })
+                                                            .xsmall()
+                                                        )
+                                                        .map(|this| {
+                                                            match &self.state.conversion_settings.settings {
+                                                                super::state::ConversionSettings::JPEG(_, _, unheic_core::AlphaPolicyKind::Composite, _, _, entity) => this.child(
+                                                                    Input::new(entity)
+                                                                        .xsmall()
+                                                                        .w_24()
+                                                                ),
+                                                                _ => this,
+                                                            }
+                                                        })
+                                                )
                                             })
-                                            .when(self.state.conversion_settings.variant == super::state::ConversionSettingsDiscriminants::PNG, |div| {
-                                                div
+                                            .when(self.state.conversion_settings.variant == super::state::ConversionSettingsDiscriminants::PNG, |this| {
+                                                this
                                                 .flex_grow()
                                                 .flex()
                                                 .flex_col()
@@ -373,18 +1018,38 @@ impl Render for Application {
                                                 .gap_1()
                                                 .child(
                                                     Slider::new(match &self.state.conversion_settings.settings {
-                                                        super::state::ConversionSettings::PNG(_, _, entity) => entity,
+                                                        super::state::ConversionSettings::PNG(_, _, _, _, entity, _, _) => entity,
                                                         _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
                                                     })
                                                     .horizontal()
                                                 )
                                                 .child(
                                                     Label::new(format!("Verlustfreie Kompression ({} %)", match &self.state.conversion_settings.settings {
-                                                        super::state::ConversionSettings::PNG(_, compression, _) => compression,
+                                                        super::state::ConversionSettings::PNG(_, compression, _, _, _, _, _) => compression,
                                                         _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
                                                     }))
                                                         .text_xs()
                                                 )  
+                                                .child(
+                                                    div()
+                                                        .flex()
+                                                        .items_center()
+                                                        .gap_1()
+                                                        .child(
+                                                            Select::new(match &self.state.conversion_settings.settings {
+                                                                super::state::ConversionSettings::PNG(_, _, _, _, _, entity, _) => entity,
+                                                                _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                            })
+                                                            .xsmall()
+                                                        )
+                                                        .child(
+                                                            Select::new(match &self.state.conversion_settings.settings {
+                                                                super::state::ConversionSettings::PNG(_, _, _, _, _, _, entity) => entity,
+                                                                _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                            })
+                                                            .xsmall()
+                                                        )
+                                                )
                                             })
                                             .when(self.state.conversion_settings.variant == super::state::ConversionSettingsDiscriminants::WebP, |div| {
                                                 div
@@ -409,8 +1074,8 @@ impl Render for Application {
                                                         .text_xs()
                                                 )  
                                             })
-                                            .when(self.state.conversion_settings.variant == super::state::ConversionSettingsDiscriminants::TIFF, |div| {
-                                                div
+                                            .when(self.state.conversion_settings.variant == super::state::ConversionSettingsDiscriminants::TIFF, |this| {
+                                                this
                                                 .flex_grow()
                                                 .flex()
                                                 .flex_col()
@@ -419,7 +1084,7 @@ impl Render for Application {
                                                 .gap_1()
                                                 .child(
                                                     Select::new(match &self.state.conversion_settings.settings {
-                                                        super::state::ConversionSettings::TIFF(_, _, entity) => entity,
+                                                        super::state::ConversionSettings::TIFF(_, _, _, _, entity, _, _) => entity,
                                                         _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
                                                     })
                                                     .xsmall()
@@ -428,6 +1093,141 @@ impl Render for Application {
                                                     Label::new("Verlustfreie Kompressionsart")
                                                         .text_xs()
                                                 )  
+                                                .child(
+                                                    div()
+                                                        .flex()
+                                                        .items_center()
+                                                        .gap_1()
+                                                        .child(
+                                                            Select::new(match &self.state.conversion_settings.settings {
+                                                                super::state::ConversionSettings::TIFF(_, _, _, _, _, entity, _) => entity,
+                                                                _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                            })
+                                                            .xsmall()
+                                                        )
+                                                        .child(
+                                                            Select::new(match &self.state.conversion_settings.settings {
+                                                                super::state::ConversionSettings::TIFF(_, _, _, _, _, _, entity) => entity,
+                                                                _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                            })
+                                                            .xsmall()
+                                                        )
+                                                )
+                                            })
+                                            .when(self.state.conversion_settings.variant == super::state::ConversionSettingsDiscriminants::HEIF, |div| {
+                                                div
+                                                .flex_grow()
+                                                .flex()
+                                                .flex_col()
+                                                .justify_center()
+                                                .items_center()
+                                                .gap_1()
+                                                .child(
+                                                    Slider::new(match &self.state.conversion_settings.settings {
+                                                        super::state::ConversionSettings::HEIF(_, _, _, entity) => entity,
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    })
+                                                    .horizontal()
+                                                    .disabled(matches!(self.state.conversion_settings.settings, super::state::ConversionSettings::HEIF(_, _, true, _)))
+                                                )
+                                                .child(
+                                                    Label::new(match &self.state.conversion_settings.settings {
+                                                        super::state::ConversionSettings::HEIF(_, _, true, _) => "Qualität (verlustfrei)".to_string(),
+                                                        super::state::ConversionSettings::HEIF(_, quality, _, _) => format!("Qualität ({} %)", quality),
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    })
+                                                        .text_xs()
+                                                )
+                                                .child(
+                                                    Checkbox::new("UnHEIC.UI.Footer.Checkbox.HEIFLossless")
+                                                        .label("Verlustfrei")
+                                                        .xsmall()
+                                                        .checked(matches!(self.state.conversion_settings.settings, super::state::ConversionSettings::HEIF(_, _, true, _)))
+                                                        .on_click(cx.listener(super::actions::handle_heif_lossless_checkbox_change))
+                                                )
+                                            })
+                                            .when(self.state.conversion_settings.variant == super::state::ConversionSettingsDiscriminants::JXL, |div| {
+                                                div
+                                                .flex_grow()
+                                                .flex()
+                                                .flex_col()
+                                                .justify_center()
+                                                .items_center()
+                                                .gap_1()
+                                                .child(
+                                                    Slider::new(match &self.state.conversion_settings.settings {
+                                                        super::state::ConversionSettings::JXL(_, _, _, entity, _) => entity,
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    })
+                                                    .horizontal()
+                                                )
+                                                .child(
+                                                    Label::new(match &self.state.conversion_settings.settings {
+                                                        // The encoder can only write sRGB, other sources fail unless they are converted to sRGB
+                                                        super::state::ConversionSettings::JXL(_, 100, _, _, _) => "Qualität (100 %, verlustfrei)".to_string(),
+                                                        super::state::ConversionSettings::JXL(_, quality, _, _, _) => format!("Qualität ({} %)", quality),
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    })
+                                                        .text_xs()
+                                                )  
+                                                .child(
+                                                    Slider::new(match &self.state.conversion_settings.settings {
+                                                        super::state::ConversionSettings::JXL(_, _, _, _, entity) => entity,
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    })
+                                                    .horizontal()
+                                                )
+                                                .child(
+                                                    Label::new(format!("Aufwand ({} / 9)", match &self.state.conversion_settings.settings {
+                                                        super::state::ConversionSettings::JXL(_, _, effort, _, _) => effort,
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    }))
+                                                        .text_xs()
+                                                )  
+                                            })
+                                            .when(self.state.conversion_settings.variant == super::state::ConversionSettingsDiscriminants::AVIF, |div| {
+                                                div
+                                                .flex_grow()
+                                                .flex()
+                                                .flex_col()
+                                                .justify_center()
+                                                .items_center()
+                                                .gap_1()
+                                                .child(
+                                                    Slider::new(match &self.state.conversion_settings.settings {
+                                                        super::state::ConversionSettings::AVIF(_, _, _, _, entity, _, _) => entity,
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    })
+                                                    .horizontal()
+                                                )
+                                                .child(
+                                                    Label::new(format!("Qualität ({} %)", match &self.state.conversion_settings.settings {
+                                                        super::state::ConversionSettings::AVIF(_, quality, _, _, _, _, _) => quality,
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    }))
+                                                        .text_xs()
+                                                )  
+                                                .child(
+                                                    Slider::new(match &self.state.conversion_settings.settings {
+                                                        super::state::ConversionSettings::AVIF(_, _, _, _, _, entity, _) => entity,
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    })
+                                                    .horizontal()
+                                                )
+                                                .child(
+                                                    Label::new(format!("Geschwindigkeit ({} / 9)", match &self.state.conversion_settings.settings {
+                                                        super::state::ConversionSettings::AVIF(_, _, speed, _, _, _, _) => speed,
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    }))
+                                                        .text_xs()
+                                                )  
+                                                .child(
+                                                    Select::new(match &self.state.conversion_settings.settings {
+                                                        super::state::ConversionSettings::AVIF(_, _, _, _, _, _, entity) => entity,
+                                                        _ => unreachable!("Logic Error: Encountered different enum variant for ConversionSettings")
+                                                    })
+                                                    .xsmall()
+                                                )
                                             })
                                     )
                             )
@@ -463,50 +1263,89 @@ impl Render for Application {
                                                 super::state::ConversionProgress::Completed(_) => 100.0,
                                                 super::state::ConversionProgress::Inactive => 0.0,
                                                 super::state::ConversionProgress::InProgress(_, _, progress) => progress,
-                                                super::state::ConversionProgress::Error(_) => 100.0,
+                                                super::state::ConversionProgress::Error(_, _) => 100.0,
+                                                super::state::ConversionProgress::Cancelled(converted, total) => converted as f32 / total as f32 * 100.,
                                             })
                                             .bg(match self.state.conversion_progress {
                                                 super::state::ConversionProgress::Completed(_) => cx.theme().success,
                                                 super::state::ConversionProgress::Inactive => cx.theme().progress_bar,
                                                 super::state::ConversionProgress::InProgress(_, _, _) => cx.theme().blue,
-                                                super::state::ConversionProgress::Error(_) => cx.theme().red,
+                                                super::state::ConversionProgress::Error(_, _) => cx.theme().red,
+                                                super::state::ConversionProgress::Cancelled(_, _) => cx.theme().progress_bar,
                                             })
                                     )
                                     .when(self.state.conversion_progress != super::state::ConversionProgress::Inactive, |this| {
                                         this.child(
                                             Label::new(match &self.state.conversion_progress {
                                                 super::state::ConversionProgress::Inactive => unreachable!("Inactive conversion state has no Label!"),
+                                                super::state::ConversionProgress::InProgress(curr, total, _) if self.state.cancellation_token.is_cancelled() => format!("Wird abgebrochen, {curr} von {total} Bild(ern) umgewandelt"),
+                                                super::state::ConversionProgress::InProgress(curr, total, _) if self.state.cancellation_token.is_paused() => format!("Pausiert, {curr} von {total} Bild(ern) umgewandelt"),
                                                 super::state::ConversionProgress::InProgress(curr, total, progress) => format!("{curr} von {total} Bild(ern) umgewandelt ({progress:.1} %)"),
-                                                super::state::ConversionProgress::Error(_) => "Fehler beim Konvertieren".into(),
+                                                super::state::ConversionProgress::Error(failed, total) => format!("Fehler beim Konvertieren von {failed} von {total} Bild(ern)"),
                                                 super::state::ConversionProgress::Completed(total) => format!("Umwandlung abgeschlossen ({total} von {total}) konvertiert"),
+                                                super::state::ConversionProgress::Cancelled(converted, total) => format!("Umwandlung abgebrochen ({converted} von {total}) konvertiert"),
                                             })
                                                 .text_xs()
                                                 .text_color(cx.theme().secondary_foreground)
                                         )
                                     })
+                                    .when(!self.state.conversion_errors.is_empty(), |this| {
+                                        this.child(
+                                            Button::new("UnHEIC.UI.Footer.Button.ShowErrorReport")
+                                                .label("Details anzeigen")
+                                                .link()
+                                                .xsmall()
+                                                .on_click(cx.listener(super::actions::handle_show_error_report_button))
+                                        )
+                                    })
                             )
                             .child(
-                                Button::new("UnHEIC.UI.Footer.Button.Convert")
-                                    .custom(
-                                        ButtonCustomVariant::new(cx)
-                                            .color(cx.theme().title_bar_border.opacity(0.5))
-                                            .hover(cx.theme().title_bar_border.opacity(0.75))
-                                            .active(cx.theme().title_bar_border.opacity(1.))
+                                div()
+                                    .flex()
+                                    .items_center()
+                                    .gap_2()
+                                    .child(
+                                        Button::new("UnHEIC.UI.Footer.Button.Convert")
+                                            .custom(
+                                                ButtonCustomVariant::new(cx)
+                                                    .color(cx.theme().title_bar_border.opacity(0.5))
+                                                    .hover(cx.theme().title_bar_border.opacity(0.75))
+                                                    .active(cx.theme().title_bar_border.opacity(1.))
+                                            )
+                                            .label(match self.state.conversion_progress {
+                                                super::state::ConversionProgress::InProgress(_, _, _) => "Umwandlung läuft",
+                                                super::state::ConversionProgress::Completed(_) => "Umwandeln",
+                                                super::state::ConversionProgress::Inactive => "Umwandeln",
+                                                super::state::ConversionProgress::Error(_, _) => "Umwandeln",
+                                                super::state::ConversionProgress::Cancelled(_, _) => "Umwandeln",
+                                            })
+                                            .tooltip("Umwandlung starten")
+                                            .disabled(self.state.conversion_running)
+                                            .small()
+                                            .compact()
+                                            .on_click(cx.listener(super::actions::handle_conversion_start_button))
                                     )
-                                    .label(match self.state.conversion_progress {
-                                        super::state::ConversionProgress::InProgress(_, _, _) => "Umwandlung läuft",
-                                        super::state::ConversionProgress::Completed(_) => "Umwandeln",
-                                        super::state::ConversionProgress::Inactive => "Umwandeln",
-                                        super::state::ConversionProgress::Error(_) => "Umwandeln",
-                                    })
-                                    .tooltip("Umwandlung starten")
-                                    .disabled(match self.state.conversion_progress {
-                                        super::state::ConversionProgress::InProgress(_, _, _) => true,
-                                        _ => false,
+                                    .when(self.state.conversion_running, |this| {
+                                        this
+                                            .child(
+                                                Button::new("UnHEIC.UI.Footer.Button.Pause")
+                                                    .label(if self.state.cancellation_token.is_paused() { "Fortsetzen" } else { "Pausieren" })
+                                                    .tooltip("Umwandlung nach den laufenden Bildern anhalten oder fortsetzen")
+                                                    .disabled(self.state.cancellation_token.is_cancelled())
+                                                    .small()
+                                                    .compact()
+                                                    .on_click(cx.listener(super::actions::handle_pause_conversion_button))
+                                            )
+                                            .child(
+                                                Button::new("UnHEIC.UI.Footer.Button.Cancel")
+                                                    .label("Abbrechen")
+                                                    .tooltip("Umwandlung nach den laufenden Bildern abbrechen")
+                                                    .disabled(self.state.cancellation_token.is_cancelled())
+                                                    .small()
+                                                    .compact()
+                                                    .on_click(cx.listener(super::actions::handle_cancel_conversion_button))
+                                            )
                                     })
-                                    .small()
-                                    .compact()
-                                    .on_click(cx.listener(super::actions::handle_conversion_start_button))
                             )
                     )
             )
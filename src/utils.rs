@@ -1,10 +1,11 @@
-use std::{ffi::OsStr, path::PathBuf, sync::{Arc, LazyLock}};
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::Arc};
 
 use directories::UserDirs;
 use gpui::{AsyncApp, IntoElement, ParentElement, RenderImage, WeakEntity, prelude::FluentBuilder};
-use image::{Frame, Rgba, RgbaImage};
-use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+use image::Frame;
 use rfd::AsyncFileDialog;
+use smol::channel::Sender;
+use unheic_core::ConversionErrorKind;
 
 pub(super) trait PlatformConditional
 where
@@ -31,9 +32,9 @@ pub(super) fn user_picture_dir() -> PathBuf {
         .to_path_buf()
 }
 
-pub(super) async fn open_single_directory() -> Option<PathBuf> {
+pub(super) async fn open_single_directory(title: &str) -> Option<PathBuf> {
     AsyncFileDialog::new()
-        .set_title("Ausgabeordner auswählen")
+        .set_title(title)
         .pick_folder()
         .await
         .map(|fh| {
@@ -43,8 +44,9 @@ pub(super) async fn open_single_directory() -> Option<PathBuf> {
 
 pub(super) async fn open_multiple_files() -> Option<Vec<PathBuf>> {
     AsyncFileDialog::new()
-        .add_filter("High-Efficiency Image Codec", &["heic", "HEIC"])
-        .set_title("HEIC Dateien hinzufügen")
+        // Some platforms match the extensions case-sensitively
+        .add_filter("HEIF-Bilder (HEIC, HEIF, HIF, AVIF, AVCI)", &unheic_core::HEIF_EXTENSIONS.iter().flat_map(|extension| { [extension.to_string(), extension.to_uppercase()] }).collect::<Vec<_>>())
+        .set_title("HEIF Dateien hinzufügen")
         .pick_files()
         .await
         .map(|fhs| {
@@ -52,54 +54,168 @@ pub(super) async fn open_multiple_files() -> Option<Vec<PathBuf>> {
         })
 }
 
-static LIBHEIF: LazyLock<LibHeif> = LazyLock::new(|| { LibHeif::new() });
+pub(super) async fn open_multiple_directories() -> Option<Vec<PathBuf>> {
+    AsyncFileDialog::new()
+        .set_title("Ordner mit HEIF Dateien hinzufügen")
+        .pick_folders()
+        .await
+        .map(|fhs| {
+            fhs.iter().map(|fh| { fh.path().to_path_buf() }).collect()
+        })
+}
+
+pub(super) enum ScanMessage {
+    /// Number of files looked at since the last message
+    Progress(usize),
+    /// Images found since the last message, with their HEIF brand
+    Found(Vec<(PathBuf, String)>),
+}
+
+/// Walks the folders recursively and reports every HEIF family image inside. Runs on a background
+/// thread, the results are sent in batches so large trees show up while scanning.
+pub(super) fn scan_directories(directories: Vec<PathBuf>, max_depth: Option<usize>, include_hidden: bool, sender: Sender<ScanMessage>) {
+    const BATCH_SIZE: usize = 64;
+    
+    let mut stack = directories.into_iter().map(|directory| { (directory, 0usize) }).collect::<Vec<_>>();
+    let mut found = Vec::new();
+    let mut visited = 0usize;
+    while let Some((directory, depth)) = stack.pop() {
+        // Unreadable folders are skipped, they should not stop the rest of the import
+        let Ok(entries) = std::fs::read_dir(&directory) else { continue; };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if !include_hidden && is_hidden(&path) { continue; }
+            // Symbolic links are not followed, so links pointing upwards cannot loop forever
+            let Ok(file_type) = entry.file_type() else { continue; };
+            if file_type.is_dir() {
+                if max_depth.is_none_or(|max_depth| { depth < max_depth }) {
+                    stack.push((path, depth + 1));
+                }
+                continue;
+            }
+            if !file_type.is_file() { continue; }
+            
+            visited += 1;
+            if let Some(brand) = unheic_core::detect_heif_brand(&path) { found.push((path, brand)); }
+            // A closed channel means the window is gone
+            if found.len() >= BATCH_SIZE && sender.send_blocking(ScanMessage::Found(std::mem::take(&mut found))).is_err() { return; }
+            if visited.is_multiple_of(BATCH_SIZE) && sender.send_blocking(ScanMessage::Progress(BATCH_SIZE)).is_err() { return; }
+        }
+    }
+    let _ = sender.send_blocking(ScanMessage::Progress(visited % BATCH_SIZE));
+    let _ = sender.send_blocking(ScanMessage::Found(found));
+}
+
+fn is_hidden(path: &Path) -> bool {
+    if path.file_name().is_some_and(|name| { name.to_string_lossy().starts_with('.') }) { return true; }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if path.metadata().is_ok_and(|metadata| { metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0 }) { return true; }
+    }
+    false
+}
 
 pub(super) async fn request_thumbnail_generation(for_path: PathBuf, we: WeakEntity<super::ui::Application>, cx: &mut AsyncApp) {
     if let Some(entity) = we.upgrade() {     
-        entity.update(cx, |this, _| {
-            this.state.input_image_state.images.get_mut(&for_path).unwrap().state = super::state::ImageLoadingState::InProgress;
-        }).unwrap();
-        
-        let ctx = HeifContext::read_from_file(for_path.to_str().unwrap()).unwrap();
-        let handle = ctx.primary_image_handle().unwrap();
-        let image = LIBHEIF.decode(
-            &handle,
-            ColorSpace::Rgb(RgbChroma::Rgba),
-            None,
-        ).unwrap();
+        let _ = entity.update(cx, |this, _| {
+            if let Some(entry) = this.state.input_image_state.images.get_mut(&for_path) {
+                entry.state = super::state::ImageLoadingState::InProgress;
+            }
+        });
         
         // We assume a fixed height of 500px
-        let ratio: f32 = 500. / image.height() as f32;
-        let thumbnail = image.scale((image.width() as f32 * ratio) as u32, (image.height() as f32 * ratio) as u32, None).unwrap();
-        let plane = thumbnail.planes().interleaved.unwrap();
-        let rgba_image = RgbaImage::from_par_fn(thumbnail.width(), thumbnail.height(), |x, y| {
-            let x = x as usize;
-            let y = y as usize;
-    
-            let row_start = y * plane.stride;
-            let pixel_start = row_start + x * 4;
-    
-            Rgba([
-                plane.data[pixel_start],
-                plane.data[pixel_start + 1],
-                plane.data[pixel_start + 2],
-                plane.data[pixel_start + 3],
-            ])
-        });
-        let render_image = Arc::new(RenderImage::new([Frame::new(rgba_image)]));
+        let (state, image_count, live_photo_video) = match unheic_core::decode_thumbnail(&for_path, 500) {
+            Ok(thumbnail) => {
+                let render_image = Arc::new(RenderImage::new([Frame::new(thumbnail.image)]));
+                let live_photo_video = thumbnail.live_photo_video.map(|video| { video.path });
+                (super::state::ImageLoadingState::Done(Arc::new(move |_, _| { Some(Ok(render_image.clone())) })), thumbnail.image_count, live_photo_video)
+            },
+            Err(err) => (super::state::ImageLoadingState::Failure(err.kind), 1, None),
+        };
         
-        entity.update(cx, move |this, _| {
-            let entry = this.state.input_image_state.images.get_mut(&for_path).unwrap();
-            entry.state = super::state::ImageLoadingState::Done(Arc::new(move |_, _| { Some(Ok(render_image.clone())) }));
-        }).unwrap();
+        // The image might have been removed from the list in the meantime
+        let _ = entity.update(cx, move |this, cx| {
+            if let Some(entry) = this.state.input_image_state.images.get_mut(&for_path) {
+                entry.state = state;
+                entry.image_count = image_count;
+                entry.live_photo_video = live_photo_video;
+                cx.notify();
+            }
+        });
     }
 }
 
-pub(super) fn file_extension_for_format(format: &super::state::ConversionSettings) -> &'static OsStr {
-    match format {
-        crate::state::ConversionSettings::JPEG(_, _, _) => OsStr::new("jpg"),
-        crate::state::ConversionSettings::PNG(_, _, _) => OsStr::new("png"),
-        crate::state::ConversionSettings::TIFF(_, _, _) => OsStr::new("tiff"),
-        crate::state::ConversionSettings::WebP(_, _, _) => OsStr::new("webp"),
+/// Describes a conversion error for the user, followed by the message of the underlying error
+pub(super) fn describe_error(kind: &ConversionErrorKind) -> String {
+    let description = match kind {
+        ConversionErrorKind::IO(_) => "Datei konnte nicht gelesen werden",
+        ConversionErrorKind::Decode(_) => "Bild konnte nicht dekodiert werden",
+        ConversionErrorKind::Unsupported(_) => "Nicht unterstütztes Bild",
+        ConversionErrorKind::Encode(_) => "Bild konnte nicht kodiert werden",
+        ConversionErrorKind::Metadata(_) => "Metadaten konnten nicht verarbeitet werden",
+        ConversionErrorKind::Write(_) => "Ausgabedatei konnte nicht geschrieben werden",
+    };
+    format!("{description}: {}", kind.message())
+}
+
+/// Plain text listing of all failed files, one per line, for copying into a bug report or mail
+pub(super) fn error_report(errors: &HashMap<PathBuf, ConversionErrorKind>) -> String {
+    let mut errors = errors.iter().collect::<Vec<_>>();
+    errors.sort_by(|(a, _), (b, _)| { a.cmp(b) });
+    errors.iter().map(|(path, kind)| { format!("{}: {}\n", path.display(), describe_error(kind)) }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// A folder tree with images at every level, hidden entries and a file which is not an image
+    fn scratch_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("unheic-scan-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let image = std::fs::read(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res").join("test.heic")).unwrap();
+        for path in ["a.heic", "sub/b.heic", "sub/deep/c.heic", ".hidden.heic", ".hidden/d.heic"] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, &image).unwrap();
+        }
+        std::fs::write(root.join("notes.txt"), b"not an image").unwrap();
+        root
+    }
+    
+    /// The images found below `root`, relative to it, and the number of files looked at
+    fn scan(root: &Path, max_depth: Option<usize>, include_hidden: bool) -> (Vec<String>, usize) {
+        let (sender, receiver) = smol::channel::unbounded();
+        scan_directories(vec![root.to_path_buf()], max_depth, include_hidden, sender);
+        let (mut found, mut visited) = (Vec::new(), 0);
+        while let Ok(message) = receiver.try_recv() {
+            match message {
+                ScanMessage::Progress(count) => visited += count,
+                ScanMessage::Found(paths) => found.extend(paths.iter().map(|(path, _)| { path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/") })),
+            }
+        }
+        found.sort();
+        (found, visited)
+    }
+    
+    #[test]
+    fn scans_stop_at_the_maximum_depth() {
+        let root = scratch_tree("depth");
+        assert_eq!(scan(&root, Some(0), false).0, ["a.heic"]);
+        assert_eq!(scan(&root, Some(1), false).0, ["a.heic", "sub/b.heic"]);
+        assert_eq!(scan(&root, None, false), (vec!["a.heic".to_string(), "sub/b.heic".into(), "sub/deep/c.heic".into()], 4));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+    
+    #[test]
+    fn hidden_files_and_folders_are_filtered() {
+        let root = scratch_tree("hidden");
+        assert!(scan(&root, None, false).0.iter().all(|path| { !path.contains(".hidden") }));
+        assert_eq!(scan(&root, None, true).0, [".hidden.heic", ".hidden/d.heic", "a.heic", "sub/b.heic", "sub/deep/c.heic"]);
+        // Hidden folders count towards the depth like any other
+        assert_eq!(scan(&root, Some(0), true).0, [".hidden.heic", "a.heic"]);
+        let _ = std::fs::remove_dir_all(&root);
     }
 }
@@ -0,0 +1,137 @@
+use std::io::Cursor;
+use image::{ImageBuffer, Luma};
+use libheif_rs::{AuxiliaryImagesFilter, ColorSpace, ImageHandle};
+use tiff::encoder::{TiffEncoder, colortype::Gray16};
+use crate::error::ConversionErrorKind;
+use crate::settings::AuxiliaryFormat;
+
+pub(crate) type GrayImage16 = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// A depth map or auxiliary image belonging to an exported image
+pub(crate) struct AuxiliaryImage {
+    /// Appended to the file name of the main output, e.g. `depth` or `hdrgainmap`
+    pub(crate) suffix: String,
+    pub(crate) image: GrayImage16,
+}
+
+/// Decodes the depth maps and/or auxiliary images (portrait mattes, HDR gain maps, …) of `handle`.
+/// Alpha is left out, it is already part of the main image.
+pub(crate) fn auxiliary_images(handle: &ImageHandle, depth: bool, auxiliary: bool) -> Result<Vec<AuxiliaryImage>, ConversionErrorKind> {
+    let mut images: Vec<AuxiliaryImage> = Vec::new();
+    if depth && handle.has_depth_image() {
+        let mut item_ids = vec![0; handle.number_of_depth_images().max(0) as usize];
+        let count = handle.depth_image_ids(&mut item_ids);
+        item_ids.truncate(count);
+        for item_id in item_ids {
+            let depth_handle = handle.depth_image_handle(item_id).map_err(ConversionErrorKind::decode)?;
+            images.push(AuxiliaryImage { suffix: "depth".into(), image: decode_grayscale(&depth_handle)? });
+        }
+    }
+    if auxiliary {
+        for auxiliary_handle in handle.auxiliary_images(AuxiliaryImagesFilter::new().omit_alpha().omit_depth()) {
+            let auxiliary_type = auxiliary_handle.auxiliary_type().map_err(ConversionErrorKind::decode)?;
+            images.push(AuxiliaryImage { suffix: auxiliary_suffix(&auxiliary_type), image: decode_grayscale(&auxiliary_handle)? });
+        }
+    }
+    
+    // Several images of the same type would otherwise overwrite each other
+    for position in 1..images.len() {
+        let duplicates = images[..position].iter().filter(|image| { image.suffix == images[position].suffix }).count();
+        if duplicates > 0 {
+            images[position].suffix = format!("{}_{}", images[position].suffix, duplicates + 1);
+        }
+    }
+    Ok(images)
+}
+
+/// Short name of an auxiliary type URN, "urn:com:apple:photo:2020:aux:hdrgainmap" becomes "hdrgainmap"
+fn auxiliary_suffix(auxiliary_type: &str) -> String {
+    let name = auxiliary_type.rsplit([':', '/', '#']).next().unwrap_or_default();
+    let name = name.chars().map(|c| { if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' } }).collect::<String>();
+    if name.is_empty() { "aux".into() } else { name }
+}
+
+/// Depth maps and mattes are single-channel, they are widened to 16 bits regardless of their precision
+fn decode_grayscale(handle: &ImageHandle) -> Result<GrayImage16, ConversionErrorKind> {
+    let image = crate::LIBHEIF.decode(handle, ColorSpace::Monochrome, None).map_err(ConversionErrorKind::decode)?;
+    let plane = image.planes().y.ok_or_else(|| { ConversionErrorKind::Decode("Decoded auxiliary image has no luma plane".into()) })?;
+    let bits = plane.bits_per_pixel as u32;
+    let bytes_per_sample = if bits > 8 { 2 } else { 1 };
+    let row_len = plane.width as usize * bytes_per_sample;
+    let mut samples = Vec::with_capacity(plane.width as usize * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        samples.extend(row[..row_len].chunks_exact(bytes_per_sample).map(|sample| {
+            let value = if bytes_per_sample == 2 { u16::from_le_bytes([sample[0], sample[1]]) } else { sample[0] as u16 };
+            crate::conversion::scale_to_16_bits(value, bits)
+        }));
+    }
+    ImageBuffer::from_raw(plane.width, plane.height, samples).ok_or_else(|| { ConversionErrorKind::Decode("Decoded auxiliary image has an invalid size".into()) })
+}
+
+pub(crate) fn encode_grayscale(image: &GrayImage16, format: AuxiliaryFormat) -> Result<Vec<u8>, ConversionErrorKind> {
+    let mut out_vec = Vec::new();
+    match format {
+        AuxiliaryFormat::PNG => {
+            let mut encoder = png::Encoder::new(&mut out_vec, image.width(), image.height());
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(png::BitDepth::Sixteen);
+            // PNG stores 16-bit samples in big endian order
+            let data = image.as_raw().iter().flat_map(|sample| { sample.to_be_bytes() }).collect::<Vec<u8>>();
+            let mut writer = encoder.write_header().map_err(ConversionErrorKind::encode)?;
+            writer.write_image_data(&data).map_err(ConversionErrorKind::encode)?;
+            writer.finish().map_err(ConversionErrorKind::encode)?;
+        },
+        AuxiliaryFormat::TIFF => {
+            let mut encoder = TiffEncoder::new(Cursor::new(&mut out_vec)).map_err(ConversionErrorKind::encode)?;
+            encoder.write_image::<Gray16>(image.width(), image.height(), image.as_raw()).map_err(ConversionErrorKind::encode)?;
+        },
+    }
+    Ok(out_vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// A gradient whose samples cannot be represented in 8 bits
+    fn gradient() -> GrayImage16 {
+        ImageBuffer::from_fn(4, 2, |x, y| { Luma([0x0101 * (x as u16 * 17 + y as u16 * 68) + 0x1234 * (x % 2) as u16]) })
+    }
+    
+    #[test]
+    fn auxiliary_types_are_shortened() {
+        assert_eq!(auxiliary_suffix("urn:com:apple:photo:2020:aux:hdrgainmap"), "hdrgainmap");
+        assert_eq!(auxiliary_suffix("urn:com:apple:photo:2018:aux:portraiteffectsmatte"), "portraiteffectsmatte");
+        assert_eq!(auxiliary_suffix("urn:com:apple:photo:2019:aux:semanticskinmatte"), "semanticskinmatte");
+        assert_eq!(auxiliary_suffix("http://example.com/aux#Sky-Matte"), "sky_matte");
+        // Types without a usable last component still get a name
+        assert_eq!(auxiliary_suffix(""), "aux");
+        assert_eq!(auxiliary_suffix("urn:com:apple:"), "aux");
+    }
+    
+    #[test]
+    fn png_keeps_16_bit_grayscale() {
+        let image = gradient();
+        let data = encode_grayscale(&image, AuxiliaryFormat::PNG).unwrap();
+        
+        let mut reader = png::Decoder::new(Cursor::new(data)).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((info.color_type, info.bit_depth), (png::ColorType::Grayscale, png::BitDepth::Sixteen));
+        let samples = decoded.chunks_exact(2).map(|sample| { u16::from_be_bytes([sample[0], sample[1]]) }).collect::<Vec<u16>>();
+        assert_eq!(&samples, image.as_raw());
+    }
+    
+    #[test]
+    fn tiff_keeps_16_bit_grayscale() {
+        let image = gradient();
+        let data = encode_grayscale(&image, AuxiliaryFormat::TIFF).unwrap();
+        
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new(data)).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), tiff::ColorType::Gray(16));
+        match decoder.read_image().unwrap() {
+            tiff::decoder::DecodingResult::U16(samples) => assert_eq!(&samples, image.as_raw()),
+            _ => panic!("TIFF samples are not 16 bits"),
+        }
+    }
+}
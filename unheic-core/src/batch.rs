@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{CancellationToken, CollisionPolicy, ConversionError, ConversionOptions, ConversionOutput, Transform};
+
+/// One source file of a batch conversion
+pub struct BatchItem {
+    pub path: PathBuf,
+    /// 1-based position in the input list, for the naming template
+    pub index: usize,
+    /// Replaces the transform of the batch options for this file only
+    pub transform: Option<Transform>,
+}
+
+/// Outputs of one source file, as returned by `convert_file`
+type FileResult = Result<Vec<ConversionOutput>, ConversionError>;
+
+/// Where the outputs of a batch are written
+pub struct BatchTarget {
+    pub output_dir: PathBuf,
+    /// Recreates the folders below this directory in `output_dir`, see `mirrored_output_dir`
+    pub structure_base: Option<PathBuf>,
+}
+
+/// What happened to the files of a batch, counted per source file
+#[derive(Default)]
+pub struct BatchSummary {
+    /// Files with at least one written output
+    pub converted: usize,
+    /// Files whose outputs all existed already
+    pub skipped: usize,
+    pub failures: Vec<ConversionError>,
+    /// Set if the batch was cancelled, files not started by then are missing from the counts
+    pub cancelled: bool,
+}
+
+/// Converts every item in parallel. Workers wait at `token` before each file, so pausing and
+/// cancelling take effect between files. `ask` answers `CollisionPolicy::Ask` like in `convert_file`,
+/// `report` receives the result of each file as soon as it is done, in no particular order.
+pub fn convert_batch(
+    items: Vec<BatchItem>,
+    target: &BatchTarget,
+    options: &ConversionOptions,
+    token: &CancellationToken,
+    ask: &(dyn Fn(&Path) -> CollisionPolicy + Sync),
+    report: &(dyn Fn(&Path, &FileResult) + Sync),
+) -> BatchSummary {
+    let results = items.into_par_iter().filter_map(|item| {
+        // Files not started before a cancellation are skipped
+        if !token.checkpoint() { return None; }
+        let output_dir = match &target.structure_base {
+            // Relative inputs are resolved, like the base they are compared with
+            Some(base) => crate::mirrored_output_dir(&std::path::absolute(&item.path).unwrap_or_else(|_| { item.path.clone() }), base, &target.output_dir),
+            None => target.output_dir.clone(),
+        };
+        let item_options = item.transform.map(|transform| { ConversionOptions { transform, ..options.clone() } });
+        let result = crate::convert_file(&item.path, item.index, &output_dir, item_options.as_ref().unwrap_or(options), ask);
+        report(&item.path, &result);
+        Some(result)
+    }).collect::<Vec<_>>();
+    
+    let mut summary = BatchSummary { cancelled: token.is_cancelled(), ..BatchSummary::default() };
+    for result in results {
+        match result {
+            Ok(outputs) if outputs.iter().any(|output| { matches!(output, ConversionOutput::Written(_)) }) => summary.converted += 1,
+            Ok(_) => summary.skipped += 1,
+            Err(err) => summary.failures.push(err),
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use crate::{DEFAULT_NAME_TEMPLATE, ConversionErrorKind, OutputFormat, OutputFormatKind};
+    use super::*;
+    
+    fn options() -> ConversionOptions {
+        ConversionOptions {
+            format: OutputFormat::default_for(OutputFormatKind::PNG),
+            keep_metadata: false,
+            convert_to_srgb: false,
+            name_template: DEFAULT_NAME_TEMPLATE.to_string(),
+            collision_policy: CollisionPolicy::Skip,
+            export_all_images: false,
+            export_depth: false,
+            export_auxiliary: false,
+            auxiliary_format: crate::AuxiliaryFormat::PNG,
+            live_photo_video: crate::LivePhotoVideoAction::Ignore,
+            transform: Transform::default(),
+            resize: crate::Resize::Original,
+            resize_filter: crate::ResizeFilter::default(),
+        }
+    }
+    
+    /// Items for files which do not exist, so every conversion fails before decoding
+    fn missing_items(count: usize) -> Vec<BatchItem> {
+        let dir = std::env::temp_dir().join(format!("unheic-batch-missing-{}", std::process::id()));
+        (1..=count).map(|index| { BatchItem { path: dir.join(format!("IMG_{index:04}.heic")), index, transform: None } }).collect()
+    }
+    
+    fn target() -> BatchTarget {
+        BatchTarget { output_dir: std::env::temp_dir().join(format!("unheic-batch-output-{}", std::process::id())), structure_base: None }
+    }
+    
+    #[test]
+    fn every_file_is_reported_and_summarised() {
+        let items = missing_items(3);
+        let expected = items.iter().map(|item| { item.path.clone() }).collect::<Vec<_>>();
+        let reported = Mutex::new(Vec::new());
+        let summary = convert_batch(items, &target(), &options(), &CancellationToken::default(), &|_| { unreachable!() }, &|path, result| {
+            assert!(result.is_err());
+            reported.lock().unwrap().push(path.to_path_buf());
+        });
+        
+        let mut reported = reported.into_inner().unwrap();
+        reported.sort();
+        assert_eq!(reported, expected);
+        assert_eq!((summary.converted, summary.skipped), (0, 0));
+        assert!(!summary.cancelled);
+        let mut failed = summary.failures.iter().map(|err| { err.path.clone() }).collect::<Vec<_>>();
+        failed.sort();
+        assert_eq!(failed, expected);
+        assert!(summary.failures.iter().all(|err| { matches!(err.kind, ConversionErrorKind::IO(_)) }));
+    }
+    
+    #[test]
+    fn cancelled_batches_start_no_files() {
+        let token = CancellationToken::default();
+        token.cancel();
+        let summary = convert_batch(missing_items(3), &target(), &options(), &token, &|_| { unreachable!() }, &|path, _| {
+            panic!("{} was started after cancelling", path.display());
+        });
+        assert!(summary.cancelled);
+        assert!(summary.failures.is_empty());
+        assert_eq!((summary.converted, summary.skipped), (0, 0));
+    }
+}
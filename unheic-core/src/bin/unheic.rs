@@ -0,0 +1,7 @@
+//! Command line version of UnHEIC, which builds without the user interface.
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    unheic_core::cli::run(std::env::args())
+}
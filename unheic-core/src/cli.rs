@@ -0,0 +1,618 @@
+use std::{path::{Path, PathBuf}, process::ExitCode};
+
+use clap::{Parser, ValueEnum};
+
+use crate::{AlphaPolicy, AuxiliaryFormat, BatchItem, BatchTarget, BitDepth, CancellationToken, ChromaSubsampling, CollisionPolicy, ColorMode, ConversionError, ConversionErrorKind, ConversionOptions, ConversionOutput, CropAnchor, CropAspect, ErrorDetails, LivePhotoVideoAction, OutputFormat, OutputFormatKind, Resize, ResizeFilter, Rotation, TIFFCompression, Transform};
+
+/// Name of the subcommand which runs UnHEIC without opening a window
+pub const SUBCOMMAND: &str = "convert";
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Jpeg,
+    Png,
+    Tiff,
+    Webp,
+    Avif,
+    Jxl,
+    Heif,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OnCollision {
+    /// Append " (1)", " (2)", … to the file name
+    Suffix,
+    /// Keep the existing file and do not convert the image
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AuxiliaryFileFormat {
+    Png,
+    Tiff,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LivePhoto {
+    /// Copy the video next to the converted still
+    Copy,
+    /// Move the video next to the converted still
+    Move,
+    /// Leave the video where it is
+    Ignore,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Filter {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Lanczos,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Rotate {
+    #[value(name = "90")]
+    Clockwise90,
+    #[value(name = "180")]
+    Rotate180,
+    #[value(name = "270")]
+    Clockwise270,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Crop {
+    #[value(name = "1:1")]
+    Square,
+    #[value(name = "4:3")]
+    FourThree,
+    #[value(name = "3:2")]
+    ThreeTwo,
+    #[value(name = "16:9")]
+    SixteenNine,
+    #[value(name = "5:4")]
+    FiveFour,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Anchor {
+    Center,
+    /// Keep the top or left part
+    Start,
+    /// Keep the bottom or right part
+    End,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorType {
+    /// Smallest type which keeps every pixel
+    Auto,
+    Rgb,
+    Rgba,
+    Gray,
+    /// PNG with 8 bits per channel and at most 256 colours only
+    Palette,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Chroma {
+    /// Half the colour resolution in both directions, smallest files
+    #[value(name = "420")]
+    Yuv420,
+    #[value(name = "422")]
+    Yuv422,
+    /// Full colour resolution
+    #[value(name = "444")]
+    Yuv444,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Alpha {
+    /// Blend onto the --background colour
+    Composite,
+    /// Multiply the colour by the alpha, which equals a black background
+    Premultiply,
+    /// Fail transparent images instead of converting them
+    Warn,
+}
+
+/// Converts HEIC images without starting the user interface
+#[derive(Parser)]
+#[command(name = "UnHEIC", version)]
+struct Arguments {
+    /// Input files or glob patterns (e.g. "Fotos/*.heic")
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Jpeg)]
+    format: Format,
+    /// Quality for JPEG, WebP, AVIF, JPEG XL and HEIF (0-100, 100 is lossless for WebP and JPEG XL)
+    #[arg(short, long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    quality: Option<u8>,
+    /// Encode HEIF losslessly instead of with --quality
+    #[arg(long)]
+    lossless: bool,
+    /// Encoder speed for AVIF (0-9, lower is slower but smaller)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=9))]
+    speed: Option<u8>,
+    /// Chroma subsampling for AVIF
+    #[arg(long, value_enum)]
+    chroma: Option<Chroma>,
+    /// Encoder effort for JPEG XL (1-9, higher is slower but smaller)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=9))]
+    effort: Option<u8>,
+    /// Compression for PNG (0-100) or TIFF (none, lzw, deflate)
+    #[arg(short, long)]
+    compression: Option<String>,
+    /// Bits per channel for PNG and TIFF (8 or 16)
+    #[arg(short, long, value_parser = ["8", "16"])]
+    bit_depth: Option<String>,
+    /// What happens to transparent images in JPEG, which cannot store transparency
+    #[arg(long, value_enum)]
+    alpha: Option<Alpha>,
+    /// Background colour for --alpha composite as hex triplet (e.g. "#FFFFFF")
+    #[arg(long)]
+    background: Option<String>,
+    /// Colour type for PNG and TIFF
+    #[arg(long, value_enum)]
+    color_type: Option<ColorType>,
+    /// Directory the converted images are written to
+    #[arg(short, long, default_value = ".")]
+    output_dir: PathBuf,
+    /// Output file name template, supports {name}, {index}, {date}, {camera}, {format}, {width} and {height}
+    #[arg(short, long, default_value = crate::DEFAULT_NAME_TEMPLATE)]
+    name: String,
+    /// What to do if an output file already exists
+    #[arg(long, value_enum, default_value_t = OnCollision::Suffix)]
+    on_collision: OnCollision,
+    /// Rotate clockwise by this many degrees
+    #[arg(long, value_enum)]
+    rotate: Option<Rotate>,
+    /// Mirror left and right, after rotating
+    #[arg(long)]
+    flip_horizontal: bool,
+    /// Mirror top and bottom, after rotating
+    #[arg(long)]
+    flip_vertical: bool,
+    /// Crop to an aspect ratio, which follows the orientation of each image (4:3 becomes 3:4 for portraits)
+    #[arg(long, value_enum)]
+    crop: Option<Crop>,
+    /// Part of the image kept when cropping
+    #[arg(long, value_enum, default_value_t = Anchor::Center)]
+    crop_anchor: Anchor,
+    /// Scale images down: "2048" (longest edge), "1920x1080" (fit into box), "50%" or "12mp" (megapixel cap)
+    #[arg(short, long)]
+    resize: Option<String>,
+    /// Resampling filter used for --resize
+    #[arg(long, value_enum, default_value_t = Filter::Lanczos)]
+    resize_filter: Filter,
+    /// Keep EXIF and XMP metadata
+    #[arg(short = 'm', long)]
+    keep_metadata: bool,
+    /// Transform the pixels into sRGB
+    #[arg(long)]
+    srgb: bool,
+    /// Export every image of multi-image files (bursts, brackets), numbered with _1, _2, …
+    #[arg(short = 'a', long)]
+    all_images: bool,
+    /// Write depth maps next to the output as 16-bit grayscale images
+    #[arg(long)]
+    depth: bool,
+    /// Write auxiliary images (portrait mattes, HDR gain maps) next to the output as 16-bit grayscale images
+    #[arg(long)]
+    auxiliary: bool,
+    /// File format of depth maps and auxiliary images
+    #[arg(long, value_enum, default_value_t = AuxiliaryFileFormat::Png)]
+    auxiliary_format: AuxiliaryFileFormat,
+    /// What to do with the video of a Live Photo (IMG_1234.MOV next to IMG_1234.HEIC)
+    #[arg(long, value_enum, default_value_t = LivePhoto::Copy)]
+    live_photo: LivePhoto,
+    /// Recreate the folders of the inputs, relative to their common folder, below the output directory
+    #[arg(short = 's', long)]
+    keep_structure: bool,
+    /// Folder the structure is taken relative to instead of the common folder, implies --keep-structure
+    #[arg(long)]
+    structure_base: Option<PathBuf>,
+}
+
+/// Runs a headless conversion. `args` starts with the program name shown in the usage.
+pub fn run(args: impl Iterator<Item = String>) -> ExitCode {
+    let arguments = Arguments::parse_from(args);
+    
+    let options = match conversion_options(&arguments) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::from(2);
+        }
+    };
+    
+    let mut failures = Vec::new();
+    let mut input_paths = Vec::new();
+    for input in &arguments.inputs {
+        match expand_input(input) {
+            Ok(paths) => input_paths.extend(paths),
+            Err(kind) => failures.push(ConversionError { path: PathBuf::from(input), kind }),
+        }
+    }
+    
+    // Relative inputs are resolved, otherwise inputs from the working directory would have no common folder
+    let absolute = |path: &Path| { std::path::absolute(path).unwrap_or_else(|_| { path.to_path_buf() }) };
+    let structure_base = match &arguments.structure_base {
+        Some(base) => Some(absolute(base)),
+        None if arguments.keep_structure => {
+            let absolute_paths = input_paths.iter().map(|path| { absolute(path) }).collect::<Vec<_>>();
+            crate::common_root(absolute_paths.iter().map(|path| { path.as_path() }))
+        },
+        None => None,
+    };
+    
+    let items = input_paths.into_iter().enumerate().map(|(index, path)| { BatchItem { path, index: index + 1, transform: None } }).collect();
+    let target = BatchTarget { output_dir: arguments.output_dir.clone(), structure_base };
+    // There is nobody to ask, the policy never is CollisionPolicy::Ask
+    let summary = crate::convert_batch(items, &target, &options, &CancellationToken::default(), &|_| { CollisionPolicy::Skip }, &|path, result| {
+        let Ok(outputs) = result else { return; };
+        for output in outputs {
+            match output {
+                ConversionOutput::Written(out_path) => println!("{} -> {}", path.display(), out_path.display()),
+                ConversionOutput::Skipped(out_path) => println!("{} -> {} (exists, skipped)", path.display(), out_path.display()),
+            }
+        }
+    });
+    failures.extend(summary.failures);
+    let (converted, skipped) = (summary.converted, summary.skipped);
+    
+    if failures.is_empty() {
+        println!("{converted} file(s) converted, {skipped} skipped");
+        return ExitCode::SUCCESS;
+    }
+    
+    eprintln!("{converted} file(s) converted, {skipped} skipped, {} failed:", failures.len());
+    for err in &failures {
+        eprintln!("  {err}");
+    }
+    ExitCode::FAILURE
+}
+
+fn conversion_options(arguments: &Arguments) -> Result<ConversionOptions, String> {
+    let kind = match arguments.format {
+        Format::Jpeg => OutputFormatKind::JPEG,
+        Format::Png => OutputFormatKind::PNG,
+        Format::Tiff => OutputFormatKind::TIFF,
+        Format::Webp => OutputFormatKind::WebP,
+        Format::Avif => OutputFormatKind::AVIF,
+        Format::Jxl => OutputFormatKind::JXL,
+        Format::Heif => OutputFormatKind::HEIF,
+    };
+    
+    let bit_depth = match arguments.bit_depth.as_deref() {
+        None => None,
+        Some("16") => Some(BitDepth::Sixteen),
+        Some(_) => Some(BitDepth::Eight),
+    };
+    
+    let mut format = OutputFormat::default_for(kind);
+    match &mut format {
+        OutputFormat::JPEG(quality, _) |
+        OutputFormat::WebP(quality) |
+        OutputFormat::AVIF(quality, _, _) |
+        OutputFormat::JXL(quality, _) |
+        OutputFormat::HEIF(quality, _) => {
+            if arguments.compression.is_some() { return Err("--compression is only supported for PNG and TIFF".into()); }
+            if bit_depth.is_some() { return Err("--bit-depth is only supported for PNG and TIFF".into()); }
+            if arguments.color_type.is_some() { return Err("--color-type is only supported for PNG and TIFF".into()); }
+            *quality = arguments.quality.unwrap_or(*quality);
+        },
+        OutputFormat::PNG(compression, depth, color_mode) => {
+            if arguments.quality.is_some() { return Err("--quality is not supported for PNG, use --compression".into()); }
+            if let Some(value) = &arguments.compression {
+                *compression = value.parse::<u8>().ok().filter(|value| { *value <= 100 }).ok_or(format!("Invalid PNG compression '{value}', expected 0-100"))?;
+            }
+            *depth = bit_depth.unwrap_or(*depth);
+            *color_mode = color_mode_for(arguments.color_type);
+            if *color_mode == ColorMode::Palette && *depth == BitDepth::Sixteen { return Err("--color-type palette requires --bit-depth 8".into()); }
+        },
+        OutputFormat::TIFF(compression, depth, color_mode) => {
+            if arguments.quality.is_some() { return Err("--quality is not supported for TIFF, use --compression".into()); }
+            if let Some(value) = &arguments.compression {
+                *compression = match value.to_lowercase().as_str() {
+                    "none" => TIFFCompression::None,
+                    "lzw" => TIFFCompression::LZW,
+                    "deflate" => TIFFCompression::Deflate,
+                    _ => return Err(format!("Invalid TIFF compression '{value}', expected none, lzw or deflate")),
+                };
+            }
+            *depth = bit_depth.unwrap_or(*depth);
+            *color_mode = color_mode_for(arguments.color_type);
+            if *color_mode == ColorMode::Palette { return Err("--color-type palette is only supported for PNG".into()); }
+        },
+    }
+    
+    match &mut format {
+        OutputFormat::AVIF(_, speed, chroma) => {
+            if arguments.effort.is_some() { return Err("--effort is only supported for JPEG XL".into()); }
+            *speed = arguments.speed.unwrap_or(*speed);
+            *chroma = match arguments.chroma {
+                None => *chroma,
+                Some(Chroma::Yuv420) => ChromaSubsampling::Yuv420,
+                Some(Chroma::Yuv422) => ChromaSubsampling::Yuv422,
+                Some(Chroma::Yuv444) => ChromaSubsampling::Yuv444,
+            };
+        },
+        OutputFormat::JXL(_, effort) => {
+            if arguments.speed.is_some() || arguments.chroma.is_some() { return Err("--speed and --chroma are only supported for AVIF".into()); }
+            *effort = arguments.effort.unwrap_or(*effort);
+        },
+        _ => {
+            if arguments.speed.is_some() || arguments.chroma.is_some() { return Err("--speed and --chroma are only supported for AVIF".into()); }
+            if arguments.effort.is_some() { return Err("--effort is only supported for JPEG XL".into()); }
+        },
+    }
+    
+    match &mut format {
+        OutputFormat::HEIF(_, lossless) => {
+            if arguments.lossless && arguments.quality.is_some() { return Err("--quality cannot be combined with --lossless".into()); }
+            *lossless = arguments.lossless;
+        },
+        _ => {
+            if arguments.lossless { return Err("--lossless is only supported for HEIF, use --quality 100 for lossless WebP and JPEG XL".into()); }
+        },
+    }
+    
+    match &mut format {
+        OutputFormat::JPEG(_, alpha_policy) => {
+            *alpha_policy = match (arguments.alpha, &arguments.background) {
+                (None | Some(Alpha::Composite), Some(value)) => AlphaPolicy::Composite(AlphaPolicy::parse_background(value).ok_or(format!("Invalid background colour '{value}', expected e.g. #FFFFFF"))?),
+                (None | Some(Alpha::Composite), None) => AlphaPolicy::default(),
+                (Some(_), Some(_)) => return Err("--background is only supported with --alpha composite".into()),
+                (Some(Alpha::Premultiply), None) => AlphaPolicy::Premultiply,
+                (Some(Alpha::Warn), None) => AlphaPolicy::Warn,
+            };
+        },
+        _ => {
+            if arguments.alpha.is_some() || arguments.background.is_some() { return Err("--alpha and --background are only supported for JPEG".into()); }
+        },
+    }
+    
+    let collision_policy = match arguments.on_collision {
+        OnCollision::Suffix => CollisionPolicy::AutoSuffix,
+        OnCollision::Skip => CollisionPolicy::Skip,
+        OnCollision::Overwrite => CollisionPolicy::Overwrite,
+    };
+    
+    let resize = match &arguments.resize {
+        Some(spec) => parse_resize(spec).ok_or(format!("Invalid resize '{spec}', expected e.g. 2048, 1920x1080, 50% or 12mp"))?,
+        None => Resize::Original,
+    };
+    
+    Ok(ConversionOptions {
+        format,
+        keep_metadata: arguments.keep_metadata,
+        convert_to_srgb: arguments.srgb,
+        name_template: arguments.name.clone(),
+        collision_policy,
+        export_all_images: arguments.all_images,
+        export_depth: arguments.depth,
+        export_auxiliary: arguments.auxiliary,
+        auxiliary_format: match arguments.auxiliary_format {
+            AuxiliaryFileFormat::Png => AuxiliaryFormat::PNG,
+            AuxiliaryFileFormat::Tiff => AuxiliaryFormat::TIFF,
+        },
+        live_photo_video: match arguments.live_photo {
+            LivePhoto::Copy => LivePhotoVideoAction::Copy,
+            LivePhoto::Move => LivePhotoVideoAction::Move,
+            LivePhoto::Ignore => LivePhotoVideoAction::Ignore,
+        },
+        transform: Transform {
+            rotation: match arguments.rotate {
+                None => Rotation::None,
+                Some(Rotate::Clockwise90) => Rotation::Clockwise90,
+                Some(Rotate::Rotate180) => Rotation::Rotate180,
+                Some(Rotate::Clockwise270) => Rotation::Clockwise270,
+            },
+            flip_horizontal: arguments.flip_horizontal,
+            flip_vertical: arguments.flip_vertical,
+            crop: match arguments.crop {
+                None => CropAspect::None,
+                Some(Crop::Square) => CropAspect::Square,
+                Some(Crop::FourThree) => CropAspect::FourThree,
+                Some(Crop::ThreeTwo) => CropAspect::ThreeTwo,
+                Some(Crop::SixteenNine) => CropAspect::SixteenNine,
+                Some(Crop::FiveFour) => CropAspect::FiveFour,
+            },
+            crop_anchor: match arguments.crop_anchor {
+                Anchor::Center => CropAnchor::Center,
+                Anchor::Start => CropAnchor::Start,
+                Anchor::End => CropAnchor::End,
+            },
+        },
+        resize,
+        resize_filter: match arguments.resize_filter {
+            Filter::Nearest => ResizeFilter::Nearest,
+            Filter::Bilinear => ResizeFilter::Bilinear,
+            Filter::Bicubic => ResizeFilter::Bicubic,
+            Filter::Lanczos => ResizeFilter::Lanczos,
+        },
+    })
+}
+
+fn color_mode_for(color_type: Option<ColorType>) -> ColorMode {
+    match color_type {
+        None | Some(ColorType::Auto) => ColorMode::Auto,
+        Some(ColorType::Rgb) => ColorMode::Rgb,
+        Some(ColorType::Rgba) => ColorMode::Rgba,
+        Some(ColorType::Gray) => ColorMode::Grayscale,
+        Some(ColorType::Palette) => ColorMode::Palette,
+    }
+}
+
+/// Parses the --resize value, all sizes have to be positive
+fn parse_resize(spec: &str) -> Option<Resize> {
+    let spec = spec.trim().to_lowercase();
+    let resize = if let Some(percentage) = spec.strip_suffix('%') {
+        Resize::Percentage(percentage.trim().parse::<u8>().ok().filter(|percentage| { (1..=100).contains(percentage) })?)
+    } else if let Some(megapixels) = spec.strip_suffix("mp") {
+        Resize::Megapixels(megapixels.trim().parse::<f32>().ok().filter(|megapixels| { *megapixels > 0. })?)
+    } else if let Some((width, height)) = spec.split_once('x') {
+        Resize::FitInBox(width.trim().parse::<u32>().ok().filter(|width| { *width > 0 })?, height.trim().parse::<u32>().ok().filter(|height| { *height > 0 })?)
+    } else {
+        Resize::LongestEdge(spec.parse::<u32>().ok().filter(|edge| { *edge > 0 })?)
+    };
+    Some(resize)
+}
+
+/// Existing paths are taken as they are, everything else is treated as a glob pattern
+fn expand_input(input: &str) -> Result<Vec<PathBuf>, ConversionErrorKind> {
+    let path = PathBuf::from(input);
+    if path.is_file() { return Ok(vec![path]); }
+    
+    let paths = glob::glob(input)
+        .map_err(|err| { ConversionErrorKind::IO(ErrorDetails::caused_by(err)) })?
+        .filter_map(Result::ok)
+        .filter(|path| { path.is_file() })
+        .collect::<Vec<_>>();
+    if paths.is_empty() { return Err(ConversionErrorKind::IO("No files match this input".into())); }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn options(args: &[&str]) -> Result<ConversionOptions, String> {
+        let arguments = Arguments::try_parse_from([SUBCOMMAND, "IMG_0001.heic"].iter().chain(args)).map_err(|err| { err.to_string() })?;
+        conversion_options(&arguments)
+    }
+    
+    #[test]
+    fn quality_is_applied_to_lossy_formats() {
+        assert!(matches!(options(&["-f", "webp", "-q", "55"]).unwrap().format, OutputFormat::WebP(55)));
+        assert!(matches!(options(&["-f", "avif", "-q", "40"]).unwrap().format, OutputFormat::AVIF(40, _, _)));
+        // Without a quality the format keeps its default
+        assert!(matches!(options(&["-f", "jpeg"]).unwrap().format, OutputFormat::JPEG(90, AlphaPolicy::Composite([255, 255, 255]))));
+    }
+    
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        assert!(options(&["-f", "jpeg", "-q", "101"]).is_err());
+        assert!(options(&["-f", "png", "-c", "101"]).is_err());
+        assert!(options(&["-f", "png", "-b", "12"]).is_err());
+        assert!(options(&["-f", "tiff", "-c", "zip"]).is_err());
+    }
+    
+    #[test]
+    fn lossless_formats_take_compression_and_bit_depth() {
+        assert!(matches!(options(&["-f", "png", "-c", "30", "-b", "16"]).unwrap().format, OutputFormat::PNG(30, BitDepth::Sixteen, ColorMode::Auto)));
+        assert!(matches!(options(&["-f", "tiff", "-c", "LZW"]).unwrap().format, OutputFormat::TIFF(TIFFCompression::LZW, BitDepth::Eight, ColorMode::Auto)));
+    }
+    
+    #[test]
+    fn color_types_are_checked_per_format() {
+        assert!(matches!(options(&["-f", "png", "--color-type", "palette"]).unwrap().format, OutputFormat::PNG(_, _, ColorMode::Palette)));
+        assert!(matches!(options(&["-f", "tiff", "--color-type", "gray"]).unwrap().format, OutputFormat::TIFF(_, _, ColorMode::Grayscale)));
+        assert!(options(&["-f", "png", "--color-type", "palette", "-b", "16"]).is_err_and(|err| { err.contains("--bit-depth 8") }));
+        assert!(options(&["-f", "tiff", "--color-type", "palette"]).is_err_and(|err| { err.contains("only supported for PNG") }));
+        assert!(options(&["-f", "jpeg", "--color-type", "rgb"]).is_err());
+    }
+    
+    #[test]
+    fn options_of_other_formats_are_rejected() {
+        assert!(options(&["-f", "png", "-q", "50"]).is_err());
+        assert!(options(&["-f", "tiff", "-q", "50"]).is_err());
+        assert!(options(&["-f", "jpeg", "-c", "50"]).is_err());
+        assert!(options(&["-f", "heif", "-b", "16"]).is_err());
+    }
+    
+    #[test]
+    fn transparency_options_apply_to_jpeg() {
+        assert!(matches!(options(&["-f", "jpeg", "--alpha", "premultiply"]).unwrap().format, OutputFormat::JPEG(_, AlphaPolicy::Premultiply)));
+        assert!(matches!(options(&["-f", "jpeg", "--background", "#000000"]).unwrap().format, OutputFormat::JPEG(_, AlphaPolicy::Composite([0, 0, 0]))));
+        assert!(options(&["-f", "jpeg", "--background", "#00000G"]).is_err_and(|err| { err.contains("Invalid background") }));
+        assert!(options(&["-f", "jpeg", "--alpha", "warn", "--background", "#000000"]).is_err());
+        assert!(options(&["-f", "png", "--alpha", "warn"]).is_err());
+    }
+    
+    #[test]
+    fn encoder_settings_apply_to_avif_and_jxl() {
+        assert!(matches!(options(&["-f", "avif", "--speed", "2", "--chroma", "444"]).unwrap().format, OutputFormat::AVIF(70, 2, ChromaSubsampling::Yuv444)));
+        assert!(matches!(options(&["-f", "avif", "--chroma", "422"]).unwrap().format, OutputFormat::AVIF(_, 6, ChromaSubsampling::Yuv422)));
+        assert!(matches!(options(&["-f", "jxl", "-q", "80", "--effort", "3"]).unwrap().format, OutputFormat::JXL(80, 3)));
+        // Without the flags the formats keep their defaults
+        assert!(matches!(options(&["-f", "avif"]).unwrap().format, OutputFormat::AVIF(70, 6, ChromaSubsampling::Yuv420)));
+        assert!(matches!(options(&["-f", "jxl"]).unwrap().format, OutputFormat::JXL(90, 7)));
+    }
+    
+    #[test]
+    fn encoder_settings_are_checked_per_format() {
+        assert!(options(&["-f", "avif", "--speed", "10"]).is_err());
+        assert!(options(&["-f", "avif", "--chroma", "411"]).is_err());
+        assert!(options(&["-f", "jxl", "--effort", "0"]).is_err());
+        assert!(options(&["-f", "jxl", "--effort", "10"]).is_err());
+        assert!(options(&["-f", "avif", "--effort", "5"]).is_err_and(|err| { err.contains("only supported for JPEG XL") }));
+        assert!(options(&["-f", "jxl", "--speed", "5"]).is_err_and(|err| { err.contains("only supported for AVIF") }));
+        assert!(options(&["-f", "webp", "--chroma", "444"]).is_err_and(|err| { err.contains("only supported for AVIF") }));
+        assert!(options(&["-f", "png", "--effort", "5"]).is_err_and(|err| { err.contains("only supported for JPEG XL") }));
+    }
+    
+    #[test]
+    fn heif_is_lossless_on_request_only() {
+        assert!(matches!(options(&["-f", "heif", "--lossless"]).unwrap().format, OutputFormat::HEIF(_, true)));
+        // Quality 100 stays a lossy setting
+        assert!(matches!(options(&["-f", "heif", "-q", "100"]).unwrap().format, OutputFormat::HEIF(100, false)));
+        assert!(matches!(options(&["-f", "heif"]).unwrap().format, OutputFormat::HEIF(60, false)));
+        assert!(options(&["-f", "heif", "--lossless", "-q", "80"]).is_err_and(|err| { err.contains("--lossless") }));
+        assert!(options(&["-f", "avif", "--lossless"]).is_err_and(|err| { err.contains("only supported for HEIF") }));
+        assert!(options(&["-f", "png", "--lossless"]).is_err());
+    }
+    
+    #[test]
+    fn structure_and_collision_options_are_parsed() {
+        let options = options(&["--on-collision", "skip", "--live-photo", "ignore", "--auxiliary-format", "tiff", "-a", "--depth"]).unwrap();
+        assert!(options.collision_policy == CollisionPolicy::Skip);
+        assert!(options.live_photo_video == LivePhotoVideoAction::Ignore);
+        assert!(matches!(options.auxiliary_format, AuxiliaryFormat::TIFF));
+        assert!(options.export_all_images && options.export_depth && !options.export_auxiliary);
+        assert!(Arguments::try_parse_from([SUBCOMMAND]).is_err(), "Inputs are required");
+        assert!(Arguments::try_parse_from([SUBCOMMAND, "IMG_0001.heic", "--on-collision", "ask"]).is_err(), "Nobody can be asked");
+    }
+    
+    #[test]
+    fn transforms_are_parsed() {
+        let transform = options(&["--rotate", "270", "--flip-horizontal", "--crop", "16:9", "--crop-anchor", "end"]).unwrap().transform;
+        assert!(matches!(transform.rotation, Rotation::Clockwise270));
+        assert!(transform.flip_horizontal && !transform.flip_vertical);
+        assert!(matches!(transform.crop, CropAspect::SixteenNine));
+        assert!(matches!(transform.crop_anchor, CropAnchor::End));
+        assert!(options(&["--rotate", "45"]).is_err());
+        assert!(options(&["--crop", "2:1"]).is_err());
+    }
+    
+    #[test]
+    fn flags_are_passed_through() {
+        let options = options(&["-m", "--srgb"]).unwrap();
+        assert!(options.keep_metadata);
+        assert!(options.convert_to_srgb);
+    }
+    
+    #[test]
+    fn resize_values_are_parsed() {
+        assert_eq!(parse_resize("2048"), Some(Resize::LongestEdge(2048)));
+        assert_eq!(parse_resize("1920x1080"), Some(Resize::FitInBox(1920, 1080)));
+        assert_eq!(parse_resize(" 1920 X 1080 "), Some(Resize::FitInBox(1920, 1080)));
+        assert_eq!(parse_resize("50%"), Some(Resize::Percentage(50)));
+        assert_eq!(parse_resize("100 %"), Some(Resize::Percentage(100)));
+        assert_eq!(parse_resize("12mp"), Some(Resize::Megapixels(12.)));
+        assert_eq!(parse_resize("0.5 MP"), Some(Resize::Megapixels(0.5)));
+    }
+    
+    #[test]
+    fn invalid_resize_values_are_rejected() {
+        for spec in ["", "abc", "0", "-5", "1.5", "0x1080", "1920x0", "1920x", "x1080", "1920x1080x2", "0%", "101%", "-1%", "%", "0mp", "-1mp", "nanmp", "mp"] {
+            assert_eq!(parse_resize(spec), None, "{spec}");
+        }
+        assert!(options(&["--resize", "50"]).is_ok());
+        assert!(options(&["--resize", "0"]).is_err_and(|err| { err.contains("Invalid resize") }));
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::{Arc, Condvar, Mutex, atomic::{AtomicBool, Ordering}};
+
+/// Lets a front end cancel or pause a batch conversion. The workers check the token
+/// between files, so the file currently being converted is always finished first.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<ControlState>);
+
+#[derive(Default)]
+struct ControlState {
+    cancelled: AtomicBool,
+    paused: Mutex<bool>,
+    resumed: Condvar,
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        // Holding the lock makes sure no worker is between its check and starting to wait
+        let _paused = self.0.paused.lock();
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        // Wake paused workers so they notice the cancellation
+        self.0.resumed.notify_all();
+    }
+    
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+    
+    pub fn pause(&self) {
+        if let Ok(mut paused) = self.0.paused.lock() { *paused = true; }
+    }
+    
+    pub fn resume(&self) {
+        if let Ok(mut paused) = self.0.paused.lock() { *paused = false; }
+        self.0.resumed.notify_all();
+    }
+    
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.lock().map(|paused| { *paused }).unwrap_or(false)
+    }
+    
+    /// Called by a worker before it starts on the next file. Blocks while the conversion
+    /// is paused and returns whether the worker may continue.
+    pub fn checkpoint(&self) -> bool {
+        let Ok(mut paused) = self.0.paused.lock() else { return !self.is_cancelled(); };
+        while *paused && !self.is_cancelled() {
+            paused = match self.0.resumed.wait(paused) {
+                Ok(paused) => paused,
+                Err(_) => break,
+            };
+        }
+        !self.is_cancelled()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread, time::Duration};
+    use super::*;
+    
+    /// Runs a checkpoint on another thread, its result arrives on the returned channel
+    fn spawn_checkpoint(token: &CancellationToken) -> mpsc::Receiver<bool> {
+        let (sender, receiver) = mpsc::channel();
+        let token = token.clone();
+        thread::spawn(move || { sender.send(token.checkpoint()).unwrap(); });
+        receiver
+    }
+    
+    #[test]
+    fn checkpoint_passes_while_running() {
+        let token = CancellationToken::default();
+        assert!(!token.is_paused());
+        assert!(token.checkpoint());
+    }
+    
+    #[test]
+    fn paused_workers_continue_after_resume() {
+        let token = CancellationToken::default();
+        token.pause();
+        assert!(token.is_paused());
+        let result = spawn_checkpoint(&token);
+        assert!(result.recv_timeout(Duration::from_millis(100)).is_err(), "checkpoint returned while paused");
+        
+        token.resume();
+        assert_eq!(result.recv_timeout(Duration::from_secs(5)), Ok(true));
+        assert!(!token.is_paused());
+    }
+    
+    #[test]
+    fn cancel_wakes_paused_workers() {
+        let token = CancellationToken::default();
+        token.pause();
+        let results = [spawn_checkpoint(&token), spawn_checkpoint(&token)];
+        thread::sleep(Duration::from_millis(50));
+        
+        token.cancel();
+        for result in results {
+            assert_eq!(result.recv_timeout(Duration::from_secs(5)), Ok(false));
+        }
+        assert!(token.is_cancelled());
+        // Files not started yet are skipped from now on
+        assert!(!token.checkpoint());
+    }
+    
+    #[test]
+    fn clones_share_the_state() {
+        let token = CancellationToken::default();
+        let worker_token = token.clone();
+        token.pause();
+        assert!(worker_token.is_paused());
+        token.cancel();
+        assert!(worker_token.is_cancelled());
+        // Resuming does not undo a cancellation
+        token.resume();
+        assert!(!worker_token.checkpoint());
+    }
+}
@@ -0,0 +1,1101 @@
+use std::{borrow::Cow, collections::HashMap, io::{Cursor, Seek, Write}, path::{Path, PathBuf}};
+use image::{DynamicImage, EncodableLayout, ImageBuffer, Pixel, Primitive, Rgb, RgbImage};
+use libheif_rs::{Channel, ColorProfileRaw, ColorSpace, CompressionFormat, EncoderParameterValue, EncoderQuality, HeifContext, Image, ImageHandle, RgbChroma, color_profile_types};
+use tiff::{encoder::{TiffEncoder, TiffValue, colortype::{ColorType, Gray8, Gray16, RGB8, RGB16, RGBA8, RGBA16}}, tags::Tag};
+use crate::auxiliary::GrayImage16;
+use crate::error::{ConversionError, ConversionErrorKind, ErrorDetails};
+use crate::live_photo::LivePhotoVideo;
+use crate::metadata::ImageMetadata;
+use crate::naming::{NameValues, ReservedOutput};
+use crate::settings::{AlphaPolicy, AuxiliaryFormat, BitDepth, ChromaSubsampling, CollisionPolicy, ColorMode, ConversionOptions, LivePhotoVideoAction, OutputFormat, Resize, Rotation, TIFFCompression, Transform};
+
+/// Decodes the primary image. Sources with more than 8 bits per channel (10/12-bit HEICs)
+/// are decoded into a 16-bit buffer so lossless outputs can keep the full precision.
+pub fn decode_image(path: &Path) -> Result<(DynamicImage, ImageMetadata), ConversionError> {
+    let data = std::fs::read(path).map_err(|err| { ConversionErrorKind::io(err).at(path) })?;
+    let ctx = HeifContext::read_from_bytes(&data).map_err(|err| { ConversionErrorKind::decode(err).at(path) })?;
+    let handle = ctx.primary_image_handle().map_err(|err| { ConversionErrorKind::decode(err).at(path) })?;
+    decode_handle(&handle).map_err(|kind| { kind.at(path) })
+}
+
+/// Handles of all top-level images in the container, e.g. every shot of a burst, in file order
+pub(crate) fn top_level_image_handles(ctx: &HeifContext) -> Result<Vec<ImageHandle>, ConversionErrorKind> {
+    let mut item_ids = vec![0; ctx.number_of_top_level_images()];
+    let count = ctx.top_level_image_ids(&mut item_ids);
+    item_ids.truncate(count);
+    item_ids.into_iter().map(|item_id| { ctx.image_handle(item_id).map_err(ConversionErrorKind::decode) }).collect()
+}
+
+fn decode_handle(handle: &ImageHandle) -> Result<(DynamicImage, ImageMetadata), ConversionErrorKind> {
+    let high_bit_depth = handle.luma_bits_per_pixel() > 8;
+    let has_alpha = handle.has_alpha_channel();
+    let chroma = match (high_bit_depth, has_alpha) {
+        (true, true) => RgbChroma::HdrRgbaLe,
+        (true, false) => RgbChroma::HdrRgbLe,
+        (false, true) => RgbChroma::Rgba,
+        (false, false) => RgbChroma::Rgb,
+    };
+    // Decoding without options applies the irot/imir transforms of the container
+    let image = crate::LIBHEIF.decode(handle, ColorSpace::Rgb(chroma), None).map_err(ConversionErrorKind::decode)?;
+    
+    let plane = image.planes().interleaved.ok_or_else(|| { ConversionErrorKind::Decode("Decoded image has no interleaved plane".into()) })?;
+    let channels = if has_alpha { 4 } else { 3 };
+    let bytes_per_pixel = if high_bit_depth { channels * 2 } else { channels };
+    let row_len = plane.width as usize * bytes_per_pixel;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+    let invalid_size = || { ConversionErrorKind::Decode("Decoded image has an invalid size".into()) };
+    let buffer = if high_bit_depth {
+        // libheif keeps the samples in their native range, scale them up to the full 16 bits
+        let bits = plane.bits_per_pixel as u32;
+        let samples = pixels.chunks_exact(2).map(|sample| { scale_to_16_bits(u16::from_le_bytes([sample[0], sample[1]]), bits) }).collect::<Vec<u16>>();
+        match has_alpha {
+            true => DynamicImage::ImageRgba16(ImageBuffer::from_raw(plane.width, plane.height, samples).ok_or_else(invalid_size)?),
+            false => DynamicImage::ImageRgb16(ImageBuffer::from_raw(plane.width, plane.height, samples).ok_or_else(invalid_size)?),
+        }
+    } else {
+        match has_alpha {
+            true => DynamicImage::ImageRgba8(ImageBuffer::from_raw(plane.width, plane.height, pixels).ok_or_else(invalid_size)?),
+            false => DynamicImage::ImageRgb8(ImageBuffer::from_raw(plane.width, plane.height, pixels).ok_or_else(invalid_size)?),
+        }
+    };
+    let buffer = drop_opaque_alpha(buffer);
+    
+    // The pixels are already upright, the metadata must not claim otherwise
+    let mut metadata = ImageMetadata::from_handle(handle);
+    metadata.reset_orientation();
+    
+    Ok((buffer, metadata))
+}
+
+/// Rotates, flips and crops the image. The orientation in the metadata is already reset
+/// after decoding, so the result is shown exactly as transformed.
+fn apply_transform(img: DynamicImage, transform: &Transform) -> DynamicImage {
+    let img = match transform.rotation {
+        Rotation::None => img,
+        Rotation::Clockwise90 => img.rotate90(),
+        Rotation::Rotate180 => img.rotate180(),
+        Rotation::Clockwise270 => img.rotate270(),
+    };
+    let img = if transform.flip_horizontal { img.fliph() } else { img };
+    let img = if transform.flip_vertical { img.flipv() } else { img };
+    let (x, y, crop_width, crop_height) = transform.crop_rect(img.width(), img.height());
+    if (crop_width, crop_height) == (img.width(), img.height()) { return img; }
+    img.crop_imm(x, y, crop_width, crop_height)
+}
+
+/// An alpha plane which is opaque everywhere carries no information, so outputs drop it
+fn drop_opaque_alpha(buffer: DynamicImage) -> DynamicImage {
+    match buffer {
+        DynamicImage::ImageRgba8(rgba) if rgba.pixels().all(|pixel| { pixel.0[3] == u8::MAX }) => DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).into_rgb8()),
+        DynamicImage::ImageRgba16(rgba) if rgba.pixels().all(|pixel| { pixel.0[3] == u16::MAX }) => DynamicImage::ImageRgb16(DynamicImage::ImageRgba16(rgba).into_rgb16()),
+        buffer => buffer,
+    }
+}
+
+/// Scales a sample with `bits` significant bits up to the full 16-bit range by repeating its high bits
+pub(crate) fn scale_to_16_bits(value: u16, bits: u32) -> u16 {
+    (value << (16 - bits)) | value.checked_shr((2 * bits).saturating_sub(16)).unwrap_or(0)
+}
+
+/// Transforms the pixels from the embedded colour profile into sRGB and replaces
+/// the profile accordingly. Images without a profile are assumed to be sRGB already.
+pub fn convert_to_srgb(input: &mut DynamicImage, metadata: &mut ImageMetadata) -> Result<(), ConversionErrorKind> {
+    use moxcms::{ColorProfile, Layout, TransformOptions};
+    
+    let Some(icc_profile) = &metadata.icc_profile else { return Ok(()); };
+    let source_profile = ColorProfile::new_from_slice(icc_profile).map_err(ConversionErrorKind::metadata)?;
+    let srgb_profile = ColorProfile::new_srgb();
+    match input {
+        DynamicImage::ImageRgba16(buffer) => {
+            let transform = source_profile.create_transform_16bit(Layout::Rgba, &srgb_profile, Layout::Rgba, TransformOptions::default()).map_err(ConversionErrorKind::metadata)?;
+            let source = buffer.as_raw().clone();
+            transform.transform(&source, buffer).map_err(ConversionErrorKind::metadata)?;
+        },
+        DynamicImage::ImageRgb16(buffer) => {
+            let transform = source_profile.create_transform_16bit(Layout::Rgb, &srgb_profile, Layout::Rgb, TransformOptions::default()).map_err(ConversionErrorKind::metadata)?;
+            let source = buffer.as_raw().clone();
+            transform.transform(&source, buffer).map_err(ConversionErrorKind::metadata)?;
+        },
+        DynamicImage::ImageRgb8(buffer) => {
+            let transform = source_profile.create_transform_8bit(Layout::Rgb, &srgb_profile, Layout::Rgb, TransformOptions::default()).map_err(ConversionErrorKind::metadata)?;
+            let source = buffer.as_raw().clone();
+            transform.transform(&source, buffer).map_err(ConversionErrorKind::metadata)?;
+        },
+        _ => {
+            let mut buffer = input.to_rgba8();
+            let transform = source_profile.create_transform_8bit(Layout::Rgba, &srgb_profile, Layout::Rgba, TransformOptions::default()).map_err(ConversionErrorKind::metadata)?;
+            let source = buffer.as_raw().clone();
+            transform.transform(&source, &mut buffer).map_err(ConversionErrorKind::metadata)?;
+            *input = DynamicImage::ImageRgba8(buffer);
+        },
+    }
+    metadata.icc_profile = Some(srgb_profile.encode().map_err(ConversionErrorKind::metadata)?);
+    Ok(())
+}
+
+/// Result of a successful `convert_file` call
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConversionOutput {
+    /// The image was written to this path
+    Written(PathBuf),
+    /// The output file already existed and was left untouched
+    Skipped(PathBuf),
+}
+
+/// Decodes, converts and writes a source file into `output_dir`, which is created if missing. `index` is the 1-based
+/// position in the input list for the naming template. `ask` is only called for
+/// `CollisionPolicy::Ask` and has to answer with one of the other policies.
+/// Returns one output per exported image, which is only the primary image unless
+/// `ConversionOptions::export_all_images` is set.
+pub fn convert_file(path: &Path, index: usize, output_dir: &Path, options: &ConversionOptions, ask: &dyn Fn(&Path) -> CollisionPolicy) -> Result<Vec<ConversionOutput>, ConversionError> {
+    let data = std::fs::read(path).map_err(|err| { ConversionErrorKind::io(err).at(path) })?;
+    let ctx = HeifContext::read_from_bytes(&data).map_err(|err| { ConversionErrorKind::decode(err).at(path) })?;
+    let handles = match options.export_all_images {
+        true => top_level_image_handles(&ctx).map_err(|kind| { kind.at(path) })?,
+        false => vec![ctx.primary_image_handle().map_err(|err| { ConversionErrorKind::decode(err).at(path) })?],
+    };
+    
+    // Only containers with several images get numbered names, single images keep the plain template
+    let numbered = handles.len() > 1;
+    let mut outputs = Vec::with_capacity(handles.len());
+    for (image_index, handle) in handles.iter().enumerate() {
+        let (img, mut metadata) = decode_handle(handle).map_err(|kind| { kind.at(path) })?;
+        // The video of a Live Photo belongs to the primary image only
+        let live_photo_video = match options.live_photo_video {
+            LivePhotoVideoAction::Ignore => None,
+            _ if !handle.is_primary() => None,
+            _ => crate::live_photo::find_live_photo_video(path, &metadata),
+        };
+        // Outputs without metadata stay free of it, the pairing is then only kept by the file names
+        if options.keep_metadata && let Some(identifier) = live_photo_video.as_ref().and_then(|video| { video.content_identifier.as_deref() }) {
+            metadata.add_live_photo_identifier(identifier);
+        }
+        
+        let main_size = output_size(img.width(), img.height(), options);
+        let output = export_image(path, index, numbered.then_some(image_index + 1), (img, metadata), output_dir, options, ask)?;
+        let ConversionOutput::Written(out_path) = &output else {
+            outputs.push(output);
+            continue;
+        };
+        // Depth maps, auxiliary images and videos only make sense next to a main image which was written
+        let mut companion_outputs = Vec::new();
+        if options.export_depth || options.export_auxiliary {
+            companion_outputs.extend(export_auxiliary_images(path, handle, out_path, main_size, options, ask)?);
+        }
+        if let Some(video) = &live_photo_video {
+            companion_outputs.extend(export_live_photo_video(path, video, out_path, options, ask)?);
+        }
+        outputs.push(output);
+        outputs.extend(companion_outputs);
+    }
+    Ok(outputs)
+}
+
+/// Copies or moves the video of a Live Photo next to `still_output`, with the same name as the still.
+/// Nothing happens if the video already is at that place, e.g. when converting into the source folder.
+fn export_live_photo_video(path: &Path, video: &LivePhotoVideo, still_output: &Path, options: &ConversionOptions, ask: &dyn Fn(&Path) -> CollisionPolicy) -> Result<Option<ConversionOutput>, ConversionError> {
+    let out_file_path = still_output.with_extension(video.path.extension().unwrap_or_default());
+    if crate::naming::is_same_file(&out_file_path, &video.path) { return Ok(None); }
+    let Some(reserved) = crate::naming::reserve_output(out_file_path.clone(), options.collision_policy, ask).map_err(|kind| { kind.at(path) })? else {
+        return Ok(Some(ConversionOutput::Skipped(out_file_path)));
+    };
+    if let Err(kind) = transfer_file(&video.path, &reserved, options.live_photo_video == LivePhotoVideoAction::Move) {
+        reserved.release();
+        return Err(kind.at(path));
+    }
+    Ok(Some(ConversionOutput::Written(reserved.path)))
+}
+
+/// Copies `source` like `write_output` writes, or moves it if `remove_source` is set
+fn transfer_file(source: &Path, reserved: &ReservedOutput, remove_source: bool) -> Result<(), ConversionErrorKind> {
+    // Renaming fails across file systems, then the file is copied and the source removed afterwards
+    if remove_source && std::fs::rename(source, &reserved.path).is_ok() {
+        reserved.release();
+        return Ok(());
+    }
+    let result = std::fs::copy(source, &reserved.part).and_then(|_| { std::fs::rename(&reserved.part, &reserved.path) });
+    if let Err(err) = result {
+        reserved.release();
+        return Err(ConversionErrorKind::write(err));
+    }
+    if remove_source {
+        std::fs::remove_file(source).map_err(ConversionErrorKind::write)?;
+    }
+    Ok(())
+}
+
+/// Writes the depth maps and auxiliary images of `handle` next to `main_output`, named after it.
+/// When resizing they are scaled to `main_size`, the size of the main output.
+fn export_auxiliary_images(path: &Path, handle: &ImageHandle, main_output: &Path, main_size: (u32, u32), options: &ConversionOptions, ask: &dyn Fn(&Path) -> CollisionPolicy) -> Result<Vec<ConversionOutput>, ConversionError> {
+    let images = crate::auxiliary::auxiliary_images(handle, options.export_depth, options.export_auxiliary).map_err(|kind| { kind.at(path) })?;
+    let mut outputs = Vec::with_capacity(images.len());
+    for image in images {
+        let out_file_path = auxiliary_output_path(main_output, &image.suffix, options.auxiliary_format);
+        refuse_source_overwrite(path, &out_file_path, options.collision_policy).map_err(|kind| { kind.at(path) })?;
+        let Some(reserved) = crate::naming::reserve_output(out_file_path.clone(), options.collision_policy, ask).map_err(|kind| { kind.at(path) })? else {
+            outputs.push(ConversionOutput::Skipped(out_file_path));
+            continue;
+        };
+        let mut map = transform_auxiliary(image.image, &options.transform);
+        if options.resize != Resize::Original && map.dimensions() != main_size {
+            map = image::imageops::resize(&map, main_size.0, main_size.1, options.resize_filter.filter_type());
+        }
+        let result = crate::auxiliary::encode_grayscale(&map, options.auxiliary_format).and_then(|data| { write_output(&reserved, &data) });
+        if let Err(kind) = result {
+            reserved.release();
+            return Err(kind.at(path));
+        }
+        outputs.push(ConversionOutput::Written(reserved.path));
+    }
+    Ok(outputs)
+}
+
+/// The auxiliary image is named after the main output with its suffix appended, e.g. `IMG_0001_depth.png`
+fn auxiliary_output_path(main_output: &Path, suffix: &str, format: AuxiliaryFormat) -> PathBuf {
+    let stem = main_output.file_stem().map(|stem| { stem.to_string_lossy().to_string() }).unwrap_or_default();
+    main_output.with_file_name(format!("{stem}_{suffix}")).with_added_extension(format.file_extension())
+}
+
+/// Size of a decoded `width` × `height` image once it is transformed and resized
+fn output_size(width: u32, height: u32, options: &ConversionOptions) -> (u32, u32) {
+    let (width, height) = options.transform.output_size(width, height);
+    options.resize.target_size(width, height)
+}
+
+/// Rotates, flips and crops a depth map or auxiliary image like the main image, so both still line up.
+/// Maps usually have a lower resolution, the crop covers the same share of them nevertheless.
+fn transform_auxiliary(map: GrayImage16, transform: &Transform) -> GrayImage16 {
+    if transform.is_identity() { return map; }
+    apply_transform(DynamicImage::ImageLuma16(map), transform).into_luma16()
+}
+
+/// Names, encodes and writes one decoded image of `path`. `image_number` is appended to the
+/// file name for containers with several exported images.
+fn export_image(path: &Path, index: usize, image_number: Option<usize>, decoded: (DynamicImage, ImageMetadata), output_dir: &Path, options: &ConversionOptions, ask: &dyn Fn(&Path) -> CollisionPolicy) -> Result<ConversionOutput, ConversionError> {
+    let (mut img, mut metadata) = decoded;
+    let (width, height) = output_size(img.width(), img.height(), options);
+    
+    // Resolve the output name before encoding, so skipped files cost no encoder time
+    let extension = options.format.file_extension();
+    let mut file_name = crate::naming::render_template(&options.name_template, &NameValues {
+        source: path,
+        index,
+        metadata: &metadata,
+        format: &extension.to_string_lossy(),
+        width,
+        height,
+    });
+    if let Some(image_number) = image_number {
+        file_name.push_str(&format!("_{image_number}"));
+    }
+    let out_file_path = output_dir.join(file_name).with_added_extension(extension);
+    // Mirrored folder structures point into directories which do not exist yet
+    std::fs::create_dir_all(output_dir).map_err(|err| { ConversionErrorKind::write(err).at(path) })?;
+    refuse_source_overwrite(path, &out_file_path, options.collision_policy).map_err(|kind| { kind.at(path) })?;
+    let Some(reserved) = crate::naming::reserve_output(out_file_path.clone(), options.collision_policy, ask).map_err(|kind| { kind.at(path) })? else {
+        return Ok(ConversionOutput::Skipped(out_file_path));
+    };
+    
+    let result = (|| {
+        if !options.transform.is_identity() {
+            img = apply_transform(img, &options.transform);
+        }
+        // Resizing first leaves fewer pixels for the colour transform
+        if (width, height) != (img.width(), img.height()) {
+            img = img.resize_exact(width, height, options.resize_filter.filter_type());
+        }
+        if !options.transform.is_identity() || options.resize != Resize::Original {
+            metadata.set_pixel_dimensions(width, height);
+        }
+        if options.convert_to_srgb {
+            convert_to_srgb(&mut img, &mut metadata)?;
+        }
+        let img = convert_to_format(img, &metadata, options)?;
+        write_output(&reserved, img.as_ref().as_ref())
+    })();
+    if let Err(kind) = result {
+        reserved.release();
+        return Err(kind.at(path));
+    }
+    Ok(ConversionOutput::Written(reserved.path))
+}
+
+/// Fails if `out_file_path` is the source itself, which a plain path comparison misses for
+/// relative paths, symlinks or a different case. Suffixed names never replace the source.
+fn refuse_source_overwrite(source: &Path, out_file_path: &Path, policy: CollisionPolicy) -> Result<(), ConversionErrorKind> {
+    if policy != CollisionPolicy::AutoSuffix && crate::naming::is_same_file(source, out_file_path) {
+        return Err(ConversionErrorKind::Write("Output file would overwrite the source file".into()));
+    }
+    Ok(())
+}
+
+/// Writes into the part file of the reservation and moves it into place once complete,
+/// so an interrupted or failed write never leaves a truncated image behind
+fn write_output(reserved: &ReservedOutput, data: &[u8]) -> Result<(), ConversionErrorKind> {
+    let result = std::fs::write(&reserved.part, data).and_then(|_| { std::fs::rename(&reserved.part, &reserved.path) });
+    if let Err(err) = result {
+        reserved.release();
+        return Err(ConversionErrorKind::write(err));
+    }
+    Ok(())
+}
+
+pub fn convert_to_format(input: DynamicImage, metadata: &ImageMetadata, options: &ConversionOptions) -> Result<Box<dyn AsRef<[u8]> + Send + Sync>, ConversionErrorKind> {
+    
+    // The colour profile is not personal metadata and always kept, everything else only on request
+    let color_only_metadata = metadata.color_only();
+    let metadata = if options.keep_metadata { metadata } else { &color_only_metadata };
+    
+    let format = &options.format;
+    match format {
+        OutputFormat::JPEG(_, alpha_policy) => convert_to_jpeg(flatten_alpha(input, *alpha_policy)?, metadata, format),
+        OutputFormat::PNG(_, _, _) => convert_to_png(input, metadata, format),
+        OutputFormat::TIFF(_, _, _) => convert_to_tiff(input, metadata, format),
+        OutputFormat::WebP(_) => convert_to_webp(input, metadata, format),
+        OutputFormat::AVIF(_, _, _) => convert_to_avif(input, metadata, format),
+        OutputFormat::JXL(_, _) => convert_to_jxl(input, metadata, format),
+        OutputFormat::HEIF(_, _) => convert_to_heif(input, metadata, format),
+    }
+    
+}
+
+/// Removes the alpha channel of transparent images according to the policy
+fn flatten_alpha(input: DynamicImage, policy: AlphaPolicy) -> Result<RgbImage, ConversionErrorKind> {
+    if !input.color().has_alpha() { return Ok(input.into_rgb8()); }
+    let background = match policy {
+        AlphaPolicy::Composite(background) => background,
+        // Premultiplied colour is the same as the colour blended onto black
+        AlphaPolicy::Premultiply => [0, 0, 0],
+        AlphaPolicy::Warn => return Err(ConversionErrorKind::Unsupported("The image is transparent, which JPEG cannot store".into())),
+    };
+    let rgba = input.into_rgba8();
+    Ok(RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [red, green, blue, alpha] = rgba.get_pixel(x, y).0;
+        let blend = |color: u8, background: u8| { ((color as u32 * alpha as u32 + background as u32 * (255 - alpha as u32) + 127) / 255) as u8 };
+        Rgb([blend(red, background[0]), blend(green, background[1]), blend(blue, background[2])])
+    }))
+}
+
+fn convert_to_jpeg(input: RgbImage, metadata: &ImageMetadata, format: &OutputFormat) -> Result<Box<dyn AsRef<[u8]> + Send + Sync>, ConversionErrorKind> {
+    use turbojpeg::{Compressor, Image, PixelFormat};
+    
+    let quality = match format {
+        OutputFormat::JPEG(quality, _) => quality,
+        _ => unreachable!("Logic Error: Found different OutputFormat")
+    };
+    
+    let mut compressor = Compressor::new().map_err(ConversionErrorKind::encode)?;
+    if *quality == 100 {
+        compressor.set_lossless(true).map_err(ConversionErrorKind::encode)?;
+    } else {
+        compressor.set_lossless(false).map_err(ConversionErrorKind::encode)?;
+        compressor.set_quality(*quality as i32).map_err(ConversionErrorKind::encode)?;
+    }
+    
+    let img = Image { pixels: input.as_bytes(), width: input.width() as usize, pitch: input.width() as usize * PixelFormat::RGB.size(), height: input.height() as usize, format: PixelFormat::RGB };
+    let out_buf = compressor.compress_to_vec(img).map_err(ConversionErrorKind::encode)?;
+    let out_buf = crate::metadata::embed_in_jpeg(out_buf, metadata)?;
+    Ok(Box::new(out_buf))
+}
+
+fn convert_to_png(input: DynamicImage, metadata: &ImageMetadata, format: &OutputFormat) -> Result<Box<dyn AsRef<[u8]> + Send + Sync>, ConversionErrorKind> {
+    use png::{ColorType, Compression, Encoder, Info};
+    
+    let (compression, bit_depth, color_mode) = match format {
+        OutputFormat::PNG(compression, bit_depth, color_mode) => (compression, bit_depth, color_mode),
+        _ => unreachable!("Logic Error: Found different OutputFormat")
+    };
+    
+    let (width, height) = (input.width(), input.height());
+    let input = with_bit_depth(input, *bit_depth);
+    let stored_color = stored_color(&input, *color_mode, true)?;
+    let (color_type, depth) = match &stored_color {
+        StoredColor::Gray => (ColorType::Grayscale, *bit_depth),
+        StoredColor::GrayAlpha => (ColorType::GrayscaleAlpha, *bit_depth),
+        StoredColor::Rgb => (ColorType::Rgb, *bit_depth),
+        StoredColor::Rgba => (ColorType::Rgba, *bit_depth),
+        StoredColor::Palette(_, _) => (ColorType::Indexed, BitDepth::Eight),
+    };
+    let is_gray = matches!(stored_color, StoredColor::Gray | StoredColor::GrayAlpha);
+    
+    // PNG stores 16-bit samples in big endian order
+    let to_be_bytes = |samples: Vec<u16>| { samples.into_iter().flat_map(u16::to_be_bytes).collect::<Vec<u8>>() };
+    let (palette, data) = match (stored_color, depth) {
+        (StoredColor::Palette(palette, indices), _) => (Some(palette), indices),
+        (StoredColor::Gray, BitDepth::Eight) => (None, input.into_luma8().into_raw()),
+        (StoredColor::Gray, BitDepth::Sixteen) => (None, to_be_bytes(input.into_luma16().into_raw())),
+        (StoredColor::GrayAlpha, BitDepth::Eight) => (None, input.into_luma_alpha8().into_raw()),
+        (StoredColor::GrayAlpha, BitDepth::Sixteen) => (None, to_be_bytes(input.into_luma_alpha16().into_raw())),
+        (StoredColor::Rgb, BitDepth::Eight) => (None, input.into_rgb8().into_raw()),
+        (StoredColor::Rgb, BitDepth::Sixteen) => (None, to_be_bytes(input.into_rgb16().into_raw())),
+        (StoredColor::Rgba, BitDepth::Eight) => (None, input.into_rgba8().into_raw()),
+        (StoredColor::Rgba, BitDepth::Sixteen) => (None, to_be_bytes(input.into_rgba16().into_raw())),
+    };
+    
+    let mut info = Info::with_size(width, height);
+    info.exif_metadata = metadata.exif.as_deref().map(Cow::Borrowed);
+    // Grayscale PNGs only allow grayscale ICC profiles, the neutral pixels need no RGB profile
+    info.icc_profile = metadata.icc_profile.as_deref().filter(|_| { !is_gray }).map(Cow::Borrowed);
+    
+    let mut out_vec = Vec::new();
+    let mut encoder = Encoder::with_info(&mut out_vec, info).map_err(ConversionErrorKind::encode)?;
+    if let Some(xmp) = &metadata.xmp {
+        let xmp = String::from_utf8(xmp.clone()).map_err(|err| { ConversionErrorKind::Metadata(ErrorDetails::with_message(format!("XMP packet is not valid UTF-8: {err}"), err)) })?;
+        encoder.add_itxt_chunk(crate::metadata::PNG_XMP_KEYWORD.into(), xmp).map_err(ConversionErrorKind::metadata)?;
+    }
+    encoder.set_color(color_type);
+    encoder.set_depth(match depth {
+        BitDepth::Eight => png::BitDepth::Eight,
+        BitDepth::Sixteen => png::BitDepth::Sixteen,
+    });
+    if let Some(palette) = palette {
+        // The transparency chunk is only needed if a palette entry is not fully opaque
+        if palette.iter().any(|color| { color[3] != u8::MAX }) {
+            encoder.set_trns(palette.iter().map(|color| { color[3] }).collect::<Vec<u8>>());
+        }
+        encoder.set_palette(palette.iter().flat_map(|color| { [color[0], color[1], color[2]] }).collect::<Vec<u8>>());
+    }
+    
+    match *compression {
+        0 => encoder.set_compression(Compression::NoCompression),
+        1..=25 => encoder.set_compression(Compression::Fastest),
+        26..=50 => encoder.set_compression(Compression::Fast),
+        51..=75 => encoder.set_compression(Compression::Balanced),
+        76.. => encoder.set_compression(Compression::High),
+    }
+    
+    let mut writer = encoder.write_header().map_err(ConversionErrorKind::encode)?;
+    writer.write_image_data(&data).map_err(ConversionErrorKind::encode)?;
+    writer.finish().map_err(ConversionErrorKind::encode)?;
+    
+    Ok(Box::new(out_vec))
+}
+
+fn convert_to_tiff(input: DynamicImage, metadata: &ImageMetadata, format: &OutputFormat) -> Result<Box<dyn AsRef<[u8]> + Send + Sync>, ConversionErrorKind> {
+    use tiff::encoder::{compression::DeflateLevel, Compression};
+    
+    let (compression, bit_depth, color_mode) = match format {
+        OutputFormat::TIFF(compression, bit_depth, color_mode) => (compression, bit_depth, color_mode),
+        _ => unreachable!("Logic Error: Found different OutputFormat")
+    };
+    
+    let mut out_vec = Vec::new();
+    let mut cursor = Cursor::new(&mut out_vec);
+    let mut encoder = TiffEncoder::new(&mut cursor).map_err(ConversionErrorKind::encode)?;
+    
+    encoder = match compression {
+        TIFFCompression::None => encoder.with_compression(Compression::Uncompressed),
+        TIFFCompression::LZW => encoder.with_compression(Compression::Lzw),
+        TIFFCompression::Deflate => encoder.with_compression(Compression::Deflate(DeflateLevel::Balanced)),
+    };
+    
+    let (width, height) = (input.width(), input.height());
+    let input = with_bit_depth(input, *bit_depth);
+    // Grayscale TIFFs only allow grayscale ICC profiles, the neutral pixels need no RGB profile
+    let gray_metadata = || { ImageMetadata { icc_profile: None, ..metadata.clone() } };
+    match (stored_color(&input, *color_mode, false)?, bit_depth) {
+        (StoredColor::Gray, BitDepth::Eight) => write_tiff_image::<_, Gray8>(&mut encoder, width, height, input.into_luma8().as_raw(), &gray_metadata())?,
+        (StoredColor::Gray, BitDepth::Sixteen) => write_tiff_image::<_, Gray16>(&mut encoder, width, height, input.into_luma16().as_raw(), &gray_metadata())?,
+        (StoredColor::Rgb, BitDepth::Eight) => write_tiff_image::<_, RGB8>(&mut encoder, width, height, input.into_rgb8().as_raw(), metadata)?,
+        (StoredColor::Rgb, BitDepth::Sixteen) => write_tiff_image::<_, RGB16>(&mut encoder, width, height, input.into_rgb16().as_raw(), metadata)?,
+        // The TIFF encoder has no grayscale type with alpha, transparent monochrome images stay RGBA
+        (_, BitDepth::Eight) => write_tiff_image::<_, RGBA8>(&mut encoder, width, height, input.into_rgba8().as_raw(), metadata)?,
+        (_, BitDepth::Sixteen) => write_tiff_image::<_, RGBA16>(&mut encoder, width, height, input.into_rgba16().as_raw(), metadata)?,
+    }
+    
+    Ok(Box::new(out_vec))
+}
+
+/// Colour type a PNG or TIFF file is written with
+enum StoredColor {
+    Gray,
+    GrayAlpha,
+    Rgb,
+    Rgba,
+    /// Colours with alpha and the index of every pixel
+    Palette(Vec<[u8; 4]>, Vec<u8>),
+}
+
+/// Converts the samples to the bit depth of the output, keeping an alpha channel if there is one
+fn with_bit_depth(input: DynamicImage, bit_depth: BitDepth) -> DynamicImage {
+    match (bit_depth, input.color().has_alpha()) {
+        (BitDepth::Eight, true) => DynamicImage::ImageRgba8(input.into_rgba8()),
+        (BitDepth::Eight, false) => DynamicImage::ImageRgb8(input.into_rgb8()),
+        (BitDepth::Sixteen, true) => DynamicImage::ImageRgba16(input.into_rgba16()),
+        (BitDepth::Sixteen, false) => DynamicImage::ImageRgb16(input.into_rgb16()),
+    }
+}
+
+/// Resolves the colour mode for an image which already has the bit depth of the output.
+/// The automatic mode only picks types which keep every pixel unchanged.
+fn stored_color(input: &DynamicImage, color_mode: ColorMode, supports_palette: bool) -> Result<StoredColor, ConversionErrorKind> {
+    let palette = || {
+        match input {
+            DynamicImage::ImageRgb8(buffer) => build_palette(buffer),
+            DynamicImage::ImageRgba8(buffer) => build_palette(buffer),
+            _ => None,
+        }
+    };
+    let (grayscale, opaque) = match input {
+        DynamicImage::ImageRgb8(buffer) => analyse_colors(buffer),
+        DynamicImage::ImageRgba8(buffer) => analyse_colors(buffer),
+        DynamicImage::ImageRgb16(buffer) => analyse_colors(buffer),
+        DynamicImage::ImageRgba16(buffer) => analyse_colors(buffer),
+        input => analyse_colors(&input.to_rgba16()),
+    };
+    
+    Ok(match color_mode {
+        ColorMode::Auto if grayscale && opaque => StoredColor::Gray,
+        ColorMode::Auto => match palette().filter(|_| { supports_palette }) {
+            Some((colors, indices)) => StoredColor::Palette(colors, indices),
+            None if grayscale => StoredColor::GrayAlpha,
+            None if opaque => StoredColor::Rgb,
+            None => StoredColor::Rgba,
+        },
+        ColorMode::Rgb => StoredColor::Rgb,
+        ColorMode::Rgba => StoredColor::Rgba,
+        ColorMode::Grayscale if opaque => StoredColor::Gray,
+        ColorMode::Grayscale => StoredColor::GrayAlpha,
+        ColorMode::Palette if !supports_palette => return Err(ConversionErrorKind::Unsupported("This format cannot store a colour palette".into())),
+        ColorMode::Palette => {
+            let (colors, indices) = palette().ok_or_else(|| { ConversionErrorKind::Unsupported("A colour palette needs 8 bits per channel and at most 256 colours".into()) })?;
+            StoredColor::Palette(colors, indices)
+        },
+    })
+}
+
+/// Whether every pixel is neutral grey and whether every pixel is fully opaque
+fn analyse_colors<P: Pixel>(buffer: &ImageBuffer<P, Vec<P::Subpixel>>) -> (bool, bool) {
+    let (mut grayscale, mut opaque) = (true, true);
+    for pixel in buffer.pixels() {
+        let channels = pixel.channels();
+        grayscale &= channels[0] == channels[1] && channels[1] == channels[2];
+        opaque &= channels.len() < 4 || channels[3] == P::Subpixel::DEFAULT_MAX_VALUE;
+        if !grayscale && !opaque { break; }
+    }
+    (grayscale, opaque)
+}
+
+/// Collects the colours and the index of every pixel, gives up after 256 colours
+fn build_palette<P: Pixel<Subpixel = u8>>(buffer: &ImageBuffer<P, Vec<u8>>) -> Option<(Vec<[u8; 4]>, Vec<u8>)> {
+    let mut colors = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::with_capacity(buffer.width() as usize * buffer.height() as usize);
+    for pixel in buffer.pixels() {
+        let color = pixel.to_rgba().0;
+        let index = match lookup.get(&color) {
+            Some(index) => *index,
+            None => {
+                let index = u8::try_from(colors.len()).ok()?;
+                lookup.insert(color, index);
+                colors.push(color);
+                index
+            },
+        };
+        indices.push(index);
+    }
+    Some((colors, indices))
+}
+
+fn write_tiff_image<W: Write + Seek, C: ColorType>(encoder: &mut TiffEncoder<W>, width: u32, height: u32, data: &[C::Inner], metadata: &ImageMetadata) -> Result<(), ConversionErrorKind> where [C::Inner]: TiffValue {
+    let exif = metadata.exif.as_deref().map(crate::metadata::parse_exif).transpose()?;
+    let exif_pointers = match &exif {
+        Some(exif) => crate::metadata::write_tiff_exif_directories(encoder, exif)?,
+        None => Vec::new(),
+    };
+    
+    let mut image = encoder.new_image::<C>(width, height).map_err(ConversionErrorKind::encode)?;
+    if let Some(exif) = &exif {
+        crate::metadata::write_tiff_primary_fields(image.encoder(), exif)?;
+    }
+    for (tag, offset) in exif_pointers {
+        image.encoder().write_tag(tag, offset).map_err(ConversionErrorKind::metadata)?;
+    }
+    if let Some(icc_profile) = &metadata.icc_profile {
+        crate::metadata::write_tiff_icc_profile(image.encoder(), icc_profile)?;
+    }
+    if let Some(xmp) = &metadata.xmp {
+        image.encoder().write_tag(Tag::Unknown(crate::metadata::TIFF_XMP_TAG), xmp.as_slice()).map_err(ConversionErrorKind::metadata)?;
+    }
+    image.write_data(data).map_err(ConversionErrorKind::encode)?;
+    
+    Ok(())
+}
+
+fn convert_to_webp(input: DynamicImage, metadata: &ImageMetadata, format: &OutputFormat) -> Result<Box<dyn AsRef<[u8]> + Send + Sync>, ConversionErrorKind> {
+    use webp::Encoder;
+    
+    let compression = match format {
+        OutputFormat::WebP(compression) => compression,
+        _ => unreachable!("Logic Error: Found different OutputFormat")
+    };
+    
+    let (width, height) = (input.width(), input.height());
+    let input = match input.color().has_alpha() {
+        true => DynamicImage::ImageRgba8(input.into_rgba8()),
+        false => DynamicImage::ImageRgb8(input.into_rgb8()),
+    };
+    let encoder = Encoder::from_image(&input).map_err(|err| { ConversionErrorKind::Encode(err.into()) })?;
+    
+    let encoded_mem = match *compression {
+        0 => encoder.encode_simple(false, *compression as f32).map_err(|err| { ConversionErrorKind::Encode(format!("{err:?}").into()) })?,
+        1..=25 => encoder.encode_simple(false, *compression as f32).map_err(|err| { ConversionErrorKind::Encode(format!("{err:?}").into()) })?,
+        26..=50 => encoder.encode_simple(false, *compression as f32).map_err(|err| { ConversionErrorKind::Encode(format!("{err:?}").into()) })?,
+        51..=75 => encoder.encode_simple(false, *compression as f32).map_err(|err| { ConversionErrorKind::Encode(format!("{err:?}").into()) })?,
+        76..100 => encoder.encode_simple(false, *compression as f32).map_err(|err| { ConversionErrorKind::Encode(format!("{err:?}").into()) })?,
+        100.. => encoder.encode_simple(true, 100.).map_err(|err| { ConversionErrorKind::Encode(format!("{err:?}").into()) })?,
+    };
+    
+    let out_buf = crate::metadata::embed_in_webp(&encoded_mem, width, height, metadata)?;
+    Ok(Box::new(out_buf))
+}
+
+fn convert_to_avif(input: DynamicImage, metadata: &ImageMetadata, format: &OutputFormat) -> Result<Box<dyn AsRef<[u8]> + Send + Sync>, ConversionErrorKind> {
+    
+    let (quality, speed, chroma) = match format {
+        OutputFormat::AVIF(quality, speed, chroma) => (quality, speed, chroma),
+        _ => unreachable!("Logic Error: Found different OutputFormat")
+    };
+    
+    let chroma = match chroma {
+        ChromaSubsampling::Yuv420 => "420",
+        ChromaSubsampling::Yuv422 => "422",
+        ChromaSubsampling::Yuv444 => "444",
+    };
+    
+    let out_buf = encode_heif_container(input, metadata, CompressionFormat::Av1, EncoderQuality::Lossy(*quality), &[
+        ("speed", EncoderParameterValue::Int(*speed as i32)),
+        ("chroma", EncoderParameterValue::String(chroma.into())),
+    ])?;
+    Ok(Box::new(out_buf))
+}
+
+fn convert_to_jxl(input: DynamicImage, metadata: &ImageMetadata, format: &OutputFormat) -> Result<Box<dyn AsRef<[u8]> + Send + Sync>, ConversionErrorKind> {
+    use crate::jxl::{JxlImage, JxlPixels};
+    
+    let (quality, effort) = match format {
+        OutputFormat::JXL(quality, effort) => (quality, effort),
+        _ => unreachable!("Logic Error: Found different OutputFormat")
+    };
+    
+    // The Exif box starts with the offset to the TIFF header, just like in HEIF
+    let exif = metadata.exif.as_ref().map(|exif| { [&[0u8; 4], exif.as_slice()].concat() });
+    let mut boxes = Vec::new();
+    if let Some(exif) = &exif { boxes.push((*b"Exif", exif.as_slice())); }
+    if let Some(xmp) = &metadata.xmp { boxes.push((*b"xml ", xmp.as_slice())); }
+    
+    let image = JxlImage {
+        width: input.width(),
+        height: input.height(),
+        has_alpha: input.color().has_alpha(),
+        icc_profile: metadata.icc_profile.as_deref(),
+        boxes,
+    };
+    let pixels = match (&input, image.has_alpha) {
+        (DynamicImage::ImageRgba16(_) | DynamicImage::ImageRgb16(_), true) => JxlPixels::Sixteen(input.into_rgba16().into_raw()),
+        (DynamicImage::ImageRgba16(_) | DynamicImage::ImageRgb16(_), false) => JxlPixels::Sixteen(input.into_rgb16().into_raw()),
+        (_, true) => JxlPixels::Eight(input.into_rgba8().into_raw()),
+        (_, false) => JxlPixels::Eight(input.into_rgb8().into_raw()),
+    };
+    // Quality 100 is a true lossless mode, which keeps the samples and the colour profile of the source
+    let out_buf = crate::jxl::encode(&pixels, &image, *quality == 100, *quality, *effort)?;
+    Ok(Box::new(out_buf))
+}
+
+fn convert_to_heif(input: DynamicImage, metadata: &ImageMetadata, format: &OutputFormat) -> Result<Box<dyn AsRef<[u8]> + Send + Sync>, ConversionErrorKind> {
+    
+    let quality = match format {
+        OutputFormat::HEIF(_, true) => EncoderQuality::LossLess,
+        OutputFormat::HEIF(quality, false) => EncoderQuality::Lossy(*quality),
+        _ => unreachable!("Logic Error: Found different OutputFormat")
+    };
+    
+    let out_buf = encode_heif_container(input, metadata, CompressionFormat::Hevc, quality, &[])?;
+    Ok(Box::new(out_buf))
+}
+
+/// Encodes an image into a HEIF based container (HEIC, AVIF) through libheif. An alpha
+/// channel is stored as an auxiliary image, metadata is attached to the primary image.
+/// 16-bit input is stored with 10 bits per channel, which both HEVC and AV1 support.
+fn encode_heif_container(input: DynamicImage, metadata: &ImageMetadata, format: CompressionFormat, quality: EncoderQuality, parameters: &[(&str, EncoderParameterValue)]) -> Result<Vec<u8>, ConversionErrorKind> {
+    
+    let (width, height) = (input.width(), input.height());
+    let to_10_bits = |samples: Vec<u16>| { samples.into_iter().flat_map(|sample| { (sample >> 6).to_le_bytes() }).collect::<Vec<u8>>() };
+    let (chroma, bit_depth, data) = match input {
+        DynamicImage::ImageRgba16(buffer) => (RgbChroma::HdrRgbaLe, 10, to_10_bits(buffer.into_raw())),
+        DynamicImage::ImageRgb16(buffer) => (RgbChroma::HdrRgbLe, 10, to_10_bits(buffer.into_raw())),
+        input if input.color().has_alpha() => (RgbChroma::Rgba, 8, input.into_rgba8().into_raw()),
+        input => (RgbChroma::Rgb, 8, input.into_rgb8().into_raw()),
+    };
+    
+    let mut image = Image::new(width, height, ColorSpace::Rgb(chroma)).map_err(ConversionErrorKind::encode)?;
+    image.create_plane(Channel::Interleaved, width, height, bit_depth).map_err(ConversionErrorKind::encode)?;
+    let plane = image.planes_mut().interleaved.ok_or_else(|| { ConversionErrorKind::Encode("Created image has no interleaved plane".into()) })?;
+    let row_len = data.len() / height as usize;
+    for (dst, src) in plane.data.chunks_mut(plane.stride).zip(data.chunks(row_len)) {
+        dst[..row_len].copy_from_slice(src);
+    }
+    if let Some(icc_profile) = &metadata.icc_profile {
+        image.set_color_profile_raw(&ColorProfileRaw::new(color_profile_types::PROF, icc_profile.clone())).map_err(ConversionErrorKind::metadata)?;
+    }
+    
+    let mut encoder = crate::LIBHEIF.encoder_for_format(format).map_err(ConversionErrorKind::encode)?;
+    encoder.set_quality(quality).map_err(ConversionErrorKind::encode)?;
+    for (name, value) in parameters {
+        encoder.set_parameter_value(name, value.clone()).map_err(ConversionErrorKind::encode)?;
+    }
+    
+    let mut ctx = HeifContext::new().map_err(ConversionErrorKind::encode)?;
+    let handle = ctx.encode_image(&image, &mut encoder, None).map_err(ConversionErrorKind::encode)?;
+    if let Some(exif) = &metadata.exif {
+        ctx.add_exif_metadata(&handle, exif).map_err(ConversionErrorKind::metadata)?;
+    }
+    if let Some(xmp) = &metadata.xmp {
+        ctx.add_xmp_metadata(&handle, xmp).map_err(ConversionErrorKind::metadata)?;
+    }
+    
+    ctx.write_to_bytes().map_err(ConversionErrorKind::encode)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+    use moxcms::ColorProfile;
+    use super::*;
+    
+    /// Options which only write the image itself
+    fn options(format: OutputFormat) -> ConversionOptions {
+        ConversionOptions { format, keep_metadata: false, convert_to_srgb: false, name_template: crate::DEFAULT_NAME_TEMPLATE.to_string(), collision_policy: CollisionPolicy::Overwrite, export_all_images: false, export_depth: false, export_auxiliary: false, auxiliary_format: crate::AuxiliaryFormat::PNG, live_photo_video: LivePhotoVideoAction::Ignore, transform: crate::Transform::default(), resize: crate::Resize::Original, resize_filter: crate::ResizeFilter::default() }
+    }
+    
+    /// A single pixel whose 16-bit samples cannot be represented in 8 bits
+    fn precise_pixel() -> DynamicImage {
+        DynamicImage::ImageRgba16(ImageBuffer::from_raw(1, 1, vec![0x1234, 0x5678, 0x9ABC, 0xFFFF]).unwrap())
+    }
+    
+    #[test]
+    fn display_p3_pixels_are_converted_to_srgb() {
+        let mut image = DynamicImage::ImageRgba8(RgbaImage::from_raw(2, 1, vec![200, 120, 80, 77, 255, 255, 255, 255]).unwrap());
+        let mut metadata = ImageMetadata { icc_profile: Some(ColorProfile::new_display_p3().encode().unwrap()), ..ImageMetadata::default() };
+        convert_to_srgb(&mut image, &mut metadata).unwrap();
+        
+        // The P3 colour is more saturated in sRGB, white stays white and alpha is untouched
+        let converted = image.as_bytes();
+        let expected = [213, 115, 71, 77, 255, 255, 255, 255];
+        assert!(converted.iter().zip(expected).all(|(value, expected)| { value.abs_diff(expected) <= 2 }), "{converted:?}");
+        assert_eq!(metadata.icc_profile, Some(ColorProfile::new_srgb().encode().unwrap()));
+    }
+    
+    #[test]
+    fn display_p3_pixels_keep_16_bits_in_srgb() {
+        let mut image = DynamicImage::ImageRgba16(ImageBuffer::from_raw(1, 1, vec![51400, 30840, 20560, 65535]).unwrap());
+        let mut metadata = ImageMetadata { icc_profile: Some(ColorProfile::new_display_p3().encode().unwrap()), ..ImageMetadata::default() };
+        convert_to_srgb(&mut image, &mut metadata).unwrap();
+        
+        // Same colour as the 8-bit pixel above, scaled by 257
+        let DynamicImage::ImageRgba16(converted) = image else { panic!("image lost its 16 bits"); };
+        let expected = [213 * 257, 115 * 257, 71 * 257, 65535];
+        assert!(converted.as_raw().iter().zip(expected).all(|(value, expected)| { value.abs_diff(expected) <= 2 * 257 }), "{:?}", converted.as_raw());
+    }
+    
+    #[test]
+    fn images_without_colour_profile_are_left_alone() {
+        let mut image = DynamicImage::ImageRgba8(RgbaImage::from_raw(1, 1, vec![200, 120, 80, 255]).unwrap());
+        let mut metadata = ImageMetadata::default();
+        convert_to_srgb(&mut image, &mut metadata).unwrap();
+        assert_eq!(image.as_bytes(), &[200, 120, 80, 255]);
+        assert_eq!(metadata.icc_profile, None);
+    }
+    
+    #[test]
+    fn high_bit_depth_samples_fill_16_bits() {
+        for bits in [10, 12] {
+            assert_eq!(scale_to_16_bits(0, bits), 0);
+            assert_eq!(scale_to_16_bits((1 << bits) - 1, bits), u16::MAX);
+            // The middle of the range stays in the middle
+            assert_eq!(scale_to_16_bits(1 << (bits - 1), bits) >> 12, 0x8);
+        }
+        // Samples which already use all 16 bits are unchanged
+        assert_eq!(scale_to_16_bits(0x1234, 16), 0x1234);
+    }
+    
+    #[test]
+    fn png_keeps_16_bit_precision() {
+        let options = options(OutputFormat::PNG(75, BitDepth::Sixteen, ColorMode::Rgba));
+        let output = convert_to_format(precise_pixel(), &ImageMetadata::default(), &options).unwrap();
+        
+        let mut reader = png::Decoder::new(Cursor::new((*output).as_ref())).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+        assert_eq!(data, [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xFF, 0xFF]);
+    }
+    
+    #[test]
+    fn tiff_keeps_16_bit_precision() {
+        let options = options(OutputFormat::TIFF(TIFFCompression::Deflate, BitDepth::Sixteen, ColorMode::Rgba));
+        let output = convert_to_format(precise_pixel(), &ImageMetadata::default(), &options).unwrap();
+        
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new((*output).as_ref())).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), tiff::ColorType::RGBA(16));
+        match decoder.read_image().unwrap() {
+            tiff::decoder::DecodingResult::U16(samples) => assert_eq!(samples, [0x1234, 0x5678, 0x9ABC, 0xFFFF]),
+            _ => panic!("TIFF samples are not 16 bits"),
+        }
+    }
+    
+    fn xmp_metadata() -> ImageMetadata {
+        let xmp = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"><rdf:Description rdf:about=\"\" xmlns:tiff=\"http://ns.adobe.com/tiff/1.0/\" tiff:Make=\"Apple\"/></rdf:RDF></x:xmpmeta>";
+        ImageMetadata { xmp: Some(xmp.as_bytes().to_vec()), ..ImageMetadata::default() }
+    }
+    
+    #[test]
+    fn png_xmp_is_stored_in_an_itxt_chunk() {
+        let metadata = xmp_metadata();
+        let output = convert_to_format(precise_pixel(), &metadata, &ConversionOptions { keep_metadata: true, ..options(OutputFormat::PNG(75, BitDepth::Eight, ColorMode::Auto)) }).unwrap();
+        
+        let reader = png::Decoder::new(Cursor::new((*output).as_ref())).read_info().unwrap();
+        let chunks = reader.info().utf8_text.iter().filter(|chunk| { chunk.keyword == crate::metadata::PNG_XMP_KEYWORD }).collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].get_text().unwrap().as_bytes(), metadata.xmp.unwrap());
+    }
+    
+    #[test]
+    fn tiff_xmp_is_stored_in_tag_700() {
+        let metadata = xmp_metadata();
+        let output = convert_to_format(precise_pixel(), &metadata, &ConversionOptions { keep_metadata: true, ..options(OutputFormat::TIFF(TIFFCompression::Deflate, BitDepth::Eight, ColorMode::Auto)) }).unwrap();
+        
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new((*output).as_ref())).unwrap();
+        assert_eq!(decoder.get_tag_u8_vec(Tag::Unknown(crate::metadata::TIFF_XMP_TAG)).unwrap(), metadata.xmp.unwrap());
+    }
+    
+    fn translucent_pixels() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_raw(3, 1, vec![200, 100, 0, 128, 10, 20, 30, 255, 10, 20, 30, 0]).unwrap())
+    }
+    
+    #[test]
+    fn transparency_is_composited_onto_the_background() {
+        let output = flatten_alpha(translucent_pixels(), AlphaPolicy::default()).unwrap();
+        assert_eq!(output.into_raw(), [227, 177, 127, 10, 20, 30, 255, 255, 255]);
+    }
+    
+    #[test]
+    fn premultiplied_transparency_is_blended_onto_black() {
+        let output = flatten_alpha(translucent_pixels(), AlphaPolicy::Premultiply).unwrap();
+        assert_eq!(output.into_raw(), [100, 50, 0, 10, 20, 30, 0, 0, 0]);
+    }
+    
+    #[test]
+    fn transparency_can_be_refused() {
+        assert!(matches!(flatten_alpha(translucent_pixels(), AlphaPolicy::Warn), Err(ConversionErrorKind::Unsupported(_))));
+        // Images without alpha never trigger the warning
+        let opaque = DynamicImage::ImageRgb8(RgbImage::from_raw(1, 1, vec![1, 2, 3]).unwrap());
+        assert_eq!(flatten_alpha(opaque, AlphaPolicy::Warn).unwrap().into_raw(), [1, 2, 3]);
+    }
+    
+    #[test]
+    fn opaque_alpha_planes_are_dropped() {
+        let opaque = DynamicImage::ImageRgba8(RgbaImage::from_raw(1, 1, vec![1, 2, 3, 255]).unwrap());
+        assert!(matches!(drop_opaque_alpha(opaque), DynamicImage::ImageRgb8(rgb) if *rgb == [1, 2, 3]));
+        let opaque = DynamicImage::ImageRgba16(ImageBuffer::from_raw(1, 1, vec![1, 2, 3, u16::MAX]).unwrap());
+        assert!(matches!(drop_opaque_alpha(opaque), DynamicImage::ImageRgb16(rgb) if *rgb == [1, 2, 3]));
+        assert!(matches!(drop_opaque_alpha(translucent_pixels()), DynamicImage::ImageRgba8(_)));
+    }
+    
+    fn rgba(width: u32, height: u32, color: impl Fn(u32, u32) -> [u8; 4]) -> DynamicImage {
+        DynamicImage::ImageRgba8(image::RgbaImage::from_fn(width, height, |x, y| { image::Rgba(color(x, y)) }))
+    }
+    
+    fn rgb(width: u32, height: u32, color: impl Fn(u32, u32) -> [u8; 3]) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| { Rgb(color(x, y)) }))
+    }
+    
+    #[test]
+    fn opaque_grey_is_stored_as_gray() {
+        // More shades than a palette can hold, so only the grey check decides
+        let grey = rgb(32, 32, |x, y| { [(x * 8 + y / 4) as u8; 3] });
+        assert!(matches!(stored_color(&grey, ColorMode::Auto, true), Ok(StoredColor::Gray)));
+        assert!(matches!(stored_color(&grey, ColorMode::Auto, false), Ok(StoredColor::Gray)));
+        // Fully opaque alpha is dropped as well
+        let grey = rgba(4, 4, |x, _| { [x as u8 * 60, x as u8 * 60, x as u8 * 60, 255] });
+        assert!(matches!(stored_color(&grey, ColorMode::Auto, true), Ok(StoredColor::Gray)));
+    }
+    
+    #[test]
+    fn grey_with_transparency_is_stored_as_gray_alpha() {
+        let grey = rgba(32, 32, |x, y| { [(x * 8) as u8, (x * 8) as u8, (x * 8) as u8, (y * 8) as u8] });
+        assert!(matches!(stored_color(&grey, ColorMode::Auto, true), Ok(StoredColor::GrayAlpha)));
+        // Few colours would make a palette, which TIFF cannot store
+        let grey = rgba(4, 4, |x, y| { [x as u8 * 60, x as u8 * 60, x as u8 * 60, y as u8 * 60] });
+        assert!(matches!(stored_color(&grey, ColorMode::Auto, false), Ok(StoredColor::GrayAlpha)));
+    }
+    
+    #[test]
+    fn up_to_256_colours_are_stored_as_palette_with_transparency() {
+        let image = rgba(16, 16, |x, y| { [(x * 16) as u8, (y * 16) as u8, 7, if (x, y) == (0, 0) { 0 } else { 255 }] });
+        let Ok(StoredColor::Palette(colors, indices)) = stored_color(&image, ColorMode::Auto, true) else { panic!("Expected a palette") };
+        assert_eq!(colors.len(), 256);
+        assert_eq!(indices.len(), 256);
+        assert!(indices.iter().zip(image.to_rgba8().pixels()).all(|(index, pixel)| { colors[*index as usize] == pixel.0 }));
+        
+        let Ok(png) = convert_to_png(image, &ImageMetadata::default(), &OutputFormat::PNG(50, BitDepth::Eight, ColorMode::Auto)) else { panic!("PNG encoding failed") };
+        let reader = png::Decoder::new(Cursor::new(png.as_ref().as_ref())).read_info().unwrap();
+        assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+        assert_eq!(reader.info().trns.as_deref().map(|trns| { trns[0] }), Some(0));
+    }
+    
+    #[test]
+    fn more_than_256_colours_are_stored_as_rgb() {
+        let image = rgb(257, 1, |x, _| { [(x % 256) as u8, (x / 256) as u8, 3] });
+        assert!(matches!(stored_color(&image, ColorMode::Auto, true), Ok(StoredColor::Rgb)));
+        assert!(matches!(stored_color(&image, ColorMode::Palette, true), Err(ConversionErrorKind::Unsupported(_))));
+        // One colour less still fits
+        let image = rgb(256, 1, |x, _| { [x as u8, 0, 3] });
+        assert!(matches!(stored_color(&image, ColorMode::Auto, true), Ok(StoredColor::Palette(_, _))));
+    }
+    
+    #[test]
+    fn sixteen_bit_input_is_never_stored_as_palette() {
+        let image = with_bit_depth(rgb(2, 2, |x, y| { [x as u8 * 200, y as u8 * 200, 3] }), BitDepth::Sixteen);
+        assert!(matches!(stored_color(&image, ColorMode::Auto, true), Ok(StoredColor::Rgb)));
+        assert!(matches!(stored_color(&image, ColorMode::Palette, true), Err(ConversionErrorKind::Unsupported(_))));
+        let image = with_bit_depth(rgba(2, 2, |x, y| { [x as u8 * 200, y as u8 * 200, 3, 128] }), BitDepth::Sixteen);
+        assert!(matches!(stored_color(&image, ColorMode::Auto, true), Ok(StoredColor::Rgba)));
+    }
+    
+    #[test]
+    fn tiff_rejects_palettes() {
+        let image = rgb(2, 2, |x, y| { [x as u8 * 200, y as u8 * 200, 3] });
+        assert!(matches!(stored_color(&image, ColorMode::Palette, false), Err(ConversionErrorKind::Unsupported(_))));
+        assert!(matches!(stored_color(&image, ColorMode::Auto, false), Ok(StoredColor::Rgb)));
+        let result = convert_to_tiff(image, &ImageMetadata::default(), &OutputFormat::TIFF(TIFFCompression::None, BitDepth::Eight, ColorMode::Palette));
+        assert!(matches!(result, Err(ConversionErrorKind::Unsupported(_))));
+    }
+    
+    /// Whether a colour profile describes sRGB
+    fn is_srgb_profile(icc_profile: &[u8]) -> bool {
+        use moxcms::{ColorProfile, ToneReprCurve, Xyzd};
+        
+        let Ok(profile) = ColorProfile::new_from_slice(icc_profile) else { return false; };
+        let srgb_profile = ColorProfile::new_srgb();
+        // ICC stores colorants and curve parameters as 16.16 fixed point numbers
+        let same_colorant = |a: &Xyzd, b: &Xyzd| { (a.x - b.x).abs() < 1e-3 && (a.y - b.y).abs() < 1e-3 && (a.z - b.z).abs() < 1e-3 };
+        let same_curve = |a: &Option<ToneReprCurve>, b: &Option<ToneReprCurve>| {
+            match (a, b) {
+                (Some(ToneReprCurve::Parametric(a)), Some(ToneReprCurve::Parametric(b))) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| { (a - b).abs() < 1e-3 }),
+                _ => false,
+            }
+        };
+        same_colorant(&profile.red_colorant, &srgb_profile.red_colorant)
+            && same_colorant(&profile.green_colorant, &srgb_profile.green_colorant)
+            && same_colorant(&profile.blue_colorant, &srgb_profile.blue_colorant)
+            && same_curve(&profile.red_trc, &srgb_profile.red_trc)
+            && same_curve(&profile.green_trc, &srgb_profile.green_trc)
+            && same_curve(&profile.blue_trc, &srgb_profile.blue_trc)
+    }
+    
+    #[test]
+    fn jxl_quality_100_is_bit_exact() {
+        let image = rgb(16, 16, |x, y| { [(x * 16) as u8, (y * 16) as u8, (x * y) as u8] });
+        let metadata = ImageMetadata { icc_profile: Some(ColorProfile::new_srgb().encode().unwrap()), ..Default::default() };
+        let output = convert_to_format(image.clone(), &metadata, &options(OutputFormat::JXL(100, 7))).unwrap();
+        
+        let (info, pixels) = jpegxl_rs::decoder_builder().build().unwrap().decode_with::<u8>((*output).as_ref()).unwrap();
+        assert_eq!((info.width, info.height), (16, 16));
+        assert_eq!(pixels, image.into_rgb8().into_raw());
+    }
+    
+    #[test]
+    fn jxl_quality_100_keeps_other_colour_spaces() {
+        let image = rgb(16, 16, |x, y| { [(x * 16) as u8, (y * 16) as u8, 250] });
+        let icc_profile = ColorProfile::new_display_p3().encode().unwrap();
+        let metadata = ImageMetadata { icc_profile: Some(icc_profile.clone()), ..Default::default() };
+        let output = convert_to_format(image.clone(), &metadata, &options(OutputFormat::JXL(100, 7))).unwrap();
+        
+        // Neither converted to sRGB nor clipped, the Display P3 samples come back unchanged with their profile
+        let (info, pixels) = jpegxl_rs::decoder_builder().icc_profile(true).build().unwrap().decode_with::<u8>((*output).as_ref()).unwrap();
+        assert_eq!(info.icc_profile.as_deref(), Some(icc_profile.as_slice()));
+        assert_eq!(pixels, image.into_rgb8().into_raw());
+    }
+    
+    #[test]
+    fn jxl_lossy_qualities_keep_the_colour_profile() {
+        let image = rgb(16, 16, |x, y| { [(x * 16) as u8, (y * 16) as u8, 250] });
+        let icc_profile = ColorProfile::new_display_p3().encode().unwrap();
+        let metadata = ImageMetadata { icc_profile: Some(icc_profile.clone()), ..Default::default() };
+        let high = convert_to_format(image.clone(), &metadata, &options(OutputFormat::JXL(90, 7))).unwrap();
+        let low = convert_to_format(image, &metadata, &options(OutputFormat::JXL(20, 7))).unwrap();
+        assert!((*low).as_ref().len() < (*high).as_ref().len());
+        
+        let (info, _) = jpegxl_rs::decoder_builder().icc_profile(true).build().unwrap().decode_with::<u8>((*high).as_ref()).unwrap();
+        assert!(info.icc_profile.is_some_and(|profile| { !is_srgb_profile(&profile) }));
+    }
+    
+    #[test]
+    fn outputs_never_replace_the_source() {
+        let dir = std::env::temp_dir().join(format!("unheic-conversion-source-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let source = dir.join("IMG_0001.heic");
+        std::fs::write(&source, b"source").unwrap();
+        
+        let same_file = dir.join("sub").join("..").join("IMG_0001.heic");
+        assert!(matches!(refuse_source_overwrite(&source, &same_file, CollisionPolicy::Overwrite), Err(ConversionErrorKind::Write(_))));
+        assert!(matches!(refuse_source_overwrite(&source, &same_file, CollisionPolicy::Skip), Err(ConversionErrorKind::Write(_))));
+        // Suffixed names move out of the way of the source
+        assert!(refuse_source_overwrite(&source, &same_file, CollisionPolicy::AutoSuffix).is_ok());
+        assert!(refuse_source_overwrite(&source, &dir.join("IMG_0001.jpg"), CollisionPolicy::Overwrite).is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn auxiliary_outputs_never_replace_the_source() {
+        let dir = std::env::temp_dir().join(format!("unheic-conversion-auxiliary-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // A HEIF source which happens to carry the name of its own depth map
+        let source = dir.join("IMG_0001_depth.png");
+        std::fs::write(&source, b"source").unwrap();
+        
+        let out_file_path = auxiliary_output_path(&dir.join("IMG_0001.jpg"), "depth", AuxiliaryFormat::PNG);
+        assert_eq!(out_file_path, source);
+        assert!(matches!(refuse_source_overwrite(&source, &out_file_path, CollisionPolicy::Overwrite), Err(ConversionErrorKind::Write(_))));
+        assert!(refuse_source_overwrite(&source, &out_file_path, CollisionPolicy::AutoSuffix).is_ok());
+        assert_eq!(auxiliary_output_path(&dir.join("IMG_0001.jpg"), "depth", AuxiliaryFormat::TIFF), dir.join("IMG_0001_depth.tiff"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn auxiliary_maps_follow_the_transform() {
+        // 3 × 2 map whose samples encode their position:
+        // 0 1 2
+        // 3 4 5
+        let map = GrayImage16::from_fn(3, 2, |x, y| { image::Luma([(y * 3 + x) as u16]) });
+        let transformed = |transform: Transform| {
+            let transformed = transform_auxiliary(map.clone(), &transform);
+            (transformed.dimensions(), transformed.into_raw())
+        };
+        assert_eq!(transformed(Transform::default()), ((3, 2), vec![0, 1, 2, 3, 4, 5]));
+        assert_eq!(transformed(Transform { rotation: Rotation::Clockwise90, ..Transform::default() }), ((2, 3), vec![3, 0, 4, 1, 5, 2]));
+        assert_eq!(transformed(Transform { rotation: Rotation::Rotate180, ..Transform::default() }), ((3, 2), vec![5, 4, 3, 2, 1, 0]));
+        assert_eq!(transformed(Transform { rotation: Rotation::Clockwise270, ..Transform::default() }), ((2, 3), vec![2, 5, 1, 4, 0, 3]));
+        assert_eq!(transformed(Transform { flip_horizontal: true, ..Transform::default() }), ((3, 2), vec![2, 1, 0, 5, 4, 3]));
+        assert_eq!(transformed(Transform { flip_vertical: true, ..Transform::default() }), ((3, 2), vec![3, 4, 5, 0, 1, 2]));
+        
+        // Rotating and mirroring transposes the map, the square crop then keeps its last two rows
+        let transform = Transform { rotation: Rotation::Clockwise90, flip_horizontal: true, crop: crate::settings::CropAspect::Square, crop_anchor: crate::settings::CropAnchor::End, ..Transform::default() };
+        assert_eq!(transformed(transform), ((2, 2), vec![1, 4, 2, 5]));
+        let transform = Transform { crop: crate::settings::CropAspect::Square, crop_anchor: crate::settings::CropAnchor::Start, ..Transform::default() };
+        assert_eq!(transformed(transform), ((2, 2), vec![0, 1, 3, 4]));
+    }
+    
+    #[test]
+    fn output_size_is_transformed_before_resizing() {
+        let options = ConversionOptions { transform: Transform { rotation: Rotation::Clockwise90, ..Transform::default() }, resize: Resize::LongestEdge(400), ..options(OutputFormat::default_for(crate::settings::OutputFormatKind::PNG)) };
+        assert_eq!(output_size(800, 600, &options), (300, 400));
+        assert_eq!(output_size(800, 600, &ConversionOptions { resize: Resize::Original, ..options.clone() }), (600, 800));
+    }
+}
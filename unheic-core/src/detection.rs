@@ -0,0 +1,100 @@
+use std::{fs::File, io::Read, path::Path};
+
+/// Brands of the ISO base media file format which libheif reads: HEIC stills and sequences,
+/// generic HEIF, AVIF and AVC-coded HEIF
+const HEIF_BRANDS: [&[u8]; 15] = [
+    b"heic", b"heix", b"heim", b"heis",
+    b"hevc", b"hevx", b"hevm", b"hevs",
+    b"mif1", b"mif2", b"msf1",
+    b"avif", b"avis",
+    b"avci", b"avcs",
+];
+
+/// File extensions of the HEIF family, lowercase, e.g. for the filters of a file dialog
+pub const HEIF_EXTENSIONS: [&str; 9] = ["heic", "heif", "hif", "heics", "heifs", "avif", "avifs", "avci", "avcs"];
+
+/// Reads the `ftyp` box of the file and returns its HEIF family brand, or none if the file
+/// is not a HEIF image. The major brand wins, otherwise the first compatible brand is used.
+pub fn detect_heif_brand(path: &Path) -> Option<String> {
+    let mut header = Vec::new();
+    // The ftyp box comes first and is small, unless a file lists an unusual number of brands
+    File::open(path).ok()?.take(256).read_to_end(&mut header).ok()?;
+    heif_brand(&header)
+}
+
+fn heif_brand(header: &[u8]) -> Option<String> {
+    if header.len() < 16 || &header[4..8] != b"ftyp" { return None; }
+    // Sizes below the minimum (0 = until end of file, 1 = 64-bit size) are not expected for ftyp
+    let box_size = u32::from_be_bytes(header[0..4].try_into().ok()?) as usize;
+    let box_end = box_size.clamp(16, header.len());
+    let major_brand = &header[8..12];
+    // Bytes 12..16 hold the minor version, the compatible brands follow
+    let compatible_brands = header[16..box_end].chunks_exact(4);
+    std::iter::once(major_brand)
+        .chain(compatible_brands)
+        .find(|brand| { HEIF_BRANDS.contains(brand) })
+        .map(|brand| { String::from_utf8_lossy(brand).to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// An ftyp box with the given brands and minor version 0
+    fn ftyp(major_brand: &[u8; 4], compatible_brands: &[&[u8; 4]]) -> Vec<u8> {
+        let size = 16 + 4 * compatible_brands.len() as u32;
+        let mut header = [size.to_be_bytes().as_slice(), b"ftyp", major_brand, &[0; 4]].concat();
+        header.extend(compatible_brands.iter().copied().flatten());
+        header
+    }
+    
+    /// Writes `data` into a file of its own, as tests run in parallel
+    fn write_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("unheic-detection-{}-{name}", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+    
+    #[test]
+    fn fixture_is_detected() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../res/test.heic");
+        assert_eq!(detect_heif_brand(&path).as_deref(), Some("heic"));
+    }
+    
+    #[test]
+    fn renamed_jpeg_is_rejected() {
+        // SOI, a JFIF APP0 segment and the start of the image data
+        let jpeg = [&[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10][..], b"JFIF\0", &[1, 1, 0, 0, 1, 0, 1, 0, 0, 0xFF, 0xDB, 0x00, 0x43], &[0; 64]].concat();
+        let path = write_file("renamed.heic", &jpeg);
+        assert_eq!(detect_heif_brand(&path), None);
+        let _ = std::fs::remove_file(&path);
+    }
+    
+    #[test]
+    fn truncated_files_are_rejected() {
+        let header = ftyp(b"heic", &[b"mif1", b"heic"]);
+        for length in [0, 4, 8, 12, 15] {
+            let path = write_file(&format!("truncated-{length}.heic"), &header[..length]);
+            assert_eq!(detect_heif_brand(&path), None, "{length} bytes");
+            let _ = std::fs::remove_file(&path);
+        }
+        // The major brand is complete, the list of compatible brands is cut off
+        assert_eq!(heif_brand(&ftyp(b"mp42", &[b"isom", b"mif1"])[..22]), None);
+        assert_eq!(heif_brand(&ftyp(b"heic", &[b"mif1"])[..16]).as_deref(), Some("heic"));
+        assert_eq!(detect_heif_brand(Path::new("/does/not/exist.heic")), None);
+    }
+    
+    #[test]
+    fn compatible_brands_are_used_without_a_heif_major_brand() {
+        assert_eq!(heif_brand(&ftyp(b"isom", &[b"mif1"])).as_deref(), Some("mif1"));
+        assert_eq!(heif_brand(&ftyp(b"iso8", &[b"isom", b"msf1"])).as_deref(), Some("msf1"));
+        let path = write_file("compatible.heif", &ftyp(b"mp42", &[b"isom", b"mif1"]));
+        assert_eq!(detect_heif_brand(&path).as_deref(), Some("mif1"));
+        let _ = std::fs::remove_file(&path);
+        // The major brand wins over compatible brands
+        assert_eq!(heif_brand(&ftyp(b"avif", &[b"mif1", b"heic"])).as_deref(), Some("avif"));
+        // Plain MP4 and QuickTime files are no HEIF images
+        assert_eq!(heif_brand(&ftyp(b"isom", &[b"isom", b"mp41"])), None);
+        assert_eq!(heif_brand(&ftyp(b"qt  ", &[b"qt  "])), None);
+    }
+}
@@ -0,0 +1,190 @@
+use std::{error::Error, fmt, path::{Path, PathBuf}, sync::Arc};
+use libheif_rs::{HeifError, HeifErrorCode};
+
+/// The message of an error, and the error itself if it was raised by a library or the OS.
+/// Two details are equal if their messages are, the source is only kept for `Error::source`.
+#[derive(Clone, Debug)]
+pub struct ErrorDetails {
+    message: String,
+    source: Option<Arc<dyn Error + Send + Sync>>,
+}
+
+impl ErrorDetails {
+    pub fn caused_by(err: impl Error + Send + Sync + 'static) -> Self {
+        Self::with_message(err.to_string(), err)
+    }
+    
+    /// Keeps `err` as source, but describes it with a message of its own
+    pub(crate) fn with_message(message: String, err: impl Error + Send + Sync + 'static) -> Self {
+        Self { message, source: Some(Arc::new(err)) }
+    }
+    
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    
+    /// The underlying error, none for errors found by the conversion itself
+    pub fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|err| -> &(dyn Error + 'static) { err })
+    }
+}
+
+impl PartialEq for ErrorDetails {
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message
+    }
+}
+
+impl From<String> for ErrorDetails {
+    fn from(message: String) -> Self {
+        Self { message, source: None }
+    }
+}
+
+impl From<&str> for ErrorDetails {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl fmt::Display for ErrorDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// What went wrong while converting an image, with the message and source of the underlying error.
+/// Functions working on in-memory images return this directly, everything taking a
+/// path wraps it into a `ConversionError`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConversionErrorKind {
+    /// The source file could not be read
+    IO(ErrorDetails),
+    /// The source is not a valid HEIF image or libheif failed to decode it
+    Decode(ErrorDetails),
+    /// The source uses a HEIF feature libheif does not support
+    Unsupported(ErrorDetails),
+    /// The encoder of the output format rejected the image or its settings
+    Encode(ErrorDetails),
+    /// Metadata or the colour profile could not be read, transformed or embedded
+    Metadata(ErrorDetails),
+    /// The converted image could not be written
+    Write(ErrorDetails),
+}
+
+impl ConversionErrorKind {
+    /// Classifies an error libheif raised while reading or decoding a source image
+    pub(crate) fn decode(err: HeifError) -> Self {
+        match err.code {
+            HeifErrorCode::UnsupportedFileType | HeifErrorCode::UnsupportedFeature => Self::Unsupported(ErrorDetails::caused_by(err)),
+            _ => Self::Decode(ErrorDetails::caused_by(err)),
+        }
+    }
+    
+    pub(crate) fn io(err: impl Error + Send + Sync + 'static) -> Self {
+        Self::IO(ErrorDetails::caused_by(err))
+    }
+    
+    pub(crate) fn encode(err: impl Error + Send + Sync + 'static) -> Self {
+        Self::Encode(ErrorDetails::caused_by(err))
+    }
+    
+    pub(crate) fn metadata(err: impl Error + Send + Sync + 'static) -> Self {
+        Self::Metadata(ErrorDetails::caused_by(err))
+    }
+    
+    pub(crate) fn write(err: impl Error + Send + Sync + 'static) -> Self {
+        Self::Write(ErrorDetails::caused_by(err))
+    }
+    
+    pub(crate) fn at(self, path: &Path) -> ConversionError {
+        ConversionError { path: path.to_path_buf(), kind: self }
+    }
+    
+    /// The message of the underlying error
+    pub fn message(&self) -> &str {
+        self.details().message()
+    }
+    
+    fn details(&self) -> &ErrorDetails {
+        match self {
+            Self::IO(details) |
+            Self::Decode(details) |
+            Self::Unsupported(details) |
+            Self::Encode(details) |
+            Self::Metadata(details) |
+            Self::Write(details) => details,
+        }
+    }
+}
+
+impl fmt::Display for ConversionErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IO(message) => write!(f, "Failed to read the file: {message}"),
+            Self::Decode(message) => write!(f, "Failed to decode the image: {message}"),
+            Self::Unsupported(message) => write!(f, "Unsupported image: {message}"),
+            Self::Encode(message) => write!(f, "Failed to encode the image: {message}"),
+            Self::Metadata(message) => write!(f, "Failed to process the metadata: {message}"),
+            Self::Write(message) => write!(f, "Failed to write the output file: {message}"),
+        }
+    }
+}
+
+/// A failed conversion of a single source file
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConversionError {
+    /// The source file the error belongs to
+    pub path: PathBuf,
+    pub kind: ConversionErrorKind,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.kind)
+    }
+}
+
+impl Error for ConversionErrorKind {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.details().source()
+    }
+}
+
+impl Error for ConversionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.kind.source()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn errors_name_the_file_and_cause() {
+        let err = ConversionErrorKind::Encode("Quality out of range".into()).at(Path::new("Fotos/IMG_0001.heic"));
+        assert_eq!(err.kind.message(), "Quality out of range");
+        assert_eq!(err.to_string(), "Fotos/IMG_0001.heic: Failed to encode the image: Quality out of range");
+    }
+    
+    #[test]
+    fn source_is_the_underlying_error() {
+        let err = ConversionErrorKind::write(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied")).at(Path::new("a.heic"));
+        let source = err.source().expect("io::Error is kept as source");
+        assert_eq!(source.downcast_ref::<std::io::Error>().map(|err| { err.kind() }), Some(std::io::ErrorKind::PermissionDenied));
+        assert_eq!(err.kind.message(), "denied");
+        assert_eq!(err.to_string(), "a.heic: Failed to write the output file: denied");
+        
+        // Errors found by the conversion itself have no source
+        assert!(ConversionErrorKind::Unsupported("No palette".into()).source().is_none());
+    }
+    
+    #[test]
+    fn errors_are_compared_by_message() {
+        let io_error = || { std::io::Error::other("disk full") };
+        assert_eq!(ConversionErrorKind::write(io_error()), ConversionErrorKind::write(io_error()));
+        assert_eq!(ConversionErrorKind::write(io_error()), ConversionErrorKind::Write("disk full".into()));
+        assert_ne!(ConversionErrorKind::write(io_error()), ConversionErrorKind::IO("disk full".into()));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{ffi::c_char, mem::MaybeUninit, ptr::null};
+use jpegxl_sys::common::types::{JxlBool, JxlBoxType, JxlDataType, JxlEndianness, JxlPixelFormat};
+use jpegxl_sys::encoder::encode::{
+    JxlColorEncodingSetToSRGB, JxlEncoder, JxlEncoderAddBox, JxlEncoderAddImageFrame, JxlEncoderCloseInput, JxlEncoderCreate, JxlEncoderDestroy,
+    JxlEncoderDistanceFromQuality, JxlEncoderFrameSettingId, JxlEncoderFrameSettingsCreate, JxlEncoderFrameSettingsSetOption, JxlEncoderGetError,
+    JxlEncoderInitBasicInfo, JxlEncoderProcessOutput, JxlEncoderSetBasicInfo, JxlEncoderSetColorEncoding, JxlEncoderSetFrameDistance,
+    JxlEncoderSetFrameLossless, JxlEncoderSetICCProfile, JxlEncoderStatus, JxlEncoderUseBoxes,
+};
+use crate::error::ConversionErrorKind;
+
+/// Interleaved RGB or RGBA samples
+pub(crate) enum JxlPixels {
+    Eight(Vec<u8>),
+    Sixteen(Vec<u16>),
+}
+
+/// What is written into the file besides the pixels
+pub(crate) struct JxlImage<'a> {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) has_alpha: bool,
+    /// Tags the pixels with their own colour space, sRGB is assumed without one
+    pub(crate) icc_profile: Option<&'a [u8]>,
+    /// Metadata boxes (`Exif`, `xml `), stored Brotli-compressed
+    pub(crate) boxes: Vec<([u8; 4], &'a [u8])>,
+}
+
+/// Owns the libjxl encoder for the duration of one image
+struct Encoder(*mut JxlEncoder);
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe { JxlEncoderDestroy(self.0) };
+    }
+}
+
+impl Encoder {
+    fn check(&self, status: JxlEncoderStatus, step: &str) -> Result<(), ConversionErrorKind> {
+        match status {
+            JxlEncoderStatus::Success => Ok(()),
+            _ => Err(ConversionErrorKind::Encode(format!("libjxl could not {step} ({:?})", unsafe { JxlEncoderGetError(self.0) }).into())),
+        }
+    }
+}
+
+/// Encodes `pixels` with libjxl directly. The `jpegxl-rs` encoder always tags its output as sRGB,
+/// which would either clip wide-gamut sources like Display P3 or mislabel their pixels.
+/// Lossless files keep the original samples and profile, lossy ones are stored as XYB with
+/// the profile attached, so decoders can map them back without losing gamut.
+pub(crate) fn encode(pixels: &JxlPixels, image: &JxlImage, lossless: bool, quality: u8, effort: u8) -> Result<Vec<u8>, ConversionErrorKind> {
+    let encoder = Encoder(unsafe { JxlEncoderCreate(null()) });
+    if encoder.0.is_null() { return Err(ConversionErrorKind::Encode("libjxl could not create an encoder".into())); }
+
+    let (bits, data_type, data, size) = match pixels {
+        JxlPixels::Eight(samples) => (8, JxlDataType::Uint8, samples.as_ptr().cast(), size_of_val(samples.as_slice())),
+        JxlPixels::Sixteen(samples) => (16, JxlDataType::Uint16, samples.as_ptr().cast(), size_of_val(samples.as_slice())),
+    };
+
+    if !image.boxes.is_empty() {
+        encoder.check(unsafe { JxlEncoderUseBoxes(encoder.0) }, "enable metadata boxes")?;
+    }
+
+    let mut basic_info = unsafe {
+        let mut basic_info = MaybeUninit::uninit();
+        JxlEncoderInitBasicInfo(basic_info.as_mut_ptr());
+        basic_info.assume_init()
+    };
+    basic_info.xsize = image.width;
+    basic_info.ysize = image.height;
+    basic_info.bits_per_sample = bits;
+    basic_info.exponent_bits_per_sample = 0;
+    basic_info.num_color_channels = 3;
+    basic_info.num_extra_channels = u32::from(image.has_alpha);
+    basic_info.alpha_bits = if image.has_alpha { bits } else { 0 };
+    // Lossless has to keep the samples as they are instead of transforming them into XYB
+    basic_info.uses_original_profile = JxlBool::from(lossless);
+    encoder.check(unsafe { JxlEncoderSetBasicInfo(encoder.0, &basic_info) }, "set the image information")?;
+
+    match image.icc_profile {
+        Some(icc_profile) => encoder.check(unsafe { JxlEncoderSetICCProfile(encoder.0, icc_profile.as_ptr(), icc_profile.len()) }, "set the colour profile")?,
+        None => {
+            let color_encoding = unsafe {
+                let mut color_encoding = MaybeUninit::uninit();
+                JxlColorEncodingSetToSRGB(color_encoding.as_mut_ptr(), false);
+                color_encoding.assume_init()
+            };
+            encoder.check(unsafe { JxlEncoderSetColorEncoding(encoder.0, &color_encoding) }, "set the colour encoding")?;
+        },
+    }
+
+    let frame_settings = unsafe { JxlEncoderFrameSettingsCreate(encoder.0, null()) };
+    encoder.check(unsafe { JxlEncoderFrameSettingsSetOption(frame_settings, JxlEncoderFrameSettingId::Effort, i64::from(effort.clamp(1, 9))) }, "set the effort")?;
+    if lossless {
+        encoder.check(unsafe { JxlEncoderSetFrameLossless(frame_settings, true) }, "enable lossless mode")?;
+    } else {
+        encoder.check(unsafe { JxlEncoderSetFrameDistance(frame_settings, JxlEncoderDistanceFromQuality(f32::from(quality))) }, "set the quality")?;
+    }
+
+    for (box_type, contents) in &image.boxes {
+        let box_type = JxlBoxType(box_type.map(|byte| { byte as c_char }));
+        encoder.check(unsafe { JxlEncoderAddBox(encoder.0, &box_type, contents.as_ptr(), contents.len(), JxlBool::True) }, "add a metadata box")?;
+    }
+
+    let pixel_format = JxlPixelFormat { num_channels: if image.has_alpha { 4 } else { 3 }, data_type, endianness: JxlEndianness::Native, align: 0 };
+    encoder.check(unsafe { JxlEncoderAddImageFrame(frame_settings, &pixel_format, data, size) }, "add the image")?;
+    // Closes the boxes as well
+    unsafe { JxlEncoderCloseInput(encoder.0) };
+
+    let mut output = vec![0u8; 64 * 1024];
+    let mut written = 0;
+    loop {
+        let mut next_out = unsafe { output.as_mut_ptr().add(written) };
+        let mut avail_out = output.len() - written;
+        let status = unsafe { JxlEncoderProcessOutput(encoder.0, &mut next_out, &mut avail_out) };
+        written = output.len() - avail_out;
+        match status {
+            JxlEncoderStatus::NeedMoreOutput => output.resize(output.len() * 2, 0),
+            status => {
+                encoder.check(status, "write the file")?;
+                break;
+            },
+        }
+    }
+    output.truncate(written);
+    Ok(output)
+}
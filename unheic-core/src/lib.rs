@@ -0,0 +1,75 @@
+//! Conversion engine behind UnHEIC. It decodes HEIF images and re-encodes them into
+//! other formats, without depending on any user interface.
+
+use std::{path::Path, sync::LazyLock};
+
+use image::{Rgba, RgbaImage};
+use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+mod auxiliary;
+mod batch;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod control;
+mod conversion;
+mod detection;
+mod error;
+mod jxl;
+mod live_photo;
+mod metadata;
+mod naming;
+mod settings;
+
+pub use batch::{BatchItem, BatchSummary, BatchTarget, convert_batch};
+pub use control::CancellationToken;
+pub use conversion::{ConversionOutput, convert_file, convert_to_format, convert_to_srgb, decode_image};
+pub use detection::{HEIF_EXTENSIONS, detect_heif_brand};
+pub use error::{ConversionError, ConversionErrorKind, ErrorDetails};
+pub use live_photo::{LivePhotoVideo, find_live_photo_still};
+pub use metadata::ImageMetadata;
+pub use naming::{common_root, mirrored_output_dir};
+pub use settings::{AlphaPolicy, AlphaPolicyKind, AuxiliaryFormat, BitDepth, ChromaSubsampling, CollisionPolicy, ColorMode, ConversionOptions, CropAnchor, CropAspect, DEFAULT_NAME_TEMPLATE, LivePhotoVideoAction, OutputFormat, OutputFormatKind, Resize, ResizeFilter, ResizeKind, Rotation, TIFFCompression, Transform};
+
+pub(crate) static LIBHEIF: LazyLock<LibHeif> = LazyLock::new(|| { LibHeif::new() });
+
+/// Preview of a source file
+pub struct Thumbnail {
+    pub image: RgbaImage,
+    /// Number of top-level images in the container, more than one for bursts and collections
+    pub image_count: usize,
+    /// Companion video if the file is the still of a Live Photo
+    pub live_photo_video: Option<LivePhotoVideo>,
+}
+
+/// Decodes the primary image scaled down to the given height, for previews
+pub fn decode_thumbnail(path: &Path, height: u32) -> Result<Thumbnail, ConversionError> {
+    let data = std::fs::read(path).map_err(|err| { ConversionErrorKind::io(err).at(path) })?;
+    let ctx = HeifContext::read_from_bytes(&data).map_err(|err| { ConversionErrorKind::decode(err).at(path) })?;
+    let image_count = ctx.number_of_top_level_images();
+    let handle = ctx.primary_image_handle().map_err(|err| { ConversionErrorKind::decode(err).at(path) })?;
+    let live_photo_video = live_photo::find_live_photo_video(path, &ImageMetadata::from_handle(&handle));
+    let image = LIBHEIF.decode(
+        &handle,
+        ColorSpace::Rgb(RgbChroma::Rgba),
+        None,
+    ).map_err(|err| { ConversionErrorKind::decode(err).at(path) })?;
+    
+    let ratio: f32 = height as f32 / image.height() as f32;
+    let thumbnail = image.scale((image.width() as f32 * ratio) as u32, (image.height() as f32 * ratio) as u32, None).map_err(|err| { ConversionErrorKind::decode(err).at(path) })?;
+    let plane = thumbnail.planes().interleaved.ok_or_else(|| { ConversionErrorKind::Decode("Decoded image has no interleaved plane".into()).at(path) })?;
+    let preview = RgbaImage::from_par_fn(thumbnail.width(), thumbnail.height(), |x, y| {
+        let x = x as usize;
+        let y = y as usize;
+    
+        let row_start = y * plane.stride;
+        let pixel_start = row_start + x * 4;
+    
+        Rgba([
+            plane.data[pixel_start],
+            plane.data[pixel_start + 1],
+            plane.data[pixel_start + 2],
+            plane.data[pixel_start + 3],
+        ])
+    });
+    Ok(Thumbnail { image: preview, image_count, live_photo_video })
+}
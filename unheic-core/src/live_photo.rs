@@ -0,0 +1,278 @@
+use std::{fs::File, io::{Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+use exif::{In, Tag, Value};
+use crate::metadata::ImageMetadata;
+
+/// Key of the QuickTime metadata item linking a Live Photo video to its still
+const QUICKTIME_CONTENT_IDENTIFIER_KEY: &[u8] = b"com.apple.quicktime.content.identifier";
+/// Tag of the content identifier in the Apple maker note of the still
+const APPLE_CONTENT_IDENTIFIER_TAG: u16 = 0x0011;
+/// Upper bound for the moov atom, which only holds track tables and metadata
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The video half of an Apple Live Photo
+#[derive(Clone, Debug, PartialEq)]
+pub struct LivePhotoVideo {
+    pub path: PathBuf,
+    /// Identifier shared by the still and the video, if the video carries one
+    pub content_identifier: Option<String>,
+}
+
+/// Looks for the companion video of a Live Photo still, which iPhones export with the same
+/// file name (`IMG_1234.HEIC` + `IMG_1234.MOV`). If both files carry a content identifier,
+/// they have to match, so an unrelated video with the same name is not picked up.
+pub(crate) fn find_live_photo_video(still: &Path, metadata: &ImageMetadata) -> Option<LivePhotoVideo> {
+    let video_path = sibling_with_extension(still, &["mov"])?;
+    let content_identifier = video_content_identifier(&video_path);
+    let still_identifier = still_content_identifier(metadata);
+    if let (Some(video_identifier), Some(still_identifier)) = (&content_identifier, &still_identifier) && video_identifier != still_identifier {
+        return None;
+    }
+    Some(LivePhotoVideo { path: video_path, content_identifier: content_identifier.or(still_identifier) })
+}
+
+/// The still next to a Live Photo video (`IMG_1234.MOV` → `IMG_1234.HEIC`), if there is one
+pub fn find_live_photo_still(video: &Path) -> Option<PathBuf> {
+    if !video.extension().is_some_and(|extension| { extension.eq_ignore_ascii_case("mov") }) { return None; }
+    sibling_with_extension(video, &crate::HEIF_EXTENSIONS)
+}
+
+/// A file with the same name as `path` and one of the given extensions, whose case is ignored.
+/// The directory is always listed, so the result is spelled like the file on disk, even on
+/// case-insensitive file systems where any spelling would exist.
+fn sibling_with_extension(path: &Path, extensions: &[&str]) -> Option<PathBuf> {
+    let file_stem = path.file_stem()?;
+    let parent = path.parent().filter(|parent| { !parent.as_os_str().is_empty() }).unwrap_or(Path::new("."));
+    std::fs::read_dir(parent).ok()?
+        .filter_map(|entry| { entry.ok() })
+        .map(|entry| { path.with_file_name(entry.file_name()) })
+        .find(|candidate| {
+            candidate.file_stem() == Some(file_stem)
+                && candidate.extension().is_some_and(|extension| { extensions.iter().any(|wanted| { extension.eq_ignore_ascii_case(wanted) }) })
+                && candidate.is_file()
+        })
+}
+
+/// Reads the content identifier from the Apple maker note of the EXIF block
+fn still_content_identifier(metadata: &ImageMetadata) -> Option<String> {
+    let exif = crate::metadata::parse_exif(metadata.exif.as_deref()?).ok()?;
+    let maker_note = match &exif.get_field(Tag::MakerNote, In::PRIMARY)?.value {
+        Value::Undefined(maker_note, _) => maker_note.clone(),
+        _ => return None,
+    };
+    // "Apple iOS\0", a version and the byte order, followed by an IFD with offsets relative to the maker note
+    if !maker_note.starts_with(b"Apple iOS\0") || maker_note.get(12..14)? != b"MM" { return None; }
+    let read_u16 = |offset: usize| { maker_note.get(offset..offset + 2).map(|bytes| { u16::from_be_bytes([bytes[0], bytes[1]]) }) };
+    let read_u32 = |offset: usize| { maker_note.get(offset..offset + 4).map(|bytes| { u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) }) };
+    let entry_count = read_u16(14)? as usize;
+    for idx in 0..entry_count {
+        let entry = 16 + idx * 12;
+        // Only ASCII (type 2) values are expected for the identifier
+        if read_u16(entry)? != APPLE_CONTENT_IDENTIFIER_TAG || read_u16(entry + 2)? != 2 { continue; }
+        let count = read_u32(entry + 4)? as usize;
+        let value_offset = if count <= 4 { entry + 8 } else { read_u32(entry + 8)? as usize };
+        return ascii_value(maker_note.get(value_offset..value_offset + count)?);
+    }
+    None
+}
+
+/// Reads the content identifier from the `moov/meta` keys and item list of a QuickTime file
+fn video_content_identifier(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let moov = read_top_level_atom(&mut file, b"moov")?;
+    let meta = child_atoms(&moov).find(|(kind, _)| { kind == b"meta" })?.1;
+    let keys = child_atoms(meta).find(|(kind, _)| { kind == b"keys" })?.1;
+    let item_list = child_atoms(meta).find(|(kind, _)| { kind == b"ilst" })?.1;
+    
+    // keys: version and flags, the entry count, then (size, namespace, name) entries numbered from 1
+    let mut key_index = None;
+    let mut offset = 8;
+    for index in 1..=u32::from_be_bytes(keys.get(4..8)?.try_into().ok()?) {
+        let size = u32::from_be_bytes(keys.get(offset..offset + 4)?.try_into().ok()?) as usize;
+        if size < 8 { return None; }
+        if keys.get(offset + 8..offset + size)? == QUICKTIME_CONTENT_IDENTIFIER_KEY {
+            key_index = Some(index);
+            break;
+        }
+        offset += size;
+    }
+    let key_index = key_index?.to_be_bytes();
+    
+    // Items are atoms named after their key index, holding a data atom with type, locale and value
+    let item = child_atoms(item_list).find(|(kind, _)| { *kind == key_index })?.1;
+    let data = child_atoms(item).find(|(kind, _)| { kind == b"data" })?.1;
+    ascii_value(data.get(8..)?)
+}
+
+/// Reads the payload of the first top-level atom of the given type, seeking over all others
+fn read_top_level_atom(file: &mut File, wanted: &[u8; 4]) -> Option<Vec<u8>> {
+    loop {
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header).ok()?;
+        let mut size = u32::from_be_bytes(header[0..4].try_into().ok()?) as u64;
+        let mut header_size = 8;
+        if size == 1 {
+            let mut extended = [0u8; 8];
+            file.read_exact(&mut extended).ok()?;
+            size = u64::from_be_bytes(extended);
+            header_size = 16;
+        }
+        // Size 0 means the atom extends to the end of the file
+        if size == 0 { size = file.metadata().ok()?.len().checked_sub(file.stream_position().ok()? - header_size)?; }
+        let payload_size = size.checked_sub(header_size)?;
+        if &header[4..8] == wanted {
+            if payload_size > MAX_MOOV_SIZE { return None; }
+            let mut payload = vec![0; payload_size as usize];
+            file.read_exact(&mut payload).ok()?;
+            return Some(payload);
+        }
+        file.seek(SeekFrom::Current(payload_size as i64)).ok()?;
+    }
+}
+
+/// Iterates over the (type, payload) pairs of the atoms packed into `data`
+fn child_atoms(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?) as usize;
+        if size < 8 { return None; }
+        let kind: [u8; 4] = data.get(offset + 4..offset + 8)?.try_into().ok()?;
+        let payload = data.get(offset + 8..offset.checked_add(size)?)?;
+        offset += size;
+        Some((kind, payload))
+    })
+}
+
+fn ascii_value(bytes: &[u8]) -> Option<String> {
+    let value = String::from_utf8_lossy(bytes).trim_end_matches('\0').trim().to_string();
+    if value.is_empty() { None } else { Some(value) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const IDENTIFIER: &str = "6A1F0F0B-3C62-4E9B-9A8C-2D1C3E4F5A6B";
+    
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("unheic-live-photo-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+    
+    fn atom(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        [&(8 + payload.len() as u32).to_be_bytes()[..], kind, payload].concat()
+    }
+    
+    /// A QuickTime file as iPhones write it: ftyp, a large mdat with the 64-bit size form, then
+    /// moov with the metadata keys and items. The identifier is the second of two keys.
+    fn quicktime(identifier: &str) -> Vec<u8> {
+        let key = |name: &[u8]| { [&(8 + name.len() as u32).to_be_bytes()[..], b"mdta", name].concat() };
+        let keys = [&[0u8; 4][..], &2u32.to_be_bytes(), &key(b"com.apple.quicktime.make"), &key(QUICKTIME_CONTENT_IDENTIFIER_KEY)].concat();
+        // data: type 1 (UTF-8) and an empty locale before the value
+        let item = |index: u32, value: &[u8]| { atom(&index.to_be_bytes(), &atom(b"data", &[&[0, 0, 0, 1, 0, 0, 0, 0][..], value].concat())) };
+        let item_list = [item(1, b"Apple"), item(2, identifier.as_bytes())].concat();
+        let meta = [atom(b"hdlr", &[0; 24]), atom(b"keys", &keys), atom(b"ilst", &item_list)].concat();
+        let moov = [atom(b"mvhd", &[0; 100]), atom(b"meta", &meta)].concat();
+        
+        let mdat_payload = vec![0xAB; 32];
+        let mdat = [&1u32.to_be_bytes()[..], b"mdat", &(16 + mdat_payload.len() as u64).to_be_bytes(), &mdat_payload].concat();
+        [atom(b"ftyp", b"qt  \0\0\0\0qt  "), mdat, atom(b"moov", &moov)].concat()
+    }
+    
+    /// The maker note of an iPhone still: "Apple iOS" header, big endian IFD, values outside
+    /// the IFD addressed relative to the start of the maker note
+    fn apple_maker_note(identifier: &str) -> Vec<u8> {
+        let value_offset = 16 + 2 * 12 + 4;
+        let mut maker_note = [&b"Apple iOS\0"[..], &[0, 1], b"MM", &2u16.to_be_bytes()].concat();
+        // A short value stored inline, then the identifier as ASCII
+        maker_note.extend([&0x0001u16.to_be_bytes()[..], &3u16.to_be_bytes(), &1u32.to_be_bytes(), &[0, 14, 0, 0]].concat());
+        let value = [identifier.as_bytes(), b"\0"].concat();
+        maker_note.extend([&APPLE_CONTENT_IDENTIFIER_TAG.to_be_bytes()[..], &2u16.to_be_bytes(), &(value.len() as u32).to_be_bytes(), &(value_offset as u32).to_be_bytes()].concat());
+        maker_note.extend([0; 4]);
+        maker_note.extend(value);
+        maker_note
+    }
+    
+    fn metadata_with_maker_note(maker_note: Vec<u8>) -> ImageMetadata {
+        let field = exif::Field { tag: Tag::MakerNote, ifd_num: In::PRIMARY, value: Value::Undefined(maker_note, 0) };
+        let mut writer = exif::experimental::Writer::new();
+        writer.push_field(&field);
+        let mut exif = std::io::Cursor::new(Vec::new());
+        writer.write(&mut exif, false).unwrap();
+        ImageMetadata { exif: Some(exif.into_inner()), ..ImageMetadata::default() }
+    }
+    
+    #[test]
+    fn maker_note_identifier_is_read() {
+        assert_eq!(still_content_identifier(&metadata_with_maker_note(apple_maker_note(IDENTIFIER))).as_deref(), Some(IDENTIFIER));
+        
+        // Only big endian Apple maker notes are understood
+        let mut little_endian = apple_maker_note(IDENTIFIER);
+        little_endian[12..14].copy_from_slice(b"II");
+        assert_eq!(still_content_identifier(&metadata_with_maker_note(little_endian)), None);
+        let mut other_vendor = apple_maker_note(IDENTIFIER);
+        other_vendor[..5].copy_from_slice(b"Nikon");
+        assert_eq!(still_content_identifier(&metadata_with_maker_note(other_vendor)), None);
+        
+        // Offsets pointing past the end are no reason to panic
+        let mut truncated = apple_maker_note(IDENTIFIER);
+        truncated.truncate(50);
+        assert_eq!(still_content_identifier(&metadata_with_maker_note(truncated)), None);
+        assert_eq!(still_content_identifier(&ImageMetadata::default()), None);
+    }
+    
+    #[test]
+    fn quicktime_identifier_is_read() {
+        let dir = scratch_dir("quicktime");
+        let path = dir.join("IMG_0001.MOV");
+        std::fs::write(&path, quicktime(IDENTIFIER)).unwrap();
+        assert_eq!(video_content_identifier(&path).as_deref(), Some(IDENTIFIER));
+        
+        // Cut into the moov atom
+        let video = quicktime(IDENTIFIER);
+        std::fs::write(&path, &video[..video.len() - 20]).unwrap();
+        assert_eq!(video_content_identifier(&path), None);
+        std::fs::write(&path, b"not a video").unwrap();
+        assert_eq!(video_content_identifier(&path), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn videos_are_paired_by_name_and_identifier() {
+        let dir = scratch_dir("pairing");
+        let still = dir.join("IMG_0001.HEIC");
+        let video = dir.join("IMG_0001.MOV");
+        std::fs::write(&video, quicktime(IDENTIFIER)).unwrap();
+        
+        let matching = metadata_with_maker_note(apple_maker_note(IDENTIFIER));
+        assert_eq!(find_live_photo_video(&still, &matching), Some(LivePhotoVideo { path: video.clone(), content_identifier: Some(IDENTIFIER.into()) }));
+        // Stills without an identifier take the one of the video
+        assert_eq!(find_live_photo_video(&still, &ImageMetadata::default()).unwrap().content_identifier.as_deref(), Some(IDENTIFIER));
+        // A video of another Live Photo which happens to have the same name is not picked up
+        let other = metadata_with_maker_note(apple_maker_note("00000000-0000-0000-0000-000000000000"));
+        assert_eq!(find_live_photo_video(&still, &other), None);
+        assert_eq!(find_live_photo_video(&dir.join("IMG_0002.HEIC"), &matching), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn extensions_are_matched_in_any_case() {
+        let dir = scratch_dir("extensions");
+        let still = dir.join("IMG_0001.Heic");
+        let video = dir.join("IMG_0001.Mov");
+        std::fs::write(&still, b"").unwrap();
+        std::fs::write(&video, quicktime(IDENTIFIER)).unwrap();
+        
+        // The paths are spelled like the files on disk, whatever spelling was asked for
+        assert_eq!(find_live_photo_video(&dir.join("IMG_0001.heic"), &ImageMetadata::default()).map(|video| { video.path }), Some(video.clone()));
+        assert_eq!(find_live_photo_video(&dir.join("IMG_0001.HEIC"), &ImageMetadata::default()).map(|video| { video.path }), Some(video.clone()));
+        assert_eq!(find_live_photo_still(&video), Some(still.clone()));
+        assert_eq!(find_live_photo_still(&dir.join("IMG_0001.MOV")), Some(still.clone()));
+        assert_eq!(find_live_photo_still(&dir.join("IMG_0001.mov")), Some(still.clone()));
+        // Only videos have a still, and only files with the same name are paired
+        assert_eq!(find_live_photo_still(&still), None);
+        assert_eq!(find_live_photo_still(&dir.join("IMG_0002.mov")), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
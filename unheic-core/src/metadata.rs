@@ -0,0 +1,851 @@
+use std::io::{Seek, Write};
+use exif::{Context, Field, In, Value};
+use libheif_rs::{ColorPrimaries, ImageHandle};
+use tiff::{Directory, encoder::{DirectoryEncoder, TiffEncoder}, tags::{Tag, Type}};
+use crate::error::ConversionErrorKind;
+
+/// Prefix of the EXIF APP1 segment in JPEG files
+const JPEG_EXIF_PREFIX: &[u8] = b"Exif\0\0";
+/// Prefix of the standard XMP APP1 segment in JPEG files
+const JPEG_XMP_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// Prefix of the extended XMP APP1 segments in JPEG files
+const JPEG_EXTENDED_XMP_PREFIX: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+/// Maximum payload of a single JPEG segment (the length field counts itself)
+const JPEG_MAX_SEGMENT_PAYLOAD: usize = u16::MAX as usize - 2;
+/// Size of an extended XMP portion as used by Adobe applications
+const JPEG_EXTENDED_XMP_PORTION: usize = 65400;
+/// Prefix of the ICC profile APP2 segments in JPEG files
+const JPEG_ICC_PREFIX: &[u8] = b"ICC_PROFILE\0";
+/// Maximum size of an ICC profile portion in a single APP2 segment (prefix + sequence number + count)
+const JPEG_ICC_PORTION: usize = JPEG_MAX_SEGMENT_PAYLOAD - 14;
+/// Keyword of the PNG iTXt chunk carrying XMP
+pub(crate) const PNG_XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+/// TIFF tag carrying XMP
+pub(crate) const TIFF_XMP_TAG: u16 = 700;
+/// XMP namespace of the properties UnHEIC adds itself
+const UNHEIC_XMP_NAMESPACE: &str = "https://github.com/philippremy/UnHEIC/ns/1.0/";
+
+/// Metadata blocks read from the source HEIF container
+#[derive(Clone, Default)]
+pub struct ImageMetadata {
+    /// The EXIF block, starting directly at the TIFF header
+    pub exif: Option<Vec<u8>>,
+    /// The serialized XMP packet
+    pub xmp: Option<Vec<u8>>,
+    /// The ICC colour profile, either copied from the source or synthesized from nclx
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+impl ImageMetadata {
+    pub(crate) fn from_handle(handle: &ImageHandle) -> Self {
+        let mut metadata = Self::default();
+        for block in handle.all_metadata() {
+            match &block.item_type.0 {
+                b"Exif" => metadata.exif = strip_heif_exif_offset(block.raw_data),
+                b"mime" if block.content_type == "application/rdf+xml" => metadata.xmp = Some(trim_xmp_packet(block.raw_data)),
+                _ => {}
+            }
+        }
+        metadata.icc_profile = match handle.color_profile_raw() {
+            Some(profile) => Some(profile.data),
+            None => handle.color_profile_nclx().and_then(|nclx| { icc_profile_from_nclx(&nclx) }),
+        };
+        metadata
+    }
+
+    /// libheif bakes the irot/imir transforms into the decoded pixels, so any orientation
+    /// recorded in the metadata would make viewers rotate or mirror the image a second time.
+    pub(crate) fn reset_orientation(&mut self) {
+        if let Some(exif) = &mut self.exif { reset_exif_orientation(exif); }
+        if let Some(xmp) = &mut self.xmp { reset_xmp_orientation(xmp); }
+    }
+
+    /// Returns only the colour information, which is kept even if the user opted out of metadata
+    pub(crate) fn color_only(&self) -> Self {
+        Self { icc_profile: self.icc_profile.clone(), ..Default::default() }
+    }
+
+    /// Updates the pixel dimensions recorded in the EXIF block after the image was resized
+    pub(crate) fn set_pixel_dimensions(&mut self, width: u32, height: u32) {
+        if let Some(exif) = &mut self.exif { set_exif_pixel_dimensions(exif, width, height); }
+    }
+
+    /// Records the content identifier pairing a Live Photo still with its video as
+    /// `unheic:LivePhotoContentIdentifier`, creating a packet if the source had none
+    pub(crate) fn add_live_photo_identifier(&mut self, identifier: &str) {
+        const DESCRIPTION: &[u8] = b"<rdf:Description";
+        let identifier = identifier.replace('&', "&amp;").replace('<', "&lt;").replace('"', "&quot;");
+        let attributes = format!(" xmlns:unheic=\"{UNHEIC_XMP_NAMESPACE}\" unheic:LivePhotoContentIdentifier=\"{identifier}\"");
+        match &mut self.xmp {
+            Some(xmp) => {
+                // Packets without a description element are left alone rather than restructured
+                if let Some(found) = xmp.windows(DESCRIPTION.len()).position(|window| { window == DESCRIPTION }) {
+                    let position = found + DESCRIPTION.len();
+                    xmp.splice(position..position, attributes.into_bytes());
+                }
+            },
+            None => {
+                self.xmp = Some(format!(
+                    "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+                    <x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+                    <rdf:Description rdf:about=\"\"{attributes}/>\
+                    </rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>"
+                ).into_bytes());
+            },
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.xmp.is_none() && self.icc_profile.is_none()
+    }
+}
+
+/// HEIF stores EXIF with a leading 4 byte big endian offset to the TIFF header
+/// (usually skipping an "Exif\0\0" marker). All output containers expect the
+/// block to start at the TIFF header itself.
+fn strip_heif_exif_offset(raw: Vec<u8>) -> Option<Vec<u8>> {
+    let offset = u32::from_be_bytes(raw.get(0..4)?.try_into().ok()?) as usize;
+    let tiff = raw.get(4 + offset..)?;
+    match tiff.get(0..4)? {
+        b"II*\0" | b"MM\0*" => Some(tiff.to_vec()),
+        _ => None,
+    }
+}
+
+/// Overwrites the Orientation entry of IFD0 with 1 (top-left) in place, leaving
+/// every other byte (including maker notes with absolute offsets) untouched.
+fn reset_exif_orientation(tiff: &mut [u8]) {
+    let big_endian = match tiff.get(0..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return,
+    };
+    let read_u16 = |bytes: &[u8]| { if big_endian { u16::from_be_bytes([bytes[0], bytes[1]]) } else { u16::from_le_bytes([bytes[0], bytes[1]]) } };
+    let read_u32 = |bytes: &[u8]| { if big_endian { u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) } else { u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) } };
+    let Some(ifd_offset) = tiff.get(4..8).map(read_u32).map(|offset| { offset as usize }) else { return; };
+    let Some(entry_count) = tiff.get(ifd_offset..ifd_offset + 2).map(read_u16) else { return; };
+    for idx in 0..entry_count as usize {
+        let entry_offset = ifd_offset + 2 + idx * 12;
+        let Some(entry) = tiff.get(entry_offset..entry_offset + 12) else { return; };
+        // Orientation is a single SHORT, so its value always sits inline in the entry
+        if read_u16(&entry[0..2]) == Tag::Orientation.to_u16() && read_u16(&entry[2..4]) == Type::SHORT.to_u16() {
+            let value = if big_endian { 1u16.to_be_bytes() } else { 1u16.to_le_bytes() };
+            tiff[entry_offset + 8..entry_offset + 10].copy_from_slice(&value);
+            return;
+        }
+    }
+}
+
+/// Overwrites the PixelXDimension and PixelYDimension entries of the EXIF IFD in place. Both are
+/// a single SHORT or LONG, so their values always sit inline in the entry.
+fn set_exif_pixel_dimensions(tiff: &mut [u8], width: u32, height: u32) {
+    let big_endian = match tiff.get(0..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return,
+    };
+    let read_u16 = |bytes: &[u8]| { if big_endian { u16::from_be_bytes([bytes[0], bytes[1]]) } else { u16::from_le_bytes([bytes[0], bytes[1]]) } };
+    let read_u32 = |bytes: &[u8]| { if big_endian { u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) } else { u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) } };
+    // Offset, tag and type of every entry of the IFD at `ifd_offset`
+    let entries = |tiff: &[u8], ifd_offset: usize| {
+        let entry_count = tiff.get(ifd_offset..ifd_offset + 2).map(read_u16).unwrap_or(0) as usize;
+        (0..entry_count)
+            .map_while(|idx| {
+                let entry_offset = ifd_offset + 2 + idx * 12;
+                tiff.get(entry_offset..entry_offset + 12).map(|entry| { (entry_offset, read_u16(&entry[0..2]), read_u16(&entry[2..4])) })
+            })
+            .collect::<Vec<_>>()
+    };
+    
+    let Some(ifd_offset) = tiff.get(4..8).map(read_u32) else { return; };
+    let Some(exif_ifd_offset) = entries(tiff, ifd_offset as usize).into_iter()
+        .find(|(_, tag, _)| { *tag == exif::Tag::ExifIFDPointer.number() })
+        .and_then(|(entry_offset, _, _)| { tiff.get(entry_offset + 8..entry_offset + 12).map(read_u32) }) else { return; };
+    for (entry_offset, tag, kind) in entries(tiff, exif_ifd_offset as usize) {
+        let value = match tag {
+            tag if tag == exif::Tag::PixelXDimension.number() => width,
+            tag if tag == exif::Tag::PixelYDimension.number() => height,
+            _ => continue,
+        };
+        if kind == Type::SHORT.to_u16() && let Ok(value) = u16::try_from(value) {
+            let value = if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+            tiff[entry_offset + 8..entry_offset + 10].copy_from_slice(&value);
+        } else if kind == Type::LONG.to_u16() {
+            let value = if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+            tiff[entry_offset + 8..entry_offset + 12].copy_from_slice(&value);
+        }
+    }
+}
+
+/// Sets the tiff:Orientation property of an XMP packet to 1, both in its attribute
+/// (`tiff:Orientation="6"`) and its element (`<tiff:Orientation>6</tiff:Orientation>`) form.
+fn reset_xmp_orientation(xmp: &mut Vec<u8>) {
+    const PROPERTY: &[u8] = b"tiff:Orientation";
+    let mut position = 0;
+    while let Some(found) = xmp[position..].windows(PROPERTY.len()).position(|window| { window == PROPERTY }) {
+        let mut value_start = position + found + PROPERTY.len();
+        while matches!(xmp.get(value_start), Some(b'=' | b'"' | b'\'' | b'>' | b' ')) { value_start += 1; }
+        let value_end = value_start + xmp[value_start..].iter().take_while(|byte| { byte.is_ascii_digit() }).count();
+        if value_end > value_start {
+            xmp.splice(value_start..value_end, [b'1']);
+        }
+        position = value_start;
+    }
+}
+
+/// None of the output formats can carry nclx colour information directly,
+/// so an equivalent ICC profile is generated for it.
+fn icc_profile_from_nclx(nclx: &libheif_rs::ColorProfileNCLX) -> Option<Vec<u8>> {
+    // Without known primaries viewers assume sRGB anyway
+    if matches!(nclx.color_primaries(), ColorPrimaries::Unspecified | ColorPrimaries::Unknown) {
+        return None;
+    }
+    icc_profile_from_cicp(nclx.color_primaries() as u8, nclx.transfer_characteristics() as u8, nclx.matrix_coefficients() as u8, nclx.full_range_flag() != 0)
+}
+
+/// Encodes the ICC profile described by the CICP code points of an nclx box
+fn icc_profile_from_cicp(color_primaries: u8, transfer_characteristics: u8, matrix_coefficients: u8, full_range: bool) -> Option<Vec<u8>> {
+    use moxcms::{CicpColorPrimaries, CicpProfile, ColorProfile, MatrixCoefficients, TransferCharacteristics};
+    
+    let cicp = CicpProfile {
+        color_primaries: CicpColorPrimaries::try_from(color_primaries).ok()?,
+        transfer_characteristics: TransferCharacteristics::try_from(transfer_characteristics).ok()?,
+        matrix_coefficients: MatrixCoefficients::try_from(matrix_coefficients).ok()?,
+        full_range,
+    };
+    ColorProfile::new_from_cicp(cicp).encode().ok()
+}
+
+/// Some writers pad the XMP item with trailing NUL bytes, which is not valid
+/// in any of the output containers.
+fn trim_xmp_packet(mut raw: Vec<u8>) -> Vec<u8> {
+    while raw.last() == Some(&0) { raw.pop(); }
+    raw
+}
+
+pub(crate) fn embed_in_jpeg(jpeg: Vec<u8>, metadata: &ImageMetadata) -> Result<Vec<u8>, ConversionErrorKind> {
+    let mut segments = Vec::new();
+    if let Some(exif) = &metadata.exif {
+        segments.push(jpeg_segment(0xE1, &[JPEG_EXIF_PREFIX, exif])?);
+    }
+    if let Some(xmp) = &metadata.xmp {
+        segments.extend(jpeg_xmp_segments(xmp)?);
+    }
+    if let Some(icc_profile) = &metadata.icc_profile {
+        let portions = icc_profile.chunks(JPEG_ICC_PORTION).collect::<Vec<_>>();
+        let count = u8::try_from(portions.len()).map_err(|_| { ConversionErrorKind::Metadata("ICC profile too large for JPEG".into()) })?;
+        for (idx, portion) in portions.into_iter().enumerate() {
+            segments.push(jpeg_segment(0xE2, &[JPEG_ICC_PREFIX, &[idx as u8 + 1, count], portion])?);
+        }
+    }
+    if segments.is_empty() { return Ok(jpeg); }
+
+    // Application segments go directly after SOI and the JFIF APP0 segment (if any)
+    let mut insert_at = 2;
+    if jpeg.get(2..4) == Some(&[0xFF, 0xE0]) {
+        let length = jpeg.get(4..6).map(|len| { u16::from_be_bytes([len[0], len[1]]) as usize }).ok_or_else(|| { ConversionErrorKind::Metadata("Truncated JFIF segment in encoded JPEG".into()) })?;
+        insert_at += 2 + length;
+    }
+    if insert_at > jpeg.len() { return Err(ConversionErrorKind::Metadata("Truncated JFIF segment in encoded JPEG".into())); }
+
+    let mut out = Vec::with_capacity(jpeg.len() + segments.iter().map(Vec::len).sum::<usize>());
+    out.extend_from_slice(&jpeg[..insert_at]);
+    segments.iter().for_each(|segment| { out.extend_from_slice(segment) });
+    out.extend_from_slice(&jpeg[insert_at..]);
+    Ok(out)
+}
+
+fn jpeg_segment(marker: u8, parts: &[&[u8]]) -> Result<Vec<u8>, ConversionErrorKind> {
+    let payload_len = parts.iter().map(|part| { part.len() }).sum::<usize>();
+    let length = u16::try_from(payload_len + 2).map_err(|_| { ConversionErrorKind::Metadata(format!("Metadata block of {payload_len} bytes does not fit into a JPEG segment").into()) })?;
+    let mut segment = Vec::with_capacity(payload_len + 4);
+    segment.extend_from_slice(&[0xFF, marker]);
+    segment.extend_from_slice(&length.to_be_bytes());
+    parts.iter().for_each(|part| { segment.extend_from_slice(part) });
+    Ok(segment)
+}
+
+/// Packets which do not fit into a single APP1 segment are stored as extended XMP:
+/// the standard segment keeps the simple properties and references (`xmpNote:HasExtendedXMP`)
+/// the MD5 digest of the full packet, which follows in numbered portions.
+fn jpeg_xmp_segments(xmp: &[u8]) -> Result<Vec<Vec<u8>>, ConversionErrorKind> {
+    if JPEG_XMP_PREFIX.len() + xmp.len() <= JPEG_MAX_SEGMENT_PAYLOAD {
+        return Ok(vec![jpeg_segment(0xE1, &[JPEG_XMP_PREFIX, xmp])?]);
+    }
+    
+    let guid = format!("{:X}", md5::compute(xmp));
+    let standard_packet = |properties: &str| {
+        format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+            <x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+            <rdf:Description rdf:about=\"\"{properties} xmlns:xmpNote=\"http://ns.adobe.com/xmp/note/\" xmpNote:HasExtendedXMP=\"{guid}\"/>\
+            </rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>"
+        )
+    };
+    let properties = String::from_utf8_lossy(xmp);
+    let properties = xmp_simple_properties(&properties).into_iter().map(|(name, value)| { format!(" {name}=\"{value}\"") }).collect::<String>();
+    let mut standard_xmp = standard_packet(&properties);
+    if JPEG_XMP_PREFIX.len() + standard_xmp.len() > JPEG_MAX_SEGMENT_PAYLOAD {
+        standard_xmp = standard_packet("");
+    }
+    let full_length = u32::try_from(xmp.len()).map_err(|_| { ConversionErrorKind::Metadata("XMP packet exceeds 4 GiB".into()) })?;
+    
+    let mut segments = vec![jpeg_segment(0xE1, &[JPEG_XMP_PREFIX, standard_xmp.as_bytes()])?];
+    for (idx, portion) in xmp.chunks(JPEG_EXTENDED_XMP_PORTION).enumerate() {
+        let offset = (idx * JPEG_EXTENDED_XMP_PORTION) as u32;
+        segments.push(jpeg_segment(0xE1, &[JPEG_EXTENDED_XMP_PREFIX, guid.as_bytes(), &full_length.to_be_bytes(), &offset.to_be_bytes(), portion])?);
+    }
+    Ok(segments)
+}
+
+/// Attributes of the `rdf:Description` elements, which is where cameras put their simple
+/// properties. Attributes whose namespace is not declared on a description are skipped.
+fn xmp_simple_properties(xmp: &str) -> Vec<(&str, &str)> {
+    let mut attributes: Vec<(&str, &str)> = Vec::new();
+    let mut rest = xmp;
+    while let Some(start) = rest.find("<rdf:Description") {
+        rest = &rest[start + "<rdf:Description".len()..];
+        while let Some((name, tail)) = rest.trim_start().split_once('=') {
+            let name = name.trim();
+            let tail = tail.trim_start();
+            let Some(quote) = tail.chars().next().filter(|quote| { *quote == '"' || *quote == '\'' }) else { break };
+            let Some((value, tail)) = tail[1..].split_once(quote) else { break };
+            if name.is_empty() || name.contains(|c: char| { c.is_whitespace() || c == '<' || c == '>' || c == '/' }) {
+                break;
+            }
+            if !attributes.iter().any(|(existing, _)| { *existing == name }) {
+                attributes.push((name, value));
+            }
+            rest = tail;
+        }
+    }
+    
+    let declared = |prefix: &str| { attributes.iter().any(|(name, _)| { name.strip_prefix("xmlns:") == Some(prefix) }) };
+    attributes.iter()
+        .filter(|(name, _)| { *name != "rdf:about" && *name != "xmlns:xmpNote" && !name.starts_with("xmpNote:") })
+        .filter(|(name, _)| { name.starts_with("xmlns:") || name.split_once(':').is_some_and(|(prefix, _)| { declared(prefix) }) })
+        .copied()
+        .collect()
+}
+
+/// TIFF tags describing the image layout. These are written by the TIFF encoder
+/// itself and must never be copied over from the source EXIF block.
+const TIFF_STRUCTURAL_TAGS: &[u16] = &[
+    0x0100, 0x0101, 0x0102, 0x0103, 0x0106, 0x0111, 0x0115, 0x0116, 0x0117, 0x011C,
+    0x013D, 0x0142, 0x0143, 0x0144, 0x0145, 0x014A, 0x0152, 0x0153, 0x0201, 0x0202,
+    0x8769, 0x8825,
+];
+
+pub(crate) fn parse_exif(exif: &[u8]) -> Result<exif::Exif, ConversionErrorKind> {
+    exif::Reader::new().read_raw(exif.to_vec()).map_err(ConversionErrorKind::metadata)
+}
+
+/// Writes the EXIF and GPS IFDs as extra directories and returns the pointer
+/// tags which need to be added to the image directory.
+pub(crate) fn write_tiff_exif_directories<W: Write + Seek>(encoder: &mut TiffEncoder<W>, exif: &exif::Exif) -> Result<Vec<(Tag, u32)>, ConversionErrorKind> {
+    let mut pointers = Vec::new();
+    for (context, pointer_tag) in [(Context::Exif, Tag::ExifDirectory), (Context::Gps, Tag::GpsDirectory)] {
+        let fields = exif.fields()
+            .filter(|field| { field.ifd_num == In::PRIMARY && field.tag.context() == context })
+            // The Interoperability IFD is not carried over, its pointer would dangle
+            .filter(|field| { field.tag != exif::Tag::InteropIFDPointer })
+            .collect::<Vec<_>>();
+        if fields.is_empty() { continue; }
+        let mut directory = encoder.extra_directory().map_err(ConversionErrorKind::metadata)?;
+        write_tiff_fields(&mut directory, fields)?;
+        let offset = directory.finish_with_offsets().map_err(ConversionErrorKind::metadata)?;
+        pointers.push((pointer_tag, offset.offset));
+    }
+    Ok(pointers)
+}
+
+/// Writes the TIFF context fields of the primary EXIF IFD (Make, Model, DateTime, ...)
+/// into the image directory.
+pub(crate) fn write_tiff_primary_fields<W: Write + Seek>(directory: &mut DirectoryEncoder<'_, W, tiff::encoder::TiffKindStandard>, exif: &exif::Exif) -> Result<(), ConversionErrorKind> {
+    let fields = exif.fields()
+        .filter(|field| { field.ifd_num == In::PRIMARY && field.tag.context() == Context::Tiff })
+        .filter(|field| { !TIFF_STRUCTURAL_TAGS.contains(&field.tag.number()) })
+        .collect::<Vec<_>>();
+    write_tiff_fields(directory, fields)
+}
+
+pub(crate) fn write_tiff_icc_profile<W: Write + Seek>(directory: &mut DirectoryEncoder<'_, W, tiff::encoder::TiffKindStandard>, icc_profile: &[u8]) -> Result<(), ConversionErrorKind> {
+    let entry = directory.write_entry_bytes(Type::UNDEFINED, icc_profile).map_err(ConversionErrorKind::metadata)?;
+    directory.extend_from(&Directory::from_iter([(Tag::IccProfile, entry)]));
+    Ok(())
+}
+
+fn write_tiff_fields<W: Write + Seek>(directory: &mut DirectoryEncoder<'_, W, tiff::encoder::TiffKindStandard>, fields: Vec<&Field>) -> Result<(), ConversionErrorKind> {
+    let mut entries = Vec::with_capacity(fields.len());
+    for field in fields {
+        let Some((field_type, bytes)) = tiff_value_bytes(&field.value) else { continue; };
+        let entry = directory.write_entry_bytes(field_type, &bytes).map_err(ConversionErrorKind::metadata)?;
+        entries.push((Tag::from_u16_exhaustive(field.tag.number()), entry));
+    }
+    directory.extend_from(&Directory::from_iter(entries));
+    Ok(())
+}
+
+/// The TIFF encoder writes in native byte order, so all values are serialized accordingly.
+fn tiff_value_bytes(value: &Value) -> Option<(Type, Vec<u8>)> {
+    Some(match value {
+        Value::Byte(values) => (Type::BYTE, values.clone()),
+        Value::Ascii(values) => (Type::ASCII, values.iter().flat_map(|value| { value.iter().copied().chain([0]) }).collect()),
+        Value::Short(values) => (Type::SHORT, values.iter().flat_map(|value| { value.to_ne_bytes() }).collect()),
+        Value::Long(values) => (Type::LONG, values.iter().flat_map(|value| { value.to_ne_bytes() }).collect()),
+        Value::Rational(values) => (Type::RATIONAL, values.iter().flat_map(|value| { [value.num, value.denom] }).flat_map(u32::to_ne_bytes).collect()),
+        Value::SByte(values) => (Type::SBYTE, values.iter().map(|value| { *value as u8 }).collect()),
+        Value::Undefined(values, _) => (Type::UNDEFINED, values.clone()),
+        Value::SShort(values) => (Type::SSHORT, values.iter().flat_map(|value| { value.to_ne_bytes() }).collect()),
+        Value::SLong(values) => (Type::SLONG, values.iter().flat_map(|value| { value.to_ne_bytes() }).collect()),
+        Value::SRational(values) => (Type::SRATIONAL, values.iter().flat_map(|value| { [value.num, value.denom] }).flat_map(i32::to_ne_bytes).collect()),
+        Value::Float(values) => (Type::FLOAT, values.iter().flat_map(|value| { value.to_ne_bytes() }).collect()),
+        Value::Double(values) => (Type::DOUBLE, values.iter().flat_map(|value| { value.to_ne_bytes() }).collect()),
+        Value::Unknown(_, _, _) => return None,
+    })
+}
+
+const WEBP_FLAG_ICC: u8 = 0x20;
+const WEBP_FLAG_ALPHA: u8 = 0x10;
+const WEBP_FLAG_EXIF: u8 = 0x08;
+const WEBP_FLAG_XMP: u8 = 0x04;
+
+/// Rewrites a simple (VP8/VP8L) or extended (VP8X) WebP file into the extended
+/// format and adds the metadata chunks in the order mandated by the container spec.
+pub(crate) fn embed_in_webp(webp: &[u8], width: u32, height: u32, metadata: &ImageMetadata) -> Result<Vec<u8>, ConversionErrorKind> {
+    if metadata.is_empty() { return Ok(webp.to_vec()); }
+    if webp.get(0..4) != Some(b"RIFF") || webp.get(8..12) != Some(b"WEBP") {
+        return Err(ConversionErrorKind::Metadata("Encoded WebP has no RIFF header".into()));
+    }
+
+    // Split the existing file into its chunks
+    let mut chunks: Vec<([u8; 4], &[u8])> = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= webp.len() {
+        let fourcc: [u8; 4] = webp[pos..pos + 4].try_into().unwrap();
+        let size = u32::from_le_bytes(webp[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let data = webp.get(pos + 8..pos + 8 + size).ok_or_else(|| { ConversionErrorKind::Metadata("Truncated chunk in encoded WebP".into()) })?;
+        chunks.push((fourcc, data));
+        pos += 8 + size + (size & 1);
+    }
+
+    let mut flags = 0u8;
+    for (fourcc, data) in &chunks {
+        match fourcc {
+            b"VP8X" => flags |= data.first().copied().unwrap_or(0) & WEBP_FLAG_ALPHA,
+            b"ALPH" => flags |= WEBP_FLAG_ALPHA,
+            // Bit 28 of the VP8L header is the "alpha is used" hint
+            b"VP8L" if data.get(4).is_some_and(|byte| { byte & 0x10 != 0 }) => flags |= WEBP_FLAG_ALPHA,
+            _ => {}
+        }
+    }
+    if metadata.icc_profile.is_some() { flags |= WEBP_FLAG_ICC; }
+    if metadata.exif.is_some() { flags |= WEBP_FLAG_EXIF; }
+    if metadata.xmp.is_some() { flags |= WEBP_FLAG_XMP; }
+
+    let mut vp8x = vec![flags, 0, 0, 0];
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+
+    let mut out_chunks: Vec<([u8; 4], &[u8])> = vec![(*b"VP8X", vp8x.as_slice())];
+    if let Some(icc_profile) = &metadata.icc_profile { out_chunks.push((*b"ICCP", icc_profile.as_slice())); }
+    out_chunks.extend(chunks.iter().copied().filter(|(fourcc, _)| { matches!(fourcc, b"ALPH" | b"VP8 " | b"VP8L") }));
+    if let Some(exif) = &metadata.exif { out_chunks.push((*b"EXIF", exif.as_slice())); }
+    if let Some(xmp) = &metadata.xmp { out_chunks.push((*b"XMP ", xmp.as_slice())); }
+
+    let mut out = Vec::with_capacity(webp.len() + out_chunks.iter().map(|(_, data)| { data.len() + 9 }).sum::<usize>());
+    out.extend_from_slice(b"RIFF\0\0\0\0WEBP");
+    for (fourcc, data) in out_chunks {
+        out.extend_from_slice(&fourcc);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        if data.len() & 1 == 1 { out.push(0); }
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use tiff::{decoder::{Decoder, ifd::Value as TiffValue}, encoder::colortype::RGBA8};
+    use super::*;
+    
+    /// EXIF with the camera in IFD0 and the capture date in the EXIF IFD, the way phones write it
+    fn camera_exif() -> Vec<u8> {
+        let fields = [
+            Field { tag: exif::Tag::Make, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Apple".to_vec()]) },
+            Field { tag: exif::Tag::DateTimeOriginal, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"2024:05:01 12:34:56".to_vec()]) },
+        ];
+        let mut writer = exif::experimental::Writer::new();
+        fields.iter().for_each(|field| { writer.push_field(field) });
+        let mut data = Cursor::new(Vec::new());
+        writer.write(&mut data, false).unwrap();
+        data.into_inner()
+    }
+    
+    fn camera_metadata() -> ImageMetadata {
+        ImageMetadata { exif: Some(camera_exif()), ..ImageMetadata::default() }
+    }
+    
+    /// An XMP packet of exactly `len` bytes
+    fn xmp_packet(len: usize) -> Vec<u8> {
+        let head = b"<?xpacket begin=\"\xEF\xBB\xBF\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?><x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><!--";
+        let tail = b"--></x:xmpmeta><?xpacket end=\"w\"?>";
+        let mut packet = head.to_vec();
+        packet.extend((0..len - head.len() - tail.len()).map(|idx| { b'a' + (idx % 26) as u8 }));
+        packet.extend_from_slice(tail);
+        packet
+    }
+    
+    /// Markers and payloads of the segments in front of the quantization table of `jfif_jpeg`
+    fn jpeg_segments(data: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut segments = Vec::new();
+        let mut offset = 2;
+        while offset + 4 <= data.len() && data[offset + 1] != 0xDB {
+            let length = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+            segments.push((data[offset + 1], &data[offset + 4..offset + 2 + length]));
+            offset += 2 + length;
+        }
+        segments
+    }
+    
+    /// Payloads of the APP1 segments starting with `prefix`, without the prefix
+    fn app1_payloads<'a>(segments: &[(u8, &'a [u8])], prefix: &[u8]) -> Vec<&'a [u8]> {
+        segments.iter().filter(|(marker, payload)| { *marker == 0xE1 && payload.starts_with(prefix) }).map(|(_, payload)| { &payload[prefix.len()..] }).collect()
+    }
+    
+    /// Checks the EXIF read from an output still has both IFDs
+    fn assert_camera_exif(exif: &exif::Exif) {
+        let make = exif.get_field(exif::Tag::Make, In::PRIMARY).expect("Make is missing");
+        assert!(matches!(&make.value, Value::Ascii(values) if values[0] == b"Apple"));
+        assert!(exif.get_field(exif::Tag::DateTimeOriginal, In::PRIMARY).is_some(), "The EXIF IFD is missing");
+    }
+    
+    /// A JPEG consisting of SOI, a JFIF APP0 segment, a quantization table stub and EOI
+    fn jfif_jpeg() -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10];
+        jpeg.extend_from_slice(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        jpeg.extend_from_slice(&[0xFF, 0xDB, 0x00, 0x03, 0x00, 0xFF, 0xD9]);
+        jpeg
+    }
+    
+    /// FourCCs and payloads of the chunks of a WebP file
+    fn webp_chunks(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut chunks = Vec::new();
+        let mut offset = 12;
+        while offset + 8 <= data.len() {
+            let size = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            chunks.push((data[offset..offset + 4].try_into().unwrap(), &data[offset + 8..offset + 8 + size]));
+            offset += 8 + size + (size & 1);
+        }
+        chunks
+    }
+    
+    #[test]
+    fn heif_exif_offset_is_stripped() {
+        let tiff = camera_exif();
+        let with_marker = [&6u32.to_be_bytes()[..], JPEG_EXIF_PREFIX, &tiff[..]].concat();
+        assert_eq!(strip_heif_exif_offset(with_marker), Some(tiff.clone()));
+        let without_marker = [&0u32.to_be_bytes()[..], &tiff[..]].concat();
+        assert_eq!(strip_heif_exif_offset(without_marker), Some(tiff.clone()));
+        // An offset pointing anywhere but a TIFF header or past the end is no EXIF
+        assert_eq!(strip_heif_exif_offset([&2u32.to_be_bytes()[..], &tiff[..]].concat()), None);
+        assert_eq!(strip_heif_exif_offset([&64u32.to_be_bytes()[..], &tiff[..16]].concat()), None);
+        assert_eq!(strip_heif_exif_offset(vec![0, 0]), None);
+    }
+    
+    #[test]
+    fn jpeg_exif_follows_the_jfif_segment() {
+        let jpeg = jfif_jpeg();
+        let out = embed_in_jpeg(jpeg.clone(), &camera_metadata()).unwrap();
+        
+        assert_eq!(out[..20], jpeg[..20], "SOI and APP0 have to stay in front");
+        assert_eq!(out[20..22], [0xFF, 0xE1]);
+        let length = u16::from_be_bytes([out[22], out[23]]) as usize;
+        let payload = &out[24..22 + length];
+        assert!(payload.starts_with(JPEG_EXIF_PREFIX));
+        assert_camera_exif(&parse_exif(&payload[JPEG_EXIF_PREFIX.len()..]).unwrap());
+        assert_eq!(out[22 + length..], jpeg[20..]);
+    }
+    
+    #[test]
+    fn jpeg_without_metadata_is_unchanged() {
+        let jpeg = jfif_jpeg();
+        assert_eq!(embed_in_jpeg(jpeg.clone(), &ImageMetadata::default()).unwrap(), jpeg);
+    }
+    
+    #[test]
+    fn small_xmp_is_stored_in_one_segment() {
+        // The largest packet which still fits next to the prefix
+        let xmp = xmp_packet(JPEG_MAX_SEGMENT_PAYLOAD - JPEG_XMP_PREFIX.len());
+        let metadata = ImageMetadata { xmp: Some(xmp.clone()), ..camera_metadata() };
+        let out = embed_in_jpeg(jfif_jpeg(), &metadata).unwrap();
+        
+        let segments = jpeg_segments(&out);
+        assert_eq!(segments.iter().map(|(marker, _)| { *marker }).collect::<Vec<_>>(), [0xE0, 0xE1, 0xE1], "EXIF has to come before XMP");
+        assert_eq!(app1_payloads(&segments, JPEG_XMP_PREFIX), [&xmp[..]]);
+        assert!(app1_payloads(&segments, JPEG_EXTENDED_XMP_PREFIX).is_empty());
+    }
+    
+    #[test]
+    fn large_xmp_is_split_into_extended_portions() {
+        for len in [JPEG_MAX_SEGMENT_PAYLOAD - JPEG_XMP_PREFIX.len() + 1, 3 * JPEG_EXTENDED_XMP_PORTION + 17] {
+            let xmp = xmp_packet(len);
+            let metadata = ImageMetadata { xmp: Some(xmp.clone()), ..ImageMetadata::default() };
+            let out = embed_in_jpeg(jfif_jpeg(), &metadata).unwrap();
+            let segments = jpeg_segments(&out);
+            
+            // The GUID is the MD5 digest of the whole packet as 32 uppercase hex digits
+            let guid = format!("{:X}", md5::compute(&xmp));
+            assert_eq!(guid.len(), 32);
+            assert!(guid.chars().all(|digit| { digit.is_ascii_digit() || digit.is_ascii_uppercase() }));
+            let standard = app1_payloads(&segments, JPEG_XMP_PREFIX);
+            assert_eq!(standard.len(), 1);
+            let standard = String::from_utf8(standard[0].to_vec()).unwrap();
+            assert!(standard.contains(&format!("xmpNote:HasExtendedXMP=\"{guid}\"")));
+            
+            let portions = app1_payloads(&segments, JPEG_EXTENDED_XMP_PREFIX);
+            assert_eq!(portions.len(), len.div_ceil(JPEG_EXTENDED_XMP_PORTION));
+            let mut reassembled = vec![0u8; len];
+            for (idx, portion) in portions.iter().enumerate() {
+                assert_eq!(&portion[..32], guid.as_bytes());
+                assert_eq!(u32::from_be_bytes(portion[32..36].try_into().unwrap()) as usize, len, "Full length of portion {idx}");
+                let offset = u32::from_be_bytes(portion[36..40].try_into().unwrap()) as usize;
+                assert_eq!(offset, idx * JPEG_EXTENDED_XMP_PORTION, "Offset of portion {idx}");
+                let data = &portion[40..];
+                assert!(data.len() <= JPEG_EXTENDED_XMP_PORTION);
+                reassembled[offset..offset + data.len()].copy_from_slice(data);
+            }
+            assert_eq!(portions.iter().map(|portion| { portion.len() - 40 }).sum::<usize>(), len);
+            assert_eq!(reassembled, xmp);
+        }
+    }
+    
+    #[test]
+    fn simple_properties_stay_in_the_standard_packet() {
+        let head = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?><x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+            <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\
+            <rdf:Description rdf:about=\"\" xmlns:tiff=\"http://ns.adobe.com/tiff/1.0/\" tiff:Make=\"Apple\" dc:format=\"image/heic\">";
+        let tail = "</rdf:Description><rdf:Description rdf:about='' xmlns:xmp='http://ns.adobe.com/xap/1.0/' xmp:CreateDate='2024-05-01T12:00:00'/></rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>";
+        let xmp = format!("{head}<tiff:Model>{}</tiff:Model>{tail}", "x".repeat(JPEG_MAX_SEGMENT_PAYLOAD)).into_bytes();
+        let metadata = ImageMetadata { xmp: Some(xmp.clone()), ..ImageMetadata::default() };
+        let out = embed_in_jpeg(jfif_jpeg(), &metadata).unwrap();
+        let segments = jpeg_segments(&out);
+        
+        let standard = app1_payloads(&segments, JPEG_XMP_PREFIX);
+        let standard = String::from_utf8(standard[0].to_vec()).unwrap();
+        assert!(standard.contains("xmlns:tiff=\"http://ns.adobe.com/tiff/1.0/\" tiff:Make=\"Apple\""));
+        assert!(standard.contains("xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\" xmp:CreateDate=\"2024-05-01T12:00:00\""));
+        assert!(standard.contains(&format!("xmpNote:HasExtendedXMP=\"{:X}\"", md5::compute(&xmp))));
+        // The namespace of `dc:format` is declared outside the description and the element form stays in the extended packet
+        assert!(!standard.contains("dc:format"));
+        assert!(!standard.contains("tiff:Model"));
+        assert_eq!(standard.matches("rdf:about").count(), 1);
+    }
+    
+    /// EXIF with an Orientation of 6 (rotated 90° clockwise) in the given byte order
+    fn rotated_exif(little_endian: bool) -> Vec<u8> {
+        let fields = [
+            Field { tag: exif::Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![6]) },
+            Field { tag: exif::Tag::Make, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Apple".to_vec()]) },
+        ];
+        let mut writer = exif::experimental::Writer::new();
+        fields.iter().for_each(|field| { writer.push_field(field) });
+        let mut data = Cursor::new(Vec::new());
+        writer.write(&mut data, little_endian).unwrap();
+        data.into_inner()
+    }
+    
+    #[test]
+    fn exif_orientation_is_reset_in_place() {
+        for little_endian in [false, true] {
+            let original = rotated_exif(little_endian);
+            let mut exif = original.clone();
+            reset_exif_orientation(&mut exif);
+            
+            let parsed = parse_exif(&exif).unwrap();
+            assert_eq!(parsed.get_field(exif::Tag::Orientation, In::PRIMARY).unwrap().value.get_uint(0), Some(1));
+            // Only the two bytes of the value change, so offsets elsewhere in the block stay valid
+            assert_eq!(exif.len(), original.len());
+            assert_eq!(exif.iter().zip(&original).filter(|(byte, original)| { byte != original }).count(), 1);
+        }
+    }
+    
+    #[test]
+    fn exif_without_orientation_is_unchanged() {
+        let mut exif = camera_exif();
+        reset_exif_orientation(&mut exif);
+        assert_eq!(exif, camera_exif());
+        
+        let mut garbage = b"not a tiff header".to_vec();
+        reset_exif_orientation(&mut garbage);
+        assert_eq!(garbage, b"not a tiff header");
+    }
+    
+    #[test]
+    fn xmp_orientation_is_reset_in_both_forms() {
+        let mut xmp = br#"<rdf:Description tiff:Orientation="6"/><rdf:Description><tiff:Orientation>8</tiff:Orientation></rdf:Description>"#.to_vec();
+        reset_xmp_orientation(&mut xmp);
+        assert_eq!(xmp, br#"<rdf:Description tiff:Orientation="1"/><rdf:Description><tiff:Orientation>1</tiff:Orientation></rdf:Description>"#);
+    }
+    
+    /// Whether two profiles have the same primaries and white point, within the precision of ICC numbers
+    fn same_colorants(profile: &moxcms::ColorProfile, expected: &moxcms::ColorProfile) -> bool {
+        [(profile.red_colorant, expected.red_colorant), (profile.green_colorant, expected.green_colorant), (profile.blue_colorant, expected.blue_colorant), (profile.white_point, expected.white_point)].iter().all(|(xyz, expected)| {
+            (xyz.x - expected.x).abs() < 1e-3 && (xyz.y - expected.y).abs() < 1e-3 && (xyz.z - expected.z).abs() < 1e-3
+        })
+    }
+    
+    #[test]
+    fn nclx_primaries_are_turned_into_icc_profiles() {
+        use moxcms::ColorProfile;
+        
+        // Display P3 (SMPTE EG 432-1) and BT.709, both with the sRGB transfer curve and BT.601 matrix
+        let p3 = ColorProfile::new_from_slice(&icc_profile_from_cicp(12, 13, 6, true).unwrap()).unwrap();
+        assert!(same_colorants(&p3, &ColorProfile::new_display_p3()));
+        assert!(!same_colorants(&p3, &ColorProfile::new_srgb()));
+        let bt709 = ColorProfile::new_from_slice(&icc_profile_from_cicp(1, 13, 6, true).unwrap()).unwrap();
+        assert!(same_colorants(&bt709, &ColorProfile::new_srgb()));
+    }
+    
+    #[test]
+    fn large_icc_profiles_are_split_into_numbered_segments() {
+        let icc_profile = (0..2 * JPEG_ICC_PORTION + 10).map(|idx| { (idx % 251) as u8 }).collect::<Vec<_>>();
+        let metadata = ImageMetadata { icc_profile: Some(icc_profile.clone()), ..camera_metadata() };
+        let out = embed_in_jpeg(jfif_jpeg(), &metadata).unwrap();
+        
+        let segments = jpeg_segments(&out);
+        let portions = segments.iter().filter(|(marker, _)| { *marker == 0xE2 }).map(|(_, payload)| { *payload }).collect::<Vec<_>>();
+        assert_eq!(portions.len(), 3);
+        for (idx, portion) in portions.iter().enumerate() {
+            assert!(portion.starts_with(JPEG_ICC_PREFIX));
+            // Sequence numbers start at one, followed by the number of segments
+            assert_eq!(portion[JPEG_ICC_PREFIX.len()..JPEG_ICC_PREFIX.len() + 2], [idx as u8 + 1, 3]);
+        }
+        let reassembled = portions.iter().flat_map(|portion| { portion[JPEG_ICC_PREFIX.len() + 2..].iter().copied() }).collect::<Vec<_>>();
+        assert_eq!(reassembled, icc_profile);
+    }
+    
+    #[test]
+    fn only_the_colour_profile_is_kept_without_metadata() {
+        let metadata = ImageMetadata { xmp: Some(xmp_packet(128)), icc_profile: Some(vec![1, 2, 3]), ..camera_metadata() };
+        let color_only = metadata.color_only();
+        assert_eq!((color_only.exif, color_only.xmp, color_only.icc_profile), (None, None, Some(vec![1, 2, 3])));
+        assert!(ImageMetadata::default().color_only().is_empty());
+    }
+    
+    #[test]
+    fn live_photo_identifier_is_dropped_without_metadata() {
+        let mut metadata = ImageMetadata { icc_profile: Some(vec![1, 2, 3]), ..ImageMetadata::default() };
+        metadata.add_live_photo_identifier("6A1F");
+        let color_only = metadata.color_only();
+        assert_eq!((color_only.exif, color_only.xmp, color_only.icc_profile), (None, None, Some(vec![1, 2, 3])));
+    }
+    
+    #[test]
+    fn webp_exif_is_stored_in_an_extended_container() {
+        let pixels = [255u8, 0, 0, 255].repeat(4);
+        let webp = webp::Encoder::from_rgba(&pixels, 2, 2).encode_lossless();
+        let out = embed_in_webp(&webp, 2, 2, &camera_metadata()).unwrap();
+        
+        assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()) as usize, out.len() - 8);
+        let chunks = webp_chunks(&out);
+        assert_eq!(chunks.iter().map(|(fourcc, _)| { fourcc }).collect::<Vec<_>>(), [b"VP8X", b"VP8L", b"EXIF"]);
+        let vp8x = chunks[0].1;
+        assert_eq!(vp8x[0] & WEBP_FLAG_EXIF, WEBP_FLAG_EXIF);
+        assert_eq!(vp8x[4..10], [1, 0, 0, 1, 0, 0], "Canvas size is stored minus one");
+        assert_camera_exif(&parse_exif(chunks[2].1).unwrap());
+        
+        assert_eq!(embed_in_webp(&webp, 2, 2, &ImageMetadata::default()).unwrap(), &webp[..]);
+    }
+    
+    #[test]
+    fn webp_xmp_follows_the_exif_chunk() {
+        let pixels = [0u8, 0, 255, 255].repeat(4);
+        let webp = webp::Encoder::from_rgba(&pixels, 2, 2).encode_lossless();
+        let xmp = xmp_packet(301);
+        let metadata = ImageMetadata { xmp: Some(xmp.clone()), ..camera_metadata() };
+        let out = embed_in_webp(&webp, 2, 2, &metadata).unwrap();
+        
+        let chunks = webp_chunks(&out);
+        assert_eq!(chunks.iter().map(|(fourcc, _)| { fourcc }).collect::<Vec<_>>(), [b"VP8X", b"VP8L", b"EXIF", b"XMP "]);
+        assert_eq!(chunks[0].1[0] & (WEBP_FLAG_EXIF | WEBP_FLAG_XMP), WEBP_FLAG_EXIF | WEBP_FLAG_XMP);
+        assert_eq!(chunks[3].1, &xmp[..]);
+        // Odd chunks are padded, the RIFF size covers the padding
+        assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()) as usize, out.len() - 8);
+        assert_eq!(out.len() % 2, 0);
+    }
+    
+    #[test]
+    fn webp_icc_profile_precedes_the_image_data() {
+        let pixels = [0u8, 255, 0, 255].repeat(4);
+        let webp = webp::Encoder::from_rgba(&pixels, 2, 2).encode_lossless();
+        let icc_profile = moxcms::ColorProfile::new_display_p3().encode().unwrap();
+        let metadata = ImageMetadata { icc_profile: Some(icc_profile.clone()), ..ImageMetadata::default() };
+        let out = embed_in_webp(&webp, 2, 2, &metadata).unwrap();
+        
+        let chunks = webp_chunks(&out);
+        assert_eq!(chunks.iter().map(|(fourcc, _)| { fourcc }).collect::<Vec<_>>(), [b"VP8X", b"ICCP", b"VP8L"]);
+        assert_eq!(chunks[0].1[0] & WEBP_FLAG_ICC, WEBP_FLAG_ICC);
+        assert_eq!(chunks[1].1, &icc_profile[..]);
+    }
+    
+    #[test]
+    fn tiff_icc_profile_is_stored_in_its_tag() {
+        let icc_profile = moxcms::ColorProfile::new_display_p3().encode().unwrap();
+        let mut out = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut out).unwrap();
+        let mut image = encoder.new_image::<RGBA8>(1, 1).unwrap();
+        write_tiff_icc_profile(image.encoder(), &icc_profile).unwrap();
+        image.write_data(&[0, 0, 0, 255]).unwrap();
+        
+        let mut decoder = Decoder::new(Cursor::new(out.into_inner())).unwrap();
+        assert_eq!(decoder.get_tag_u8_vec(Tag::IccProfile).unwrap(), icc_profile);
+    }
+    
+    #[test]
+    fn xmp_padding_is_trimmed() {
+        assert_eq!(trim_xmp_packet(b"<x:xmpmeta/>\0\0\0".to_vec()), b"<x:xmpmeta/>");
+        assert_eq!(trim_xmp_packet(b"<x:xmpmeta/>".to_vec()), b"<x:xmpmeta/>");
+    }
+    
+    #[test]
+    fn tiff_exif_is_split_into_directories() {
+        let exif = parse_exif(&camera_exif()).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut out).unwrap();
+        let pointers = write_tiff_exif_directories(&mut encoder, &exif).unwrap();
+        assert_eq!(pointers.iter().map(|(tag, _)| { *tag }).collect::<Vec<_>>(), [Tag::ExifDirectory]);
+        let mut image = encoder.new_image::<RGBA8>(1, 1).unwrap();
+        write_tiff_primary_fields(image.encoder(), &exif).unwrap();
+        for (tag, offset) in pointers {
+            image.encoder().write_tag(tag, offset).unwrap();
+        }
+        image.write_data(&[0, 0, 0, 255]).unwrap();
+        let out = out.into_inner();
+        
+        let mut decoder = Decoder::new(Cursor::new(&out)).unwrap();
+        assert!(matches!(decoder.find_tag(Tag::Make).unwrap(), Some(TiffValue::Ascii(make)) if make == "Apple"));
+        assert!(decoder.find_tag(Tag::ExifDirectory).unwrap().is_some());
+        assert_camera_exif(&parse_exif(&out).unwrap());
+    }
+    
+    #[test]
+    fn live_photo_identifier_is_added_to_the_packet() {
+        const ATTRIBUTE: &str = "unheic:LivePhotoContentIdentifier=\"6A1F&amp;&lt;\"";
+        // Without a source packet a new one is created
+        let mut metadata = ImageMetadata::default();
+        metadata.add_live_photo_identifier("6A1F&<");
+        let xmp = String::from_utf8(metadata.xmp.unwrap()).unwrap();
+        assert!(xmp.starts_with("<?xpacket begin=") && xmp.ends_with("<?xpacket end=\"w\"?>"));
+        assert!(xmp.contains(ATTRIBUTE) && xmp.contains(UNHEIC_XMP_NAMESPACE));
+        
+        // Existing packets keep their properties and get the identifier on their description
+        let mut metadata = ImageMetadata { xmp: Some(b"<x:xmpmeta><rdf:RDF><rdf:Description rdf:about=\"\" tiff:Make=\"Apple\"/></rdf:RDF></x:xmpmeta>".to_vec()), ..ImageMetadata::default() };
+        metadata.add_live_photo_identifier("6A1F&<");
+        let xmp = String::from_utf8(metadata.xmp.unwrap()).unwrap();
+        assert!(xmp.starts_with("<x:xmpmeta><rdf:RDF><rdf:Description xmlns:unheic=") && xmp.contains(ATTRIBUTE) && xmp.contains("tiff:Make=\"Apple\""));
+    }
+}
@@ -0,0 +1,431 @@
+use std::{fs::OpenOptions, io::ErrorKind, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}, time::UNIX_EPOCH};
+use exif::{In, Tag, Value};
+use crate::error::ConversionErrorKind;
+use crate::metadata::ImageMetadata;
+use crate::settings::CollisionPolicy;
+
+/// Values the tokens of a naming template are replaced with
+pub(crate) struct NameValues<'a> {
+    pub(crate) source: &'a Path,
+    /// 1-based position of the source in the batch
+    pub(crate) index: usize,
+    pub(crate) metadata: &'a ImageMetadata,
+    pub(crate) format: &'a str,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+/// Replaces the tokens of `template`. Unknown tokens are kept as they are, and characters
+/// which are not allowed in file names are replaced so the result stays a single file name.
+pub(crate) fn render_template(template: &str, values: &NameValues) -> String {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| { start + end }) else { break; };
+        name.push_str(&rest[..start]);
+        match token_value(&rest[start + 1..end], values) {
+            Some(value) => name.push_str(&value),
+            None => name.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    name.push_str(rest);
+    
+    let name = name.chars().map(|c| { if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c } }).collect::<String>();
+    let name = name.trim();
+    // An empty name would turn into a hidden file consisting only of the extension
+    if name.is_empty() || name.chars().all(|c| { c == '.' }) {
+        return token_value("name", values).unwrap_or_default();
+    }
+    name.to_string()
+}
+
+fn token_value(token: &str, values: &NameValues) -> Option<String> {
+    match token {
+        "name" => Some(values.source.file_prefix().map(|name| { name.to_string_lossy().to_string() }).unwrap_or_default()),
+        "index" => Some(values.index.to_string()),
+        "date" => Some(capture_date(values).unwrap_or_default()),
+        "camera" => Some(exif_ascii(values.metadata, Tag::Model).unwrap_or_default()),
+        "format" => Some(values.format.to_string()),
+        "width" => Some(values.width.to_string()),
+        "height" => Some(values.height.to_string()),
+        _ => None,
+    }
+}
+
+fn exif_ascii(metadata: &ImageMetadata, tag: Tag) -> Option<String> {
+    let exif = crate::metadata::parse_exif(metadata.exif.as_deref()?).ok()?;
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    match &field.value {
+        Value::Ascii(values) => values.first().map(|value| { String::from_utf8_lossy(value).trim().to_string() }).filter(|value| { !value.is_empty() }),
+        _ => None,
+    }
+}
+
+/// The capture date as YYYY-MM-DD, taken from EXIF and falling back to the modification time of the source
+fn capture_date(values: &NameValues) -> Option<String> {
+    let exif_date = exif_ascii(values.metadata, Tag::DateTimeOriginal).or_else(|| { exif_ascii(values.metadata, Tag::DateTime) });
+    // EXIF dates look like "2024:05:01 12:34:56"
+    if let Some(date) = exif_date.as_deref().and_then(|date| { date.get(..10) }).filter(|date| { !date.starts_with("0000") }) {
+        return Some(date.replace(':', "-"));
+    }
+    
+    let modified = values.source.metadata().and_then(|metadata| { metadata.modified() }).ok()?;
+    let days = modified.duration_since(UNIX_EPOCH).ok()?.as_secs() / 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    Some(format!("{year:04}-{month:02}-{day:02}"))
+}
+
+/// Converts days since 1970-01-01 into a proleptic Gregorian date (Howard Hinnant's algorithm)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The deepest directory containing all `paths`, or none if they do not share one (e.g. different drives)
+pub fn common_root<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Option<PathBuf> {
+    let mut root: Option<PathBuf> = None;
+    for parent in paths.into_iter().filter_map(|path| { path.parent() }) {
+        root = Some(match root {
+            None => parent.to_path_buf(),
+            Some(root) => root.components().zip(parent.components()).take_while(|(a, b)| { a == b }).map(|(a, _)| { a }).collect(),
+        });
+    }
+    root.filter(|root| { root.has_root() })
+}
+
+/// Recreates the location of `source` relative to `base` under `output_dir`.
+/// Sources outside of `base` are written directly into `output_dir`.
+pub fn mirrored_output_dir(source: &Path, base: &Path, output_dir: &Path) -> PathBuf {
+    match source.parent().and_then(|parent| { parent.strip_prefix(base).ok() }) {
+        Some(relative) => output_dir.join(relative),
+        None => output_dir.to_path_buf(),
+    }
+}
+
+/// Whether both paths name the same existing file, no matter if they differ by symlinks,
+/// relative components or, on case-insensitive file systems, by case
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (a.metadata(), b.metadata()) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+    // The file index is not available on stable Rust elsewhere, the canonical path resolves links and case as well
+    #[cfg(not(unix))]
+    {
+        match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// The temporary file a claimed output is written into before it is moved into place
+pub(crate) fn part_path(path: &Path) -> PathBuf {
+    path.with_added_extension("part")
+}
+
+/// The path the output is written to, or none if the file is skipped
+pub(crate) struct ReservedOutput {
+    pub(crate) path: PathBuf,
+    /// The temporary file created for this output, which is renamed to `path` once it is complete
+    pub(crate) part: PathBuf,
+}
+
+impl ReservedOutput {
+    /// Gives the name free again after a failed conversion
+    pub(crate) fn release(&self) {
+        let _ = std::fs::remove_file(&self.part);
+    }
+}
+
+/// Applies the collision policy to `path`. Free names are claimed right away by creating the `.part` file
+/// they are written into later, so parallel workers can never pick the same file and an aborted
+/// run leaves no empty file under the final name. Overwriting workers each write into a part file of
+/// their own, so two of them rendering the same name replace the file one after the other.
+pub(crate) fn reserve_output(path: PathBuf, policy: CollisionPolicy, ask: &dyn Fn(&Path) -> CollisionPolicy) -> Result<Option<ReservedOutput>, ConversionErrorKind> {
+    if policy == CollisionPolicy::Overwrite {
+        return overwrite(path).map(Some);
+    }
+    if claim(&path)? {
+        return Ok(Some(ReservedOutput { part: part_path(&path), path }));
+    }
+    
+    let policy = match policy {
+        CollisionPolicy::Ask => ask(&path),
+        policy => policy,
+    };
+    match policy {
+        CollisionPolicy::Overwrite => overwrite(path).map(Some),
+        CollisionPolicy::AutoSuffix => {
+            let stem = path.file_stem().map(|stem| { stem.to_string_lossy().to_string() }).unwrap_or_default();
+            let extension = path.extension().map(|extension| { extension.to_string_lossy().to_string() });
+            for number in 1.. {
+                let file_name = match &extension {
+                    Some(extension) => format!("{stem} ({number}).{extension}"),
+                    None => format!("{stem} ({number})"),
+                };
+                let candidate = path.with_file_name(file_name);
+                if claim(&candidate)? {
+                    return Ok(Some(ReservedOutput { part: part_path(&candidate), path: candidate }));
+                }
+            }
+            unreachable!("Logic Error: Ran out of file name suffixes")
+        },
+        // Asking again is not possible, so an unanswered question skips the file
+        CollisionPolicy::Skip | CollisionPolicy::Ask => Ok(None),
+    }
+}
+
+/// Creates a part file only this worker writes into (`IMG_0001.jpg.<pid>-<n>.part`)
+fn overwrite(path: PathBuf) -> Result<ReservedOutput, ConversionErrorKind> {
+    static NEXT_PART: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let part = path.with_added_extension(format!("{}-{}.part", std::process::id(), NEXT_PART.fetch_add(1, Ordering::Relaxed)));
+        match OpenOptions::new().write(true).create_new(true).open(&part) {
+            Ok(_) => return Ok(ReservedOutput { path, part }),
+            // Left behind by an earlier run which had the same process id
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(ConversionErrorKind::write(err)),
+        }
+    }
+}
+
+/// Returns false if the file already exists or another worker is writing it
+fn claim(path: &Path) -> Result<bool, ConversionErrorKind> {
+    let part_file_path = part_path(path);
+    match OpenOptions::new().write(true).create_new(true).open(&part_file_path) {
+        Ok(_) => {},
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => return Ok(false),
+        Err(err) => return Err(ConversionErrorKind::write(err)),
+    }
+    // Checked only after claiming, a worker renaming its `.part` into place in between would be missed otherwise
+    if path.symlink_metadata().is_ok() {
+        let _ = std::fs::remove_file(&part_file_path);
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// An empty directory of its own for every test, as tests run in parallel
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("unheic-naming-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+    
+    /// EXIF with a camera model and a capture date, as written by a phone
+    fn exif_metadata(model: &str, date: &str) -> ImageMetadata {
+        let fields = [
+            exif::Field { tag: Tag::Model, ifd_num: In::PRIMARY, value: Value::Ascii(vec![model.as_bytes().to_vec()]) },
+            exif::Field { tag: Tag::DateTimeOriginal, ifd_num: In::PRIMARY, value: Value::Ascii(vec![date.as_bytes().to_vec()]) },
+        ];
+        let mut writer = exif::experimental::Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut exif = std::io::Cursor::new(Vec::new());
+        writer.write(&mut exif, false).unwrap();
+        ImageMetadata { exif: Some(exif.into_inner()), ..ImageMetadata::default() }
+    }
+    
+    fn render(template: &str, source: &str, metadata: &ImageMetadata) -> String {
+        render_template(template, &NameValues { source: Path::new(source), index: 3, metadata, format: "jpg", width: 4032, height: 3024 })
+    }
+    
+    #[test]
+    fn every_token_is_replaced() {
+        let metadata = exif_metadata("iPhone 15 Pro", "2024:05:01 12:34:56");
+        assert_eq!(render("{name}_{index}_{date}_{camera}_{format}_{width}x{height}", "/photos/IMG_0001.HEIC", &metadata), "IMG_0001_3_2024-05-01_iPhone 15 Pro_jpg_4032x3024");
+        // The name stops at the first dot
+        assert_eq!(render("{name}", "/photos/IMG_0001.edited.HEIC", &metadata), "IMG_0001");
+    }
+    
+    #[test]
+    fn missing_values_render_empty() {
+        let metadata = exif_metadata("", "0000:00:00 00:00:00");
+        // Neither a usable EXIF date nor a source file to take the modification time from
+        assert_eq!(render("{name}_{date}_{camera}", "/does/not/exist/IMG_0001.HEIC", &metadata), "IMG_0001__");
+        assert_eq!(render("{name}_{date}", "/does/not/exist/IMG_0001.HEIC", &ImageMetadata::default()), "IMG_0001_");
+    }
+    
+    #[test]
+    fn unknown_tokens_are_kept() {
+        let metadata = ImageMetadata::default();
+        assert_eq!(render("{name}-{unknown}-{}", "IMG_0001.HEIC", &metadata), "IMG_0001-{unknown}-{}");
+        assert_eq!(render("{name}-{index", "IMG_0001.HEIC", &metadata), "IMG_0001-{index");
+    }
+    
+    #[test]
+    fn names_are_sanitised() {
+        let metadata = exif_metadata("Canon EOS R5/R6", "2024:05:01 12:34:56");
+        assert_eq!(render("{camera}", "IMG_0001.HEIC", &metadata), "Canon EOS R5_R6");
+        assert_eq!(render("a/b\\c:d*e?f\"g<h>i|j\tk", "IMG_0001.HEIC", &metadata), "a_b_c_d_e_f_g_h_i_j_k");
+        assert_eq!(render("  {name}  ", "IMG_0001.HEIC", &metadata), "IMG_0001");
+        // Names which would be empty or hidden fall back to the source name
+        assert_eq!(render("", "IMG_0001.HEIC", &metadata), "IMG_0001");
+        assert_eq!(render("..", "IMG_0001.HEIC", &metadata), "IMG_0001");
+    }
+    
+    #[test]
+    fn dates_are_converted_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19844), (2024, 5, 1));
+    }
+    
+    #[test]
+    fn reserved_names_count_up_suffixes() {
+        let dir = scratch_dir("suffixes");
+        let path = dir.join("a.jpg");
+        let never_asked = |_: &Path| -> CollisionPolicy { unreachable!() };
+        
+        let first = reserve_output(path.clone(), CollisionPolicy::AutoSuffix, &never_asked).unwrap().unwrap();
+        assert_eq!(first.path, path);
+        // The name is held by the `.part` file only, an aborted run leaves nothing under the final name
+        assert!(first.part == part_path(&path) && first.part.exists() && !path.exists());
+        
+        let suffixed = (1..=3).map(|_| { reserve_output(path.clone(), CollisionPolicy::AutoSuffix, &never_asked).unwrap().unwrap().path }).collect::<Vec<_>>();
+        assert_eq!(suffixed, [dir.join("a (1).jpg"), dir.join("a (2).jpg"), dir.join("a (3).jpg")]);
+        
+        first.release();
+        assert!(!part_path(&path).exists());
+        assert_eq!(reserve_output(path.clone(), CollisionPolicy::AutoSuffix, &never_asked).unwrap().unwrap().path, path);
+        
+        let without_extension = dir.join("b");
+        std::fs::write(&without_extension, b"existing").unwrap();
+        assert_eq!(reserve_output(without_extension, CollisionPolicy::AutoSuffix, &never_asked).unwrap().unwrap().path, dir.join("b (1)"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn existing_files_follow_the_policy() {
+        let dir = scratch_dir("policies");
+        let path = dir.join("a.jpg");
+        std::fs::write(&path, b"existing").unwrap();
+        let never_asked = |_: &Path| -> CollisionPolicy { unreachable!() };
+        
+        assert!(reserve_output(path.clone(), CollisionPolicy::Skip, &never_asked).unwrap().is_none());
+        let overwritten = reserve_output(path.clone(), CollisionPolicy::Overwrite, &never_asked).unwrap().unwrap();
+        assert!(overwritten.path == path && overwritten.part.exists());
+        overwritten.release();
+        
+        let asked = std::cell::RefCell::new(Vec::new());
+        let answer = |answer: CollisionPolicy| {
+            let asked = &asked;
+            move |path: &Path| -> CollisionPolicy {
+                asked.borrow_mut().push(path.to_path_buf());
+                answer
+            }
+        };
+        assert_eq!(reserve_output(path.clone(), CollisionPolicy::Ask, &answer(CollisionPolicy::AutoSuffix)).unwrap().unwrap().path, dir.join("a (1).jpg"));
+        assert!(reserve_output(path.clone(), CollisionPolicy::Ask, &answer(CollisionPolicy::Skip)).unwrap().is_none());
+        assert!(reserve_output(path.clone(), CollisionPolicy::Ask, &answer(CollisionPolicy::Ask)).unwrap().is_none());
+        assert_eq!(*asked.borrow(), [path.clone(), path.clone(), path.clone()]);
+        // The existing file is left untouched by all of them
+        assert_eq!(std::fs::read(&path).unwrap(), b"existing");
+        // Free names are claimed without asking
+        assert_eq!(reserve_output(dir.join("b.jpg"), CollisionPolicy::Ask, &never_asked).unwrap().unwrap().path, dir.join("b.jpg"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn overwriting_workers_write_into_their_own_part_files() {
+        let dir = scratch_dir("overwrite");
+        let path = dir.join("IMG_0001.jpg");
+        let never_asked = |_: &Path| -> CollisionPolicy { unreachable!() };
+        
+        // Two sources rendering the same name, e.g. IMG_0001.HEIC from two folders
+        let first = reserve_output(path.clone(), CollisionPolicy::Overwrite, &never_asked).unwrap().unwrap();
+        let second = reserve_output(path.clone(), CollisionPolicy::Overwrite, &never_asked).unwrap().unwrap();
+        assert_eq!((&first.path, &second.path), (&path, &path));
+        assert_ne!(first.part, second.part);
+        assert!(first.part.exists() && second.part.exists() && !path.exists());
+        // Neither blocks the `.part` file claiming the name for the other policies
+        assert!(!part_path(&path).exists());
+        
+        // Each write is complete, the later one replaces the earlier one
+        std::fs::write(&first.part, b"first").unwrap();
+        std::fs::write(&second.part, b"second").unwrap();
+        std::fs::rename(&first.part, &path).unwrap();
+        std::fs::rename(&second.part, &path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    fn root(paths: &[&str]) -> Option<PathBuf> {
+        common_root(paths.iter().map(|path| { Path::new(path) }))
+    }
+    
+    #[test]
+    fn common_root_is_the_deepest_shared_directory() {
+        assert_eq!(root(&["/photos/2024/IMG_0001.HEIC"]), Some(PathBuf::from("/photos/2024")));
+        assert_eq!(root(&["/photos/2024/a/IMG_0001.HEIC", "/photos/2024/b/c/IMG_0002.HEIC", "/photos/2024/IMG_0003.HEIC"]), Some(PathBuf::from("/photos/2024")));
+        // Directories are compared as a whole, not by their names' prefixes
+        assert_eq!(root(&["/photos/ab/IMG_0001.HEIC", "/photos/abc/IMG_0002.HEIC"]), Some(PathBuf::from("/photos")));
+        assert_eq!(root(&[]), None);
+    }
+    
+    #[test]
+    fn disjoint_roots_share_no_directory() {
+        // Only the file system root is shared, other platforms do not even have that across drives
+        #[cfg(unix)]
+        assert_eq!(root(&["/home/user/IMG_0001.HEIC", "/media/card/IMG_0002.HEIC"]), Some(PathBuf::from("/")));
+        #[cfg(windows)]
+        assert_eq!(root(&["C:\\Users\\IMG_0001.HEIC", "D:\\DCIM\\IMG_0002.HEIC"]), None);
+        // Relative paths have no root to mirror from
+        assert_eq!(root(&["photos/IMG_0001.HEIC", "photos/IMG_0002.HEIC"]), None);
+    }
+    
+    #[test]
+    fn output_dirs_mirror_the_source_layout() {
+        let base = Path::new("/photos");
+        let output_dir = Path::new("/export");
+        assert_eq!(mirrored_output_dir(Path::new("/photos/IMG_0001.HEIC"), base, output_dir), PathBuf::from("/export"));
+        assert_eq!(mirrored_output_dir(Path::new("/photos/2024/05/IMG_0001.HEIC"), base, output_dir), PathBuf::from("/export/2024/05"));
+        // Sources outside of the base fall back to a flat layout
+        assert_eq!(mirrored_output_dir(Path::new("/other/2024/IMG_0001.HEIC"), base, output_dir), PathBuf::from("/export"));
+        assert_eq!(mirrored_output_dir(Path::new("/photos2/IMG_0001.HEIC"), base, output_dir), PathBuf::from("/export"));
+        assert_eq!(mirrored_output_dir(Path::new("IMG_0001.HEIC"), base, output_dir), PathBuf::from("/export"));
+    }
+    
+    #[test]
+    fn same_file_is_detected_through_other_spellings() {
+        let dir = scratch_dir("same-file");
+        let source = dir.join("a.heic");
+        std::fs::write(&source, b"source").unwrap();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        
+        assert!(is_same_file(&source, &source));
+        assert!(is_same_file(&source, &dir.join("sub").join("..").join("a.heic")));
+        assert!(is_same_file(&source, &dir.join(".").join("a.heic")));
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&source, dir.join("link.heic")).unwrap();
+            assert!(is_same_file(&source, &dir.join("link.heic")));
+        }
+        
+        std::fs::write(dir.join("b.heic"), b"source").unwrap();
+        assert!(!is_same_file(&source, &dir.join("b.heic")));
+        assert!(!is_same_file(&source, &dir.join("missing.heic")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,480 @@
+use std::ffi::OsStr;
+use strum::{EnumDiscriminants, EnumIter, EnumMessage};
+
+#[derive(Clone, Copy, Default, EnumIter, EnumMessage)]
+pub enum TIFFCompression {
+    #[strum(message = "Keine Kompression")]
+    #[default]
+    None,
+    #[strum(message = "Lempel-Ziv-Welch (LZW)")]
+    LZW,
+    #[strum(message = "Deflate (LZ77 / Huffman)")]
+    Deflate,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, EnumIter, EnumMessage)]
+pub enum BitDepth {
+    #[strum(message = "8 Bit pro Kanal")]
+    #[default]
+    Eight,
+    #[strum(message = "16 Bit pro Kanal")]
+    Sixteen,
+}
+
+/// Colour type of PNG and TIFF files
+#[derive(Clone, Copy, Default, PartialEq, EnumIter, EnumMessage)]
+pub enum ColorMode {
+    /// Smallest type which keeps every pixel: alpha only for transparent images, grayscale for
+    /// monochrome images and a palette for PNGs with at most 256 colours
+    #[strum(message = "Farbtyp automatisch")]
+    #[default]
+    Auto,
+    /// Drops the alpha channel of transparent images
+    #[strum(message = "RGB")]
+    Rgb,
+    #[strum(message = "RGB mit Alpha")]
+    Rgba,
+    /// Keeps the alpha channel of transparent images
+    #[strum(message = "Graustufen")]
+    Grayscale,
+    /// PNG with 8 bits per channel only, images with more than 256 colours fail
+    #[strum(message = "Palette (bis 256 Farben)")]
+    Palette,
+}
+
+#[derive(Clone, Copy, Default, EnumIter, EnumMessage)]
+pub enum ChromaSubsampling {
+    #[strum(message = "4:2:0 (kleinste Datei)")]
+    #[default]
+    Yuv420,
+    #[strum(message = "4:2:2")]
+    Yuv422,
+    #[strum(message = "4:4:4 (volle Farbauflösung)")]
+    Yuv444,
+}
+
+/// What happens to transparent pixels in formats which cannot store them
+#[derive(Clone, Copy, PartialEq, EnumDiscriminants)]
+#[strum_discriminants(name(AlphaPolicyKind), derive(EnumIter, EnumMessage))]
+pub enum AlphaPolicy {
+    /// Blend onto an opaque background colour (red, green, blue)
+    #[strum_discriminants(strum(message = "Transparenz: Auf Hintergrundfarbe legen"))]
+    Composite([u8; 3]),
+    /// Multiply the colour by the alpha, which equals blending onto black
+    #[strum_discriminants(strum(message = "Transparenz: Vormultiplizieren"))]
+    Premultiply,
+    /// Fail the image instead of losing its transparency
+    #[strum_discriminants(strum(message = "Transparenz: Warnen, nicht umwandeln"))]
+    Warn,
+}
+
+impl Default for AlphaPolicy {
+    fn default() -> Self {
+        Self::Composite([255, 255, 255])
+    }
+}
+
+impl AlphaPolicy {
+    pub fn default_for(kind: AlphaPolicyKind) -> Self {
+        match kind {
+            AlphaPolicyKind::Composite => Self::default(),
+            AlphaPolicyKind::Premultiply => Self::Premultiply,
+            AlphaPolicyKind::Warn => Self::Warn,
+        }
+    }
+    
+    /// Parses a background colour written as hex triplet, e.g. `#FFFFFF` or `ffffff`
+    pub fn parse_background(value: &str) -> Option<[u8; 3]> {
+        let hex = value.trim().trim_start_matches('#');
+        if hex.len() != 6 || !hex.is_ascii() { return None; }
+        let channel = |idx: usize| { u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).ok() };
+        Some([channel(0)?, channel(1)?, channel(2)?])
+    }
+}
+
+/// Plain-data description of an output format and its encoder settings. Formats with an
+/// alpha channel only store it for images which are actually transparent.
+#[derive(Clone, Copy, EnumDiscriminants)]
+#[strum_discriminants(name(OutputFormatKind), derive(EnumIter))]
+pub enum OutputFormat {
+    /// JPEG has no alpha channel, transparent images are handled according to the policy
+    JPEG(u8, AlphaPolicy),
+    PNG(u8, BitDepth, ColorMode),
+    TIFF(TIFFCompression, BitDepth, ColorMode),
+    WebP(u8),
+    AVIF(u8, u8, ChromaSubsampling),
+    JXL(u8, u8),
+    /// Quality and whether to encode losslessly instead, which ignores the quality
+    HEIF(u8, bool),
+}
+
+impl OutputFormat {
+    /// The settings a format starts out with, both in the UI and on the command line
+    pub fn default_for(kind: OutputFormatKind) -> Self {
+        match kind {
+            OutputFormatKind::JPEG => Self::JPEG(90, AlphaPolicy::default()),
+            OutputFormatKind::PNG => Self::PNG(75, BitDepth::Eight, ColorMode::Auto),
+            OutputFormatKind::TIFF => Self::TIFF(TIFFCompression::None, BitDepth::Eight, ColorMode::Auto),
+            OutputFormatKind::WebP => Self::WebP(80),
+            OutputFormatKind::AVIF => Self::AVIF(70, 6, ChromaSubsampling::Yuv420),
+            OutputFormatKind::JXL => Self::JXL(90, 7),
+            OutputFormatKind::HEIF => Self::HEIF(60, false),
+        }
+    }
+    
+    pub fn file_extension(&self) -> &'static OsStr {
+        match self {
+            Self::JPEG(_, _) => OsStr::new("jpg"),
+            Self::PNG(_, _, _) => OsStr::new("png"),
+            Self::TIFF(_, _, _) => OsStr::new("tiff"),
+            Self::WebP(_) => OsStr::new("webp"),
+            Self::AVIF(_, _, _) => OsStr::new("avif"),
+            Self::JXL(_, _) => OsStr::new("jxl"),
+            Self::HEIF(_, _) => OsStr::new("heic"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, EnumIter, EnumMessage)]
+pub enum Rotation {
+    #[strum(message = "Nicht drehen")]
+    #[default]
+    None,
+    #[strum(message = "90° im Uhrzeigersinn")]
+    Clockwise90,
+    #[strum(message = "180°")]
+    Rotate180,
+    #[strum(message = "90° gegen den Uhrzeigersinn")]
+    Clockwise270,
+}
+
+/// Aspect ratio the image is cropped to. The ratio follows the orientation of the image,
+/// so 4:3 crops portrait images to 3:4.
+#[derive(Clone, Copy, Debug, Default, PartialEq, EnumIter, EnumMessage)]
+pub enum CropAspect {
+    #[strum(message = "Nicht zuschneiden")]
+    #[default]
+    None,
+    #[strum(message = "1:1 (quadratisch)")]
+    Square,
+    #[strum(message = "4:3")]
+    FourThree,
+    #[strum(message = "3:2")]
+    ThreeTwo,
+    #[strum(message = "16:9")]
+    SixteenNine,
+    #[strum(message = "5:4")]
+    FiveFour,
+}
+
+impl CropAspect {
+    /// The longer and the shorter side of the ratio
+    pub fn ratio(&self) -> Option<(u32, u32)> {
+        match self {
+            Self::None => None,
+            Self::Square => Some((1, 1)),
+            Self::FourThree => Some((4, 3)),
+            Self::ThreeTwo => Some((3, 2)),
+            Self::SixteenNine => Some((16, 9)),
+            Self::FiveFour => Some((5, 4)),
+        }
+    }
+}
+
+/// Which part of the image is kept when cropping. A crop only ever shortens one side,
+/// so the anchor picks between the start and the end of that side.
+#[derive(Clone, Copy, Debug, Default, PartialEq, EnumIter, EnumMessage)]
+pub enum CropAnchor {
+    #[strum(message = "Mitte behalten")]
+    #[default]
+    Center,
+    #[strum(message = "Oben bzw. links behalten")]
+    Start,
+    #[strum(message = "Unten bzw. rechts behalten")]
+    End,
+}
+
+/// Geometric changes applied after decoding and before resizing, in this order: rotate, flip, crop
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Transform {
+    pub rotation: Rotation,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    pub crop: CropAspect,
+    pub crop_anchor: CropAnchor,
+}
+
+impl Transform {
+    pub fn is_identity(&self) -> bool {
+        self.rotation == Rotation::None && !self.flip_horizontal && !self.flip_vertical && self.crop == CropAspect::None
+    }
+    
+    /// Size of a `width` × `height` image after rotating and cropping
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width, height) = match self.rotation {
+            Rotation::Clockwise90 | Rotation::Clockwise270 => (height, width),
+            Rotation::None | Rotation::Rotate180 => (width, height),
+        };
+        let (_, _, crop_width, crop_height) = self.crop_rect(width, height);
+        (crop_width, crop_height)
+    }
+    
+    /// Position and size of the crop within an upright `width` × `height` image
+    pub(crate) fn crop_rect(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let Some((long_side, short_side)) = self.crop.ratio() else { return (0, 0, width, height); };
+        let (ratio_width, ratio_height) = if height > width { (short_side as u64, long_side as u64) } else { (long_side as u64, short_side as u64) };
+        // Keep the full height if the image is too wide for the ratio, otherwise the full width
+        let (crop_width, crop_height) = if width as u64 * ratio_height > height as u64 * ratio_width {
+            (((height as u64 * ratio_width / ratio_height) as u32).max(1), height)
+        } else {
+            (width, ((width as u64 * ratio_height / ratio_width) as u32).max(1))
+        };
+        let offset = |excess: u32| {
+            match self.crop_anchor {
+                CropAnchor::Center => excess / 2,
+                CropAnchor::Start => 0,
+                CropAnchor::End => excess,
+            }
+        };
+        (offset(width - crop_width), offset(height - crop_height), crop_width, crop_height)
+    }
+}
+
+/// How the image is scaled down before encoding. Images are never enlarged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, EnumDiscriminants)]
+#[strum_discriminants(name(ResizeKind), derive(EnumIter, EnumMessage))]
+pub enum Resize {
+    #[default]
+    #[strum_discriminants(strum(message = "Originalgröße"))]
+    Original,
+    /// The longer side of the image in pixels
+    #[strum_discriminants(strum(message = "Längste Kante"))]
+    LongestEdge(u32),
+    /// Width and height of a box the image is fitted into, keeping its aspect ratio
+    #[strum_discriminants(strum(message = "In Rahmen einpassen"))]
+    FitInBox(u32, u32),
+    #[strum_discriminants(strum(message = "Prozent"))]
+    Percentage(u8),
+    /// Upper bound for width × height in millions of pixels
+    #[strum_discriminants(strum(message = "Höchstens Megapixel"))]
+    Megapixels(f32),
+}
+
+impl Resize {
+    /// The values a resize mode starts out with in the UI
+    pub fn default_for(kind: ResizeKind) -> Self {
+        match kind {
+            ResizeKind::Original => Self::Original,
+            ResizeKind::LongestEdge => Self::LongestEdge(2048),
+            ResizeKind::FitInBox => Self::FitInBox(1920, 1080),
+            ResizeKind::Percentage => Self::Percentage(50),
+            ResizeKind::Megapixels => Self::Megapixels(12.),
+        }
+    }
+    
+    /// Size of a `width` × `height` image after resizing. Zero or invalid values keep the size.
+    pub fn target_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width_f, height_f) = (width as f64, height as f64);
+        let scale = match *self {
+            Self::Original => 1.,
+            Self::LongestEdge(edge) => edge as f64 / width_f.max(height_f),
+            Self::FitInBox(box_width, box_height) => (box_width as f64 / width_f).min(box_height as f64 / height_f),
+            Self::Percentage(percentage) => percentage as f64 / 100.,
+            Self::Megapixels(megapixels) => (megapixels as f64 * 1_000_000. / (width_f * height_f)).sqrt(),
+        };
+        if scale.is_nan() || scale <= 0. || scale >= 1. { return (width, height); }
+        (((width_f * scale).round() as u32).max(1), ((height_f * scale).round() as u32).max(1))
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, EnumIter, EnumMessage)]
+pub enum ResizeFilter {
+    #[strum(message = "Lanczos (am schärfsten)")]
+    #[default]
+    Lanczos,
+    #[strum(message = "Bikubisch (Catmull-Rom)")]
+    Bicubic,
+    #[strum(message = "Bilinear")]
+    Bilinear,
+    #[strum(message = "Nächster Nachbar (pixelig)")]
+    Nearest,
+}
+
+impl ResizeFilter {
+    pub(crate) fn filter_type(&self) -> image::imageops::FilterType {
+        match self {
+            Self::Lanczos => image::imageops::FilterType::Lanczos3,
+            Self::Bicubic => image::imageops::FilterType::CatmullRom,
+            Self::Bilinear => image::imageops::FilterType::Triangle,
+            Self::Nearest => image::imageops::FilterType::Nearest,
+        }
+    }
+}
+
+/// What happens when the output file already exists
+#[derive(Clone, Copy, Default, PartialEq, EnumIter, EnumMessage)]
+pub enum CollisionPolicy {
+    #[strum(message = "Nummer anhängen")]
+    #[default]
+    AutoSuffix,
+    #[strum(message = "Überspringen")]
+    Skip,
+    #[strum(message = "Überschreiben")]
+    Overwrite,
+    #[strum(message = "Nachfragen")]
+    Ask,
+}
+
+/// File format depth maps and auxiliary images are written in, always as 16-bit grayscale
+#[derive(Clone, Copy, Default, PartialEq, EnumIter, EnumMessage)]
+pub enum AuxiliaryFormat {
+    #[strum(message = "PNG (16 Bit Graustufen)")]
+    #[default]
+    PNG,
+    #[strum(message = "TIFF (16 Bit Graustufen)")]
+    TIFF,
+}
+
+impl AuxiliaryFormat {
+    pub fn file_extension(&self) -> &'static OsStr {
+        match self {
+            Self::PNG => OsStr::new("png"),
+            Self::TIFF => OsStr::new("tiff"),
+        }
+    }
+}
+
+/// What happens to the video of a Live Photo when its still is converted
+#[derive(Clone, Copy, Default, PartialEq, EnumIter, EnumMessage)]
+pub enum LivePhotoVideoAction {
+    #[strum(message = "Live Photo: Video kopieren")]
+    #[default]
+    Copy,
+    #[strum(message = "Live Photo: Video verschieben")]
+    Move,
+    #[strum(message = "Live Photo: Video ignorieren")]
+    Ignore,
+}
+
+/// Template the output file name is built from, the extension is appended automatically
+pub const DEFAULT_NAME_TEMPLATE: &str = "{name}";
+
+/// Everything needed to convert a single file
+#[derive(Clone)]
+pub struct ConversionOptions {
+    pub format: OutputFormat,
+    pub keep_metadata: bool,
+    pub convert_to_srgb: bool,
+    /// Output file name with the tokens `{name}`, `{index}`, `{date}`, `{camera}`, `{format}`, `{width}` and `{height}`
+    pub name_template: String,
+    pub collision_policy: CollisionPolicy,
+    /// Export every top-level image of a container (bursts, brackets, collections) instead of only the primary one
+    pub export_all_images: bool,
+    /// Write depth maps next to the main output, e.g. `IMG_0001_depth.png`
+    pub export_depth: bool,
+    /// Write auxiliary images such as portrait mattes and HDR gain maps next to the main output
+    pub export_auxiliary: bool,
+    pub auxiliary_format: AuxiliaryFormat,
+    /// Copies or moves the video of a Live Photo next to the converted still, named after it. Their
+    /// content identifier is added to the XMP of the still, as long as metadata is kept.
+    pub live_photo_video: LivePhotoVideoAction,
+    pub transform: Transform,
+    /// Applied after the transform, `{width}` and `{height}` in the name template refer to the resized image
+    pub resize: Resize,
+    pub resize_filter: ResizeFilter,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn crop(crop: CropAspect, crop_anchor: CropAnchor) -> Transform {
+        Transform { crop, crop_anchor, ..Transform::default() }
+    }
+    
+    #[test]
+    fn crop_rect_keeps_the_image_without_a_ratio() {
+        for anchor in [CropAnchor::Center, CropAnchor::Start, CropAnchor::End] {
+            assert_eq!(crop(CropAspect::None, anchor).crop_rect(4032, 3024), (0, 0, 4032, 3024));
+        }
+    }
+    
+    #[test]
+    fn crop_rect_follows_the_orientation_of_the_image() {
+        let transform = crop(CropAspect::SixteenNine, CropAnchor::Center);
+        assert_eq!(transform.crop_rect(4032, 3024), (0, 378, 4032, 2268));
+        assert_eq!(transform.crop_rect(3024, 4032), (378, 0, 2268, 4032));
+        // Images which already have the ratio stay untouched
+        assert_eq!(transform.crop_rect(1920, 1080), (0, 0, 1920, 1080));
+        assert_eq!(crop(CropAspect::FourThree, CropAnchor::Center).crop_rect(4032, 3024), (0, 0, 4032, 3024));
+    }
+    
+    #[test]
+    fn crop_rect_rounds_odd_sizes_down() {
+        // 7 × 5 needs 6.67 × 5 for 4:3, 5 × 7 needs 5 × 6.67 for 3:4
+        let transform = crop(CropAspect::FourThree, CropAnchor::End);
+        assert_eq!(transform.crop_rect(7, 5), (1, 0, 6, 5));
+        assert_eq!(transform.crop_rect(5, 7), (0, 1, 5, 6));
+        // An odd excess cannot be split evenly, the centre leans towards the start
+        assert_eq!(crop(CropAspect::Square, CropAnchor::Center).crop_rect(6, 3), (1, 0, 3, 3));
+        // Degenerate images never end up empty
+        assert_eq!(crop(CropAspect::SixteenNine, CropAnchor::Center).crop_rect(100, 1), (49, 0, 1, 1));
+        assert_eq!(crop(CropAspect::Square, CropAnchor::Center).crop_rect(1, 1), (0, 0, 1, 1));
+    }
+    
+    #[test]
+    fn crop_rect_places_every_anchor() {
+        let expected = [(CropAnchor::Center, 1000), (CropAnchor::Start, 0), (CropAnchor::End, 2000)];
+        for (anchor, offset) in expected {
+            let transform = crop(CropAspect::Square, anchor);
+            assert_eq!(transform.crop_rect(5000, 3000), (offset, 0, 3000, 3000));
+            assert_eq!(transform.crop_rect(3000, 5000), (0, offset, 3000, 3000));
+        }
+    }
+    
+    #[test]
+    fn target_size_ignores_invalid_values() {
+        for resize in [Resize::LongestEdge(0), Resize::FitInBox(0, 1080), Resize::FitInBox(1920, 0), Resize::Percentage(0), Resize::Megapixels(0.), Resize::Megapixels(-1.), Resize::Megapixels(f32::NAN)] {
+            assert_eq!(resize.target_size(4032, 3024), (4032, 3024));
+        }
+        assert_eq!(Resize::LongestEdge(100).target_size(0, 0), (0, 0));
+        assert_eq!(Resize::Megapixels(1.).target_size(0, 0), (0, 0));
+    }
+    
+    #[test]
+    fn rotation_is_applied_before_the_crop() {
+        let mut transform = crop(CropAspect::SixteenNine, CropAnchor::Center);
+        assert_eq!(transform.output_size(4032, 3024), (4032, 2268));
+        for rotation in [Rotation::Clockwise90, Rotation::Clockwise270] {
+            transform.rotation = rotation;
+            assert_eq!(transform.output_size(4032, 3024), (2268, 4032));
+        }
+        transform.rotation = Rotation::Rotate180;
+        assert_eq!(transform.output_size(4032, 3024), (4032, 2268));
+        // Flipping changes no size
+        let flipped = Transform { rotation: Rotation::Clockwise90, flip_horizontal: true, flip_vertical: true, ..Transform::default() };
+        assert_eq!(flipped.output_size(4032, 3024), (3024, 4032));
+    }
+    
+    #[test]
+    fn target_size_rounds_to_the_nearest_pixel() {
+        assert_eq!(Resize::Percentage(50).target_size(4032, 3024), (2016, 1512));
+        assert_eq!(Resize::LongestEdge(1000).target_size(4032, 3024), (1000, 750));
+        assert_eq!(Resize::LongestEdge(1000).target_size(3024, 4032), (750, 1000));
+        assert_eq!(Resize::FitInBox(1920, 1080).target_size(4032, 3024), (1440, 1080));
+        // 12.19 megapixels scale to 3999.99 × 2999.99
+        assert_eq!(Resize::Megapixels(12.).target_size(4032, 3024), (4000, 3000));
+        assert_eq!(Resize::Percentage(50).target_size(3, 3), (2, 2));
+        // A side never shrinks to nothing
+        assert_eq!(Resize::Percentage(10).target_size(1000, 1), (100, 1));
+    }
+    
+    #[test]
+    fn target_size_never_upscales() {
+        for resize in [Resize::Original, Resize::LongestEdge(8000), Resize::LongestEdge(4032), Resize::FitInBox(8000, 8000), Resize::Percentage(100), Resize::Megapixels(50.)] {
+            assert_eq!(resize.target_size(4032, 3024), (4032, 3024));
+        }
+        // Fitting into the box only needs one side to shrink
+        assert_eq!(Resize::FitInBox(8000, 1512).target_size(4032, 3024), (2016, 1512));
+    }
+}
@@ -0,0 +1,66 @@
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use strum::IntoEnumIterator;
+use unheic_core::{AuxiliaryFormat, CollisionPolicy, ConversionOptions, DEFAULT_NAME_TEMPLATE, LivePhotoVideoAction, OutputFormat, OutputFormatKind, Resize, ResizeFilter, Transform};
+
+/// Path of a test image in the `res` folder of the workspace
+pub fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").join("res").join(name)
+}
+
+/// Every output format with its default encoder settings
+pub fn all_formats() -> Vec<OutputFormat> {
+    OutputFormatKind::iter().map(OutputFormat::default_for).collect()
+}
+
+/// Options which only write the image itself, without any of the optional extras
+pub fn options(format: OutputFormat, keep_metadata: bool) -> ConversionOptions {
+    ConversionOptions {
+        format,
+        keep_metadata,
+        convert_to_srgb: false,
+        name_template: DEFAULT_NAME_TEMPLATE.to_string(),
+        collision_policy: CollisionPolicy::Overwrite,
+        export_all_images: false,
+        export_depth: false,
+        export_auxiliary: false,
+        auxiliary_format: AuxiliaryFormat::PNG,
+        live_photo_video: LivePhotoVideoAction::Ignore,
+        transform: Transform::default(),
+        resize: Resize::Original,
+        resize_filter: ResizeFilter::default(),
+    }
+}
+
+/// Offsets of every TIFF header in an encoded file, one of them starts the EXIF block
+fn tiff_headers(data: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    data.windows(4).enumerate().filter(|(_, window)| { *window == b"MM\0*" || *window == b"II*\0" }).map(|(offset, _)| { offset })
+}
+
+/// The EXIF block of an encoded file, found by its TIFF header so every container is covered
+pub fn find_exif(data: &[u8]) -> Option<exif::Exif> {
+    tiff_headers(data).find_map(|offset| { exif::Reader::new().read_raw(data[offset..].to_vec()).ok() })
+}
+
+/// The Orientation value of IFD0 in the EXIF block of an encoded file
+pub fn exif_orientation(data: &[u8]) -> Option<u32> {
+    find_exif(data)?.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?.value.get_uint(0)
+}
+
+/// The tiff:Orientation value of the XMP packet of an encoded file, attribute or element form
+pub fn xmp_orientation(data: &[u8]) -> Option<u32> {
+    const PROPERTY: &[u8] = b"tiff:Orientation";
+    let start = data.windows(PROPERTY.len()).position(|window| { window == PROPERTY })? + PROPERTY.len();
+    let value = data[start..].iter().skip_while(|byte| { matches!(byte, b'=' | b'"' | b'\'' | b'>' | b' ') }).take_while(|byte| { byte.is_ascii_digit() }).map(|byte| { *byte as char }).collect::<String>();
+    value.parse().ok()
+}
+
+/// An empty directory of its own, as tests run in parallel
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("unheic-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
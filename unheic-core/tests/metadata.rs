@@ -0,0 +1,133 @@
+//! Encodes `res/test.heic` with and without metadata and checks the native EXIF container of
+//! every format: JPEG APP1, PNG eXIf, the TIFF EXIF IFD and the WebP EXIF chunk.
+
+mod common;
+
+use std::io::Cursor;
+
+use common::{find_exif, fixture, options};
+use image::DynamicImage;
+use unheic_core::{AlphaPolicy, BitDepth, ColorMode, ImageMetadata, OutputFormat, TIFFCompression, convert_to_format, decode_image};
+
+/// The formats which carry EXIF in a container of their own
+fn formats() -> [OutputFormat; 4] {
+    [
+        OutputFormat::JPEG(90, AlphaPolicy::default()),
+        OutputFormat::PNG(50, BitDepth::Eight, ColorMode::Auto),
+        OutputFormat::TIFF(TIFFCompression::Deflate, BitDepth::Eight, ColorMode::Auto),
+        OutputFormat::WebP(90),
+    ]
+}
+
+/// The source with a camera model and capture date added to its EXIF. The fixture only has
+/// an IFD0, phones always write an EXIF IFD as well.
+fn source() -> (DynamicImage, ImageMetadata) {
+    let (image, mut metadata) = decode_image(&fixture("test.heic")).unwrap();
+    let exif = exif::Reader::new().read_raw(metadata.exif.clone().expect("test.heic has EXIF")).unwrap();
+    let camera_fields = [
+        exif::Field { tag: exif::Tag::Make, ifd_num: exif::In::PRIMARY, value: exif::Value::Ascii(vec![b"Apple".to_vec()]) },
+        exif::Field { tag: exif::Tag::DateTimeOriginal, ifd_num: exif::In::PRIMARY, value: exif::Value::Ascii(vec![b"2024:05:01 12:34:56".to_vec()]) },
+    ];
+    let mut writer = exif::experimental::Writer::new();
+    for field in exif.fields().chain(&camera_fields) {
+        writer.push_field(field);
+    }
+    let mut data = Cursor::new(Vec::new());
+    writer.write(&mut data, false).unwrap();
+    metadata.exif = Some(data.into_inner());
+    (image.crop_imm(0, 0, 64, 48), metadata)
+}
+
+/// Payloads of the JPEG segments before the image data, with their marker
+fn jpeg_segments(data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = Vec::new();
+    let mut offset = 2;
+    while offset + 4 <= data.len() && data[offset] == 0xFF && data[offset + 1] != 0xDA {
+        let length = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        segments.push((data[offset + 1], &data[offset + 4..offset + 2 + length]));
+        offset += 2 + length;
+    }
+    segments
+}
+
+/// Types and payloads of the PNG chunks
+fn png_chunks(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut offset = 8;
+    while offset + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        chunks.push((data[offset + 4..offset + 8].try_into().unwrap(), &data[offset + 8..offset + 8 + length]));
+        offset += 12 + length;
+    }
+    chunks
+}
+
+/// FourCCs of the chunks of a WebP RIFF container
+fn webp_chunks(data: &[u8]) -> Vec<[u8; 4]> {
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let size = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        chunks.push(data[offset..offset + 4].try_into().unwrap());
+        offset += 8 + size + (size & 1);
+    }
+    chunks
+}
+
+/// Whether the first image directory of a TIFF file has `tag`
+fn tiff_has_tag(data: &[u8], tag: tiff::tags::Tag) -> bool {
+    let mut decoder = tiff::decoder::Decoder::new(Cursor::new(data)).unwrap();
+    decoder.find_tag(tag).unwrap().is_some()
+}
+
+/// Whether the output stores EXIF in the container the format defines for it
+fn has_exif_container(format: &OutputFormat, data: &[u8]) -> bool {
+    match format {
+        OutputFormat::JPEG(_, _) => jpeg_segments(data).iter().any(|(marker, payload)| { *marker == 0xE1 && payload.starts_with(b"Exif\0\0") }),
+        OutputFormat::PNG(_, _, _) => png_chunks(data).iter().any(|(chunk_type, _)| { chunk_type == b"eXIf" }),
+        OutputFormat::TIFF(_, _, _) => tiff_has_tag(data, tiff::tags::Tag::ExifDirectory),
+        OutputFormat::WebP(_) => webp_chunks(data).contains(b"EXIF"),
+        _ => unreachable!("Only formats with an EXIF container are checked"),
+    }
+}
+
+/// Whether the output has any EXIF or XMP, in the native container or elsewhere
+fn has_any_metadata(format: &OutputFormat, data: &[u8]) -> bool {
+    match format {
+        // XMP is stored in APP1 as well
+        OutputFormat::JPEG(_, _) => jpeg_segments(data).iter().any(|(marker, _)| { *marker == 0xE1 }),
+        OutputFormat::PNG(_, _, _) => png_chunks(data).iter().any(|(chunk_type, _)| { matches!(chunk_type, b"eXIf" | b"iTXt" | b"tEXt" | b"zTXt") }),
+        OutputFormat::TIFF(_, _, _) => [tiff::tags::Tag::ExifDirectory, tiff::tags::Tag::GpsDirectory, tiff::tags::Tag::Make, tiff::tags::Tag::Unknown(700)].into_iter().any(|tag| { tiff_has_tag(data, tag) }),
+        OutputFormat::WebP(_) => webp_chunks(data).iter().any(|chunk| { matches!(chunk, b"EXIF" | b"XMP ") }),
+        _ => unreachable!("Only formats with an EXIF container are checked"),
+    }
+}
+
+#[test]
+fn exif_is_embedded_when_kept() {
+    let (image, metadata) = source();
+    for format in formats() {
+        let extension = format.file_extension().to_string_lossy().into_owned();
+        let output = convert_to_format(image.clone(), &metadata, &options(format, true)).unwrap_or_else(|err| { panic!("{extension}: {err}") });
+        let output = (*output).as_ref();
+        
+        assert!(has_exif_container(&format, output), "{extension} has no EXIF container");
+        let exif = find_exif(output).unwrap_or_else(|| { panic!("{extension} has no readable EXIF") });
+        let make = exif.get_field(exif::Tag::Make, exif::In::PRIMARY).map(|field| { field.display_value().to_string() });
+        assert_eq!(make.as_deref(), Some("\"Apple\""), "Make of {extension}");
+        assert!(exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY).is_some(), "{extension} lost the EXIF IFD");
+    }
+}
+
+#[test]
+fn no_metadata_is_embedded_when_dropped() {
+    let (image, metadata) = source();
+    for format in formats() {
+        let extension = format.file_extension().to_string_lossy().into_owned();
+        let output = convert_to_format(image.clone(), &metadata, &options(format, false)).unwrap_or_else(|err| { panic!("{extension}: {err}") });
+        let output = (*output).as_ref();
+        
+        assert!(!has_exif_container(&format, output), "{extension} has an EXIF container");
+        assert!(!has_any_metadata(&format, output), "{extension} has metadata");
+    }
+}
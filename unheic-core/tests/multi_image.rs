@@ -0,0 +1,69 @@
+//! Tests for exporting every top-level image of a container. The burst is encoded on the fly,
+//! two small images of different colours in one HEIF file.
+
+mod common;
+
+use std::path::Path;
+
+use common::{fixture, options, scratch_dir};
+use libheif_rs::{Channel, ColorSpace, CompressionFormat, EncoderQuality, HeifContext, Image, LibHeif, RgbChroma};
+use unheic_core::{CollisionPolicy, ConversionOutput, OutputFormat, OutputFormatKind, convert_file};
+
+/// Writes a HEIF file with one single-coloured top-level image per entry of `colours`
+fn burst(path: &Path, colours: &[[u8; 3]]) {
+    let lib_heif = LibHeif::new();
+    let mut ctx = HeifContext::new().unwrap();
+    let mut encoder = lib_heif.encoder_for_format(CompressionFormat::Hevc).unwrap();
+    encoder.set_quality(EncoderQuality::LossLess).unwrap();
+    for colour in colours {
+        let mut image = Image::new(64, 64, ColorSpace::Rgb(RgbChroma::Rgb)).unwrap();
+        image.create_plane(Channel::Interleaved, 64, 64, 8).unwrap();
+        let plane = image.planes_mut().interleaved.unwrap();
+        for row in plane.data.chunks_mut(plane.stride) {
+            row[..64 * 3].copy_from_slice(&colour.repeat(64));
+        }
+        ctx.encode_image(&image, &mut encoder, None).unwrap();
+    }
+    std::fs::write(path, ctx.write_to_bytes().unwrap()).unwrap();
+}
+
+/// Converts `path` into `output_dir` and returns the names of the written files
+fn convert(path: &Path, output_dir: &Path, export_all_images: bool) -> Vec<String> {
+    let mut options = options(OutputFormat::default_for(OutputFormatKind::PNG), false);
+    options.export_all_images = export_all_images;
+    let outputs = convert_file(path, 1, output_dir, &options, &|_: &Path| -> CollisionPolicy { unreachable!() }).unwrap();
+    outputs.into_iter().map(|output| {
+        match output {
+            ConversionOutput::Written(path) => file_name(&path),
+            ConversionOutput::Skipped(path) => panic!("{} was skipped", path.display()),
+        }
+    }).collect()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+#[test]
+fn every_image_of_a_burst_is_numbered() {
+    let dir = scratch_dir("burst");
+    let source = dir.join("IMG_0001.heic");
+    burst(&source, &[[255, 0, 0], [0, 0, 255]]);
+    
+    assert_eq!(convert(&source, &dir.join("all"), true), ["IMG_0001_1.png", "IMG_0001_2.png"]);
+    // The images are exported in file order, not twice the primary image
+    let first = image::open(dir.join("all").join("IMG_0001_1.png")).unwrap().into_rgb8();
+    let second = image::open(dir.join("all").join("IMG_0001_2.png")).unwrap().into_rgb8();
+    assert!(first.get_pixel(0, 0)[0] > 200 && second.get_pixel(0, 0)[2] > 200);
+    
+    // Without the option only the primary image is written, under the plain name
+    assert_eq!(convert(&source, &dir.join("primary"), false), ["IMG_0001.png"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn single_images_stay_unnumbered() {
+    let dir = scratch_dir("single");
+    assert_eq!(convert(&fixture("test.heic"), &dir, true), ["test.png"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
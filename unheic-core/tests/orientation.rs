@@ -0,0 +1,52 @@
+//! Golden tests for sources with irot/imir transforms. Both fixtures are `res/test.heic`
+//! with a transform property added to the primary image, plus EXIF and XMP orientations
+//! describing the same transform the way cameras write them.
+
+mod common;
+
+use common::{all_formats, exif_orientation, fixture, options, xmp_orientation};
+use image::DynamicImage;
+use unheic_core::{ImageMetadata, convert_to_format, decode_image};
+
+/// Encodes a corner of the image in every format and checks no orientation survives
+fn assert_orientation_reset(image: &DynamicImage, metadata: &ImageMetadata) {
+    let corner = image.crop_imm(0, 0, 64, 48);
+    for format in all_formats() {
+        let extension = format.file_extension().to_string_lossy().into_owned();
+        let output = convert_to_format(corner.clone(), metadata, &options(format, true)).unwrap_or_else(|err| { panic!("{extension}: {err}") });
+        let output = (*output).as_ref();
+        assert_eq!(exif_orientation(output), Some(1), "EXIF orientation of {extension}");
+        assert_eq!(xmp_orientation(output), Some(1), "XMP orientation of {extension}");
+    }
+}
+
+#[test]
+fn rotated_source_is_decoded_upright() {
+    let (reference, _) = decode_image(&fixture("test.heic")).unwrap();
+    let (rotated, metadata) = decode_image(&fixture("test_rotated.heic")).unwrap();
+    
+    // irot 3 turns the stored pixels 270° anti-clockwise, which is 90° clockwise
+    assert_eq!((rotated.width(), rotated.height()), (reference.height(), reference.width()));
+    assert!(rotated == reference.rotate90(), "rotated pixels differ from the reference turned 90° clockwise");
+    assert_orientation_reset(&rotated, &metadata);
+}
+
+#[test]
+fn mirrored_source_is_decoded_upright() {
+    let (reference, _) = decode_image(&fixture("test.heic")).unwrap();
+    let (mirrored, metadata) = decode_image(&fixture("test_mirrored.heic")).unwrap();
+    
+    // imir with axis 1 swaps left and right
+    assert!(mirrored == reference.fliph(), "mirrored pixels differ from the reference flipped horizontally");
+    assert_orientation_reset(&mirrored, &metadata);
+}
+
+#[test]
+fn fixtures_carry_the_orientation_before_decoding() {
+    // Guards the fixtures themselves, a reset is only meaningful if there was something to reset
+    for (name, orientation) in [("test_rotated.heic", 6), ("test_mirrored.heic", 2)] {
+        let data = std::fs::read(fixture(name)).unwrap();
+        assert_eq!(exif_orientation(&data), Some(orientation), "EXIF orientation of {name}");
+        assert_eq!(xmp_orientation(&data), Some(orientation), "XMP orientation of {name}");
+    }
+}